@@ -0,0 +1,13 @@
+#!/bin/sh
+# Replaces the built-in database with the latest upstream files. Rebuild the
+# emulator afterwards to compile them in.
+set -e
+
+cd "$(dirname "$0")"
+
+UPSTREAM=https://raw.githubusercontent.com/chip-8/chip-8-database/master/database
+
+for file in programs.json sha1-hashes.json platforms.json; do
+    curl -fsSL -o "$file.tmp" "$UPSTREAM/$file"
+    mv "$file.tmp" "$file"
+done
@@ -0,0 +1,708 @@
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::fmt::Write;
+
+use crate::cpu::PROGRAM_START;
+use crate::memory::FONTSET_SIZE;
+use crate::opcode::Opcode;
+use crate::platform::Platform;
+use crate::stack::STACK_SIZE;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum EdgeKind {
+    Fallthrough,
+    Jump,
+    Call,
+    Skip,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct Edge {
+    pub target: u16,
+    pub kind: EdgeKind,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct Instruction {
+    pub address: u16,
+    pub raw: u16,
+    pub opcode: Opcode,
+}
+
+#[derive(Clone, Debug)]
+pub struct BasicBlock {
+    pub start: u16,
+    pub instructions: Vec<Instruction>,
+    pub edges: Vec<Edge>,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum RegionKind {
+    Code,
+    Data,
+    Unreachable,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct Region {
+    pub start: u16,
+    pub end: u16,
+    pub kind: RegionKind,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct FontWrite {
+    pub address: u16,
+    pub target: Option<u16>,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+enum IndexValue {
+    Known(u16),
+    Font,
+    Unknown,
+}
+
+impl IndexValue {
+    fn merge(self, other: IndexValue) -> IndexValue {
+        if self == other {
+            self
+        } else {
+            IndexValue::Unknown
+        }
+    }
+}
+
+pub struct ControlFlowGraph {
+    rom: Vec<u8>,
+    platform: Platform,
+    // Where the ROM is loaded.
+    start: u16,
+    // Where execution starts, past any interpreter patch the image carries.
+    entry: u16,
+    instructions: BTreeMap<u16, Instruction>,
+    successors: BTreeMap<u16, Vec<Edge>>,
+    blocks: BTreeMap<u16, BasicBlock>,
+    subroutines: BTreeMap<u16, BTreeSet<u16>>,
+    sprite_references: BTreeSet<u16>,
+    indirect_jumps: BTreeSet<u16>,
+    invalid_instructions: BTreeSet<u16>,
+    external_targets: BTreeSet<u16>,
+    font_writes: BTreeMap<u16, FontWrite>,
+}
+
+fn fetch(rom: &[u8], start: u16, address: u16) -> Option<u16> {
+    let offset = address.checked_sub(start)? as usize;
+
+    match (rom.get(offset), rom.get(offset + 1)) {
+        (Some(&byte1), Some(&byte2)) => Some((byte1 as u16) << 8 | (byte2 as u16)),
+        _ => None,
+    }
+}
+
+fn font_write(address: u16, index: IndexValue) -> Option<FontWrite> {
+    match index {
+        IndexValue::Known(target) if (target as usize) < FONTSET_SIZE => Some(FontWrite {
+            address,
+            target: Some(target),
+        }),
+        IndexValue::Font => Some(FontWrite {
+            address,
+            target: None,
+        }),
+        _ => None,
+    }
+}
+
+impl ControlFlowGraph {
+    pub fn build(rom: &[u8]) -> ControlFlowGraph {
+        ControlFlowGraph::build_on(rom, Platform::Chip8, PROGRAM_START)
+    }
+
+    // For `platform`'s ROMs loaded at `start`, which need not be the
+    // platform's usual load address.
+    pub fn build_on(rom: &[u8], platform: Platform, start: u16) -> ControlFlowGraph {
+        let mut cfg = ControlFlowGraph {
+            rom: rom.to_vec(),
+            platform,
+            start,
+            entry: start + (platform.program_start() - platform.load_address()),
+            instructions: BTreeMap::new(),
+            successors: BTreeMap::new(),
+            blocks: BTreeMap::new(),
+            subroutines: BTreeMap::new(),
+            sprite_references: BTreeSet::new(),
+            indirect_jumps: BTreeSet::new(),
+            invalid_instructions: BTreeSet::new(),
+            external_targets: BTreeSet::new(),
+            font_writes: BTreeMap::new(),
+        };
+
+        cfg.trace_instructions();
+        cfg.build_blocks();
+        cfg.build_subroutines();
+
+        cfg
+    }
+
+    fn rom_contains(&self, address: u16) -> bool {
+        address >= self.start && ((address - self.start) as usize) < self.rom.len()
+    }
+
+    fn trace_instructions(&mut self) {
+        let mut states: BTreeMap<u16, IndexValue> = BTreeMap::new();
+        let mut worklist = VecDeque::from([(self.entry, IndexValue::Unknown)]);
+
+        while let Some((address, index)) = worklist.pop_front() {
+            let index = match states.get(&address) {
+                Some(&previous) => {
+                    let merged = previous.merge(index);
+                    if merged == previous {
+                        continue;
+                    }
+                    merged
+                }
+                None => index,
+            };
+            states.insert(address, index);
+
+            let raw = match fetch(&self.rom, self.start, address) {
+                Some(raw) => raw,
+                None => {
+                    self.external_targets.insert(address);
+                    continue;
+                }
+            };
+
+            let opcode = match Opcode::decode_on(raw, self.platform) {
+                Some(opcode) => opcode,
+                None => {
+                    self.invalid_instructions.insert(address);
+                    continue;
+                }
+            };
+
+            self.instructions.insert(
+                address,
+                Instruction {
+                    address,
+                    raw,
+                    opcode,
+                },
+            );
+
+            let next = address + 2;
+            let fallthrough = Edge {
+                target: next,
+                kind: EdgeKind::Fallthrough,
+            };
+            let mut index_after = index;
+
+            let edges = match opcode {
+                Opcode::JumpToAddress { address: target } => vec![Edge {
+                    target,
+                    kind: EdgeKind::Jump,
+                }],
+                Opcode::CallAddress { address: target } => vec![
+                    Edge {
+                        target,
+                        kind: EdgeKind::Call,
+                    },
+                    fallthrough,
+                ],
+                Opcode::ReturnFromSubroutine => vec![],
+                Opcode::JumpToAddressPlusRegister0 { .. } => {
+                    self.indirect_jumps.insert(address);
+                    vec![]
+                }
+                _ if opcode.is_skip() => vec![
+                    fallthrough,
+                    Edge {
+                        target: next + 2,
+                        kind: EdgeKind::Skip,
+                    },
+                ],
+                Opcode::SetIndexToAddress { address: target } => {
+                    if self.rom_contains(target) {
+                        self.sprite_references.insert(target);
+                    }
+                    index_after = IndexValue::Known(target);
+                    vec![fallthrough]
+                }
+                Opcode::AddRegisterToIndex { .. } => {
+                    index_after = IndexValue::Unknown;
+                    vec![fallthrough]
+                }
+                Opcode::SetIndexToSpriteLocation { .. } => {
+                    index_after = IndexValue::Font;
+                    vec![fallthrough]
+                }
+                Opcode::StoreBCD { .. } | Opcode::StoreRegisters { .. } => {
+                    if let Some(write) = font_write(address, index) {
+                        self.font_writes.insert(address, write);
+                    }
+                    vec![fallthrough]
+                }
+                _ => vec![fallthrough],
+            };
+
+            for edge in &edges {
+                // The callee may leave I anywhere, so the return site starts over.
+                let state = match (opcode, edge.kind) {
+                    (Opcode::CallAddress { .. }, EdgeKind::Fallthrough) => IndexValue::Unknown,
+                    _ => index_after,
+                };
+
+                worklist.push_back((edge.target, state));
+            }
+
+            self.successors.insert(address, edges);
+        }
+    }
+
+    fn build_blocks(&mut self) {
+        let mut leaders = BTreeSet::from([self.entry]);
+
+        for edges in self.successors.values() {
+            for edge in edges {
+                if edge.kind != EdgeKind::Fallthrough {
+                    leaders.insert(edge.target);
+                }
+            }
+        }
+
+        let mut current: Option<BasicBlock> = None;
+
+        for (&address, &instruction) in &self.instructions {
+            let continues = match &current {
+                Some(block) => {
+                    let last = block.instructions[block.instructions.len() - 1];
+                    last.address + 2 == address
+                        && !leaders.contains(&address)
+                        && self.successors[&last.address]
+                            == [Edge {
+                                target: address,
+                                kind: EdgeKind::Fallthrough,
+                            }]
+                }
+                None => false,
+            };
+
+            if !continues {
+                if let Some(block) = current.take() {
+                    self.blocks.insert(block.start, block);
+                }
+                current = Some(BasicBlock {
+                    start: address,
+                    instructions: Vec::new(),
+                    edges: Vec::new(),
+                });
+            }
+
+            if let Some(block) = current.as_mut() {
+                block.instructions.push(instruction);
+                block.edges = self.successors[&address].clone();
+            }
+        }
+
+        if let Some(block) = current {
+            self.blocks.insert(block.start, block);
+        }
+    }
+
+    fn build_subroutines(&mut self) {
+        let mut entries = BTreeSet::from([self.entry]);
+
+        for edges in self.successors.values() {
+            for edge in edges {
+                if edge.kind == EdgeKind::Call && self.instructions.contains_key(&edge.target) {
+                    entries.insert(edge.target);
+                }
+            }
+        }
+
+        for entry in entries {
+            let mut callees = BTreeSet::new();
+            let mut visited = BTreeSet::new();
+            let mut worklist = vec![entry];
+
+            while let Some(address) = worklist.pop() {
+                if !visited.insert(address) {
+                    continue;
+                }
+
+                let Some(edges) = self.successors.get(&address) else {
+                    continue;
+                };
+
+                for edge in edges {
+                    match edge.kind {
+                        EdgeKind::Call => {
+                            callees.insert(edge.target);
+                        }
+                        _ => worklist.push(edge.target),
+                    }
+                }
+            }
+
+            self.subroutines.insert(entry, callees);
+        }
+    }
+
+    pub fn instructions(&self) -> &BTreeMap<u16, Instruction> {
+        &self.instructions
+    }
+
+    pub fn blocks(&self) -> &BTreeMap<u16, BasicBlock> {
+        &self.blocks
+    }
+
+    pub fn subroutines(&self) -> &BTreeMap<u16, BTreeSet<u16>> {
+        &self.subroutines
+    }
+
+    pub fn indirect_jumps(&self) -> &BTreeSet<u16> {
+        &self.indirect_jumps
+    }
+
+    pub fn invalid_instructions(&self) -> &BTreeSet<u16> {
+        &self.invalid_instructions
+    }
+
+    pub fn external_targets(&self) -> &BTreeSet<u16> {
+        &self.external_targets
+    }
+
+    pub fn font_writes(&self) -> Vec<FontWrite> {
+        self.font_writes.values().cloned().collect()
+    }
+
+    pub fn regions(&self) -> Vec<Region> {
+        let mut kinds: Vec<Option<RegionKind>> = vec![None; self.rom.len()];
+
+        for &address in self.instructions.keys() {
+            let offset = (address - self.start) as usize;
+            kinds[offset] = Some(RegionKind::Code);
+            kinds[offset + 1] = Some(RegionKind::Code);
+        }
+
+        for &reference in &self.sprite_references {
+            let mut offset = (reference - self.start) as usize;
+            while offset < kinds.len() && kinds[offset] != Some(RegionKind::Code) {
+                kinds[offset] = Some(RegionKind::Data);
+                offset += 1;
+            }
+        }
+
+        let mut offset = 0;
+        while offset < kinds.len() {
+            if kinds[offset].is_some() {
+                offset += 1;
+                continue;
+            }
+
+            let address = self.start + offset as u16;
+            let decodes = kinds.get(offset + 1) == Some(&None)
+                && fetch(&self.rom, self.start, address)
+                    .and_then(|raw| Opcode::decode_on(raw, self.platform))
+                    .is_some();
+
+            if decodes {
+                kinds[offset] = Some(RegionKind::Unreachable);
+                kinds[offset + 1] = Some(RegionKind::Unreachable);
+                offset += 2;
+            } else {
+                kinds[offset] = Some(RegionKind::Data);
+                if kinds.get(offset + 1) == Some(&None) {
+                    kinds[offset + 1] = Some(RegionKind::Data);
+                }
+                offset += 2;
+            }
+        }
+
+        let mut regions: Vec<Region> = Vec::new();
+
+        for (offset, kind) in kinds.into_iter().enumerate() {
+            let address = self.start + offset as u16;
+            let kind = kind.unwrap_or(RegionKind::Data);
+
+            match regions.last_mut() {
+                Some(region) if region.kind == kind => region.end = address + 1,
+                _ => regions.push(Region {
+                    start: address,
+                    end: address + 1,
+                    kind,
+                }),
+            }
+        }
+
+        regions
+    }
+
+    pub fn unreachable_code(&self) -> Vec<Region> {
+        self.regions()
+            .into_iter()
+            .filter(|region| region.kind == RegionKind::Unreachable)
+            .collect()
+    }
+
+    pub fn max_call_depth(&self) -> Option<usize> {
+        let mut depths = BTreeMap::new();
+        let mut active = BTreeSet::new();
+
+        self.call_depth(self.entry, &mut depths, &mut active)
+    }
+
+    fn call_depth(
+        &self,
+        entry: u16,
+        depths: &mut BTreeMap<u16, usize>,
+        active: &mut BTreeSet<u16>,
+    ) -> Option<usize> {
+        if let Some(&depth) = depths.get(&entry) {
+            return Some(depth);
+        }
+
+        // Recursion means the depth is bounded only by the data, not the code.
+        if !active.insert(entry) {
+            return None;
+        }
+
+        let mut depth = 0;
+
+        if let Some(callees) = self.subroutines.get(&entry) {
+            for &callee in callees {
+                depth = depth.max(1 + self.call_depth(callee, depths, active)?);
+            }
+        }
+
+        active.remove(&entry);
+        depths.insert(entry, depth);
+
+        Some(depth)
+    }
+
+    pub fn exceeds_stack(&self) -> bool {
+        match self.max_call_depth() {
+            Some(depth) => depth > STACK_SIZE,
+            None => true,
+        }
+    }
+
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::new();
+
+        writeln!(dot, "digraph rom {{").unwrap();
+        writeln!(dot, "    node [shape=box, fontname=\"monospace\"];").unwrap();
+
+        for block in self.blocks.values() {
+            let mut label = String::new();
+            for instruction in &block.instructions {
+                write!(
+                    label,
+                    "0x{:03X}: {:04X}  {}\\l",
+                    instruction.address, instruction.raw, instruction.opcode
+                )
+                .unwrap();
+            }
+
+            let style = if self.subroutines.contains_key(&block.start) {
+                ", style=bold"
+            } else {
+                ""
+            };
+
+            writeln!(
+                dot,
+                "    \"0x{:03X}\" [label=\"{}\"{}];",
+                block.start, label, style
+            )
+            .unwrap();
+
+            if let Some(last) = block.instructions.last() {
+                if self.indirect_jumps.contains(&last.address) {
+                    writeln!(
+                        dot,
+                        "    \"0x{:03X}\" -> \"indirect\" [style=dotted];",
+                        block.start
+                    )
+                    .unwrap();
+                }
+            }
+
+            for edge in &block.edges {
+                let attributes = match edge.kind {
+                    EdgeKind::Fallthrough => "",
+                    EdgeKind::Jump => " [label=\"jump\"]",
+                    EdgeKind::Call => " [label=\"call\", style=dashed]",
+                    EdgeKind::Skip => " [label=\"skip\"]",
+                };
+
+                writeln!(
+                    dot,
+                    "    \"0x{:03X}\" -> \"0x{:03X}\"{};",
+                    block.start, edge.target, attributes
+                )
+                .unwrap();
+            }
+        }
+
+        writeln!(dot, "}}").unwrap();
+
+        dot
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rom(words: &[u16]) -> Vec<u8> {
+        words.iter().flat_map(|word| word.to_be_bytes()).collect()
+    }
+
+    fn block_starts(cfg: &ControlFlowGraph) -> Vec<u16> {
+        cfg.blocks().keys().copied().collect()
+    }
+
+    #[test]
+    fn blocks_end_at_skips_and_start_at_targets() {
+        // LD V0, 5; SE V0, 5; JP 208; ADD V0, 1; JP 208.
+        let cfg = ControlFlowGraph::build(&rom(&[0x6005, 0x3005, 0x1208, 0x7001, 0x1208]));
+
+        assert_eq!(block_starts(&cfg), [0x200, 0x204, 0x206, 0x208]);
+        assert_eq!(cfg.blocks()[&0x200].instructions.len(), 2);
+        assert_eq!(
+            cfg.blocks()[&0x200].edges,
+            [
+                Edge {
+                    target: 0x204,
+                    kind: EdgeKind::Fallthrough,
+                },
+                Edge {
+                    target: 0x206,
+                    kind: EdgeKind::Skip,
+                },
+            ]
+        );
+        assert_eq!(
+            cfg.blocks()[&0x206].edges,
+            [Edge {
+                target: 0x208,
+                kind: EdgeKind::Fallthrough,
+            }]
+        );
+    }
+
+    #[test]
+    fn call_depth_follows_nested_calls() {
+        // CALL 204; JP 202; CALL 208; RET; RET.
+        let cfg = ControlFlowGraph::build(&rom(&[0x2204, 0x1202, 0x2208, 0x00EE, 0x00EE]));
+
+        assert_eq!(cfg.subroutines()[&0x200], BTreeSet::from([0x204]));
+        assert_eq!(cfg.subroutines()[&0x204], BTreeSet::from([0x208]));
+        assert_eq!(cfg.subroutines()[&0x208], BTreeSet::new());
+        assert_eq!(cfg.max_call_depth(), Some(2));
+        assert!(!cfg.exceeds_stack());
+    }
+
+    #[test]
+    fn recursion_has_no_call_depth() {
+        // CALL 204; JP 202; SE V0, 0; CALL 204; RET.
+        let cfg = ControlFlowGraph::build(&rom(&[0x2204, 0x1202, 0x3000, 0x2204, 0x00EE]));
+
+        assert_eq!(cfg.max_call_depth(), None);
+        assert!(cfg.exceeds_stack());
+    }
+
+    #[test]
+    fn regions_separate_code_sprites_and_unreachable_code() {
+        // LD I, 20A; DRW V0, V0, 2; JP 204; never reached: JP 204, LD V0, 1;
+        // the sprite.
+        let cfg = ControlFlowGraph::build(&rom(&[0xA20A, 0xD002, 0x1204, 0x1204, 0x6001, 0xF090]));
+
+        assert_eq!(
+            cfg.regions(),
+            [
+                Region {
+                    start: 0x200,
+                    end: 0x206,
+                    kind: RegionKind::Code,
+                },
+                Region {
+                    start: 0x206,
+                    end: 0x20A,
+                    kind: RegionKind::Unreachable,
+                },
+                Region {
+                    start: 0x20A,
+                    end: 0x20C,
+                    kind: RegionKind::Data,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn indirect_jumps_and_jumps_out_of_the_rom_are_recorded() {
+        // SE V0, 0; JP V0, 300; JP 400.
+        let cfg = ControlFlowGraph::build(&rom(&[0x3000, 0xB300, 0x1400]));
+
+        assert_eq!(cfg.indirect_jumps(), &BTreeSet::from([0x202]));
+        assert_eq!(cfg.external_targets(), &BTreeSet::from([0x400]));
+        assert!(cfg.invalid_instructions().is_empty());
+    }
+
+    #[test]
+    fn writes_through_a_font_pointer_are_found() {
+        // LD F, V0; LD B, V0; JP 204.
+        let cfg = ControlFlowGraph::build(&rom(&[0xF029, 0xF033, 0x1204]));
+
+        assert_eq!(
+            cfg.font_writes(),
+            [FontWrite {
+                address: 0x202,
+                target: None,
+            }]
+        );
+    }
+
+    #[test]
+    fn build_on_follows_the_load_address() {
+        // JP 302; JP 302, loaded at 0x300.
+        let cfg = ControlFlowGraph::build_on(&rom(&[0x1302, 0x1302]), Platform::Chip8, 0x300);
+
+        assert_eq!(block_starts(&cfg), [0x300, 0x302]);
+        assert!(cfg.external_targets().is_empty());
+        assert_eq!(cfg.max_call_depth(), Some(0));
+    }
+
+    #[test]
+    fn build_on_starts_past_the_interpreter_patch() {
+        // The hi-res patch up to 0x2BF, then CLS through 0x0230 and a loop.
+        let mut image = vec![0; 0xC0];
+        image.extend(rom(&[0x0230, 0x12C2]));
+        let cfg = ControlFlowGraph::build_on(&image, Platform::HiRes, 0x200);
+
+        assert_eq!(block_starts(&cfg), [0x2C0, 0x2C2]);
+        assert_eq!(cfg.instructions()[&0x2C0].opcode, Opcode::ClearScreen);
+        assert!(cfg.invalid_instructions().is_empty());
+        assert_eq!(
+            cfg.subroutines().keys().copied().collect::<Vec<_>>(),
+            [0x2C0]
+        );
+    }
+
+    #[test]
+    fn chip8x_instructions_decode() {
+        // The colour and second keypad instructions, loaded at 0x300.
+        let cfg =
+            ControlFlowGraph::build_on(&rom(&[0xB120, 0xE1F2, 0x1304]), Platform::Chip8X, 0x300);
+
+        assert_eq!(cfg.instructions().len(), 3);
+        assert!(cfg.invalid_instructions().is_empty());
+        assert!(cfg
+            .regions()
+            .iter()
+            .all(|region| region.kind == RegionKind::Code));
+    }
+}
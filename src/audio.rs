@@ -0,0 +1,68 @@
+use sdl2::audio::{AudioCallback, AudioDevice, AudioSpecDesired};
+
+const SAMPLE_RATE: i32 = 44_100;
+const TONE_FREQUENCY: f32 = 440.0;
+pub const DEFAULT_VOLUME: f32 = 0.25;
+
+struct SquareWave {
+    phase_increment: f32,
+    phase: f32,
+    volume: f32,
+}
+
+impl AudioCallback for SquareWave {
+    type Channel = f32;
+
+    fn callback(&mut self, out: &mut [f32]) {
+        for sample in out.iter_mut() {
+            *sample = if self.phase < 0.5 {
+                self.volume
+            } else {
+                -self.volume
+            };
+            self.phase = (self.phase + self.phase_increment) % 1.0;
+        }
+    }
+}
+
+pub struct Audio {
+    device: AudioDevice<SquareWave>,
+    playing: bool,
+}
+
+impl Audio {
+    pub fn new(sdl_context: &sdl2::Sdl, volume: f32) -> Result<Audio, String> {
+        let audio_subsystem = sdl_context.audio()?;
+
+        let desired_spec = AudioSpecDesired {
+            freq: Some(SAMPLE_RATE),
+            channels: Some(1),
+            samples: None,
+        };
+
+        let device = audio_subsystem.open_playback(None, &desired_spec, |spec| SquareWave {
+            phase_increment: TONE_FREQUENCY / spec.freq as f32,
+            phase: 0.0,
+            volume,
+        })?;
+
+        Ok(Audio {
+            device,
+            playing: false,
+        })
+    }
+
+    pub fn set_playing(&mut self, playing: bool) {
+        if playing == self.playing {
+            return;
+        }
+
+        if playing {
+            self.device.resume();
+        } else {
+            self.device.pause();
+        }
+
+        self.playing = playing;
+    }
+}
@@ -0,0 +1,90 @@
+use chip_8_emulator::analysis::{ControlFlowGraph, RegionKind};
+use chip_8_emulator::platform::Platform;
+use chip_8_emulator::stack::STACK_SIZE;
+
+const USAGE: &str =
+    "Usage: cargo run --bin chip-8-analyze [--platform chip8|hires|chip8x] <filename> [graph.dot]";
+
+pub fn main() {
+    let mut args = std::env::args().skip(1).peekable();
+
+    let platform = match args.next_if(|arg| arg == "--platform") {
+        Some(_) => match args.next().as_deref() {
+            Some("chip8") => Platform::Chip8,
+            Some("hires") => Platform::HiRes,
+            Some("chip8x") => Platform::Chip8X,
+            _ => panic!("{}", USAGE),
+        },
+        None => Platform::Chip8,
+    };
+
+    let filename = args.next().expect(USAGE);
+
+    let rom = std::fs::read(&filename).expect("Failed to read ROM");
+
+    let cfg = ControlFlowGraph::build_on(&rom, platform, platform.load_address());
+
+    println!("{}: {} bytes", filename, rom.len());
+    println!();
+    println!("Regions:");
+    for region in cfg.regions() {
+        let kind = match region.kind {
+            RegionKind::Code => "code",
+            RegionKind::Data => "data",
+            RegionKind::Unreachable => "unreachable code",
+        };
+        println!(
+            "  0x{:03X}-0x{:03X}  {}",
+            region.start,
+            region.end - 1,
+            kind
+        );
+    }
+
+    println!();
+    println!("Subroutines:");
+    for (entry, callees) in cfg.subroutines() {
+        let callees: Vec<String> = callees.iter().map(|c| format!("0x{:03X}", c)).collect();
+        println!("  0x{:03X} calls [{}]", entry, callees.join(", "));
+    }
+
+    println!();
+    match cfg.max_call_depth() {
+        Some(depth) => println!("Maximum call depth: {} (stack size {})", depth, STACK_SIZE),
+        None => println!("Maximum call depth: unbounded (recursion)"),
+    }
+    if cfg.exceeds_stack() {
+        println!("WARNING: call depth may overflow the stack");
+    }
+
+    for write in cfg.font_writes() {
+        match write.target {
+            Some(target) => println!(
+                "WARNING: 0x{:03X} writes to the font area at 0x{:03X}",
+                write.address, target
+            ),
+            None => println!(
+                "WARNING: 0x{:03X} writes to a font character set by LD F",
+                write.address
+            ),
+        }
+    }
+    for address in cfg.indirect_jumps() {
+        println!("NOTE: 0x{:03X} jumps indirectly through V0", address);
+    }
+    for address in cfg.invalid_instructions() {
+        println!("WARNING: 0x{:03X} is reached but does not decode", address);
+    }
+    for address in cfg.external_targets() {
+        println!(
+            "WARNING: 0x{:03X} is reached but lies outside the ROM",
+            address
+        );
+    }
+
+    if let Some(dot_filename) = args.next() {
+        std::fs::write(&dot_filename, cfg.to_dot()).expect("Failed to write graph");
+        println!();
+        println!("Wrote control-flow graph to {}", dot_filename);
+    }
+}
@@ -0,0 +1,637 @@
+// The RCA CDP1802 microprocessor. Timing is counted in machine cycles of
+// eight clock periods: two per instruction, three for long branches and
+// skips, and one for each interrupt or DMA cycle.
+
+// Memory, I/O ports and external flag inputs as seen by the processor.
+pub trait Bus {
+    fn read(&mut self, address: u16) -> u8;
+    fn write(&mut self, address: u16, value: u8);
+    // INP N: returns the byte on the data bus, which is also stored at R(X).
+    fn input(&mut self, port: u8) -> u8;
+    // OUT N: the byte at R(X).
+    fn output(&mut self, port: u8, value: u8);
+    // Whether EF1-EF4 is asserted.
+    fn flag(&mut self, number: u8) -> bool;
+}
+
+#[derive(Clone)]
+pub struct Cdp1802 {
+    registers: [u16; 16],
+    d: u8,
+    df: bool,
+    p: u8,
+    x: u8,
+    t: u8,
+    ie: bool,
+    q: bool,
+    idle: bool,
+}
+
+impl Default for Cdp1802 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Cdp1802 {
+    pub fn new() -> Cdp1802 {
+        Cdp1802 {
+            registers: [0; 16],
+            d: 0,
+            df: false,
+            p: 0,
+            x: 0,
+            t: 0,
+            ie: true,
+            q: false,
+            idle: false,
+        }
+    }
+
+    // Only the registers the reset signal clears. The rest keep whatever they
+    // held, which the software must not rely on.
+    pub fn reset(&mut self) {
+        self.registers[0] = 0;
+        self.p = 0;
+        self.x = 0;
+        self.ie = true;
+        self.q = false;
+        self.idle = false;
+    }
+
+    pub fn get_register(&self, index: u8) -> u16 {
+        self.registers[index as usize & 0xF]
+    }
+
+    pub fn set_register(&mut self, index: u8, value: u16) {
+        self.registers[index as usize & 0xF] = value;
+    }
+
+    pub fn get_d(&self) -> u8 {
+        self.d
+    }
+
+    pub fn get_df(&self) -> bool {
+        self.df
+    }
+
+    pub fn get_p(&self) -> u8 {
+        self.p
+    }
+
+    pub fn get_x(&self) -> u8 {
+        self.x
+    }
+
+    pub fn q(&self) -> bool {
+        self.q
+    }
+
+    pub fn interrupts_enabled(&self) -> bool {
+        self.ie
+    }
+
+    fn fetch(&mut self, bus: &mut impl Bus) -> u8 {
+        let p = self.p as usize;
+        let byte = bus.read(self.registers[p]);
+        self.registers[p] = self.registers[p].wrapping_add(1);
+
+        byte
+    }
+
+    fn read_x(&mut self, bus: &mut impl Bus) -> u8 {
+        bus.read(self.registers[self.x as usize])
+    }
+
+    fn increment_x(&mut self) {
+        let x = self.x as usize;
+        self.registers[x] = self.registers[x].wrapping_add(1);
+    }
+
+    // D = a + b + carry, with DF set on carry out.
+    fn add(&mut self, a: u8, b: u8, carry: bool) {
+        let sum = a as u16 + b as u16 + carry as u16;
+        self.d = sum as u8;
+        self.df = sum > 0xFF;
+    }
+
+    // D = a - b - borrow, with DF set when there was no borrow.
+    fn subtract(&mut self, a: u8, b: u8, borrow: bool) {
+        let difference = a as i16 - b as i16 - borrow as i16;
+        self.d = difference as u8;
+        self.df = difference >= 0;
+    }
+
+    fn short_branch(&mut self, bus: &mut impl Bus, taken: bool) {
+        let p = self.p as usize;
+
+        if taken {
+            let low = bus.read(self.registers[p]);
+            self.registers[p] = (self.registers[p] & 0xFF00) | low as u16;
+        } else {
+            self.registers[p] = self.registers[p].wrapping_add(1);
+        }
+    }
+
+    fn long_branch(&mut self, bus: &mut impl Bus, taken: bool) {
+        let p = self.p as usize;
+
+        if taken {
+            let high = bus.read(self.registers[p]);
+            let low = bus.read(self.registers[p].wrapping_add(1));
+            self.registers[p] = u16::from_be_bytes([high, low]);
+        } else {
+            self.registers[p] = self.registers[p].wrapping_add(2);
+        }
+    }
+
+    fn long_skip(&mut self, taken: bool) {
+        if taken {
+            let p = self.p as usize;
+            self.registers[p] = self.registers[p].wrapping_add(2);
+        }
+    }
+
+    // RET and DIS: X and P from the byte at R(X), then R(X) + 1.
+    fn return_from_interrupt(&mut self, bus: &mut impl Bus, enable: bool) {
+        let value = self.read_x(bus);
+        self.increment_x();
+        self.x = value >> 4;
+        self.p = value & 0xF;
+        self.ie = enable;
+    }
+
+    // Acknowledges an interrupt request if interrupts are enabled and returns
+    // the machine cycles taken.
+    pub fn interrupt(&mut self) -> u64 {
+        if !self.ie {
+            return 0;
+        }
+
+        self.t = (self.x << 4) | self.p;
+        self.x = 2;
+        self.p = 1;
+        self.ie = false;
+        self.idle = false;
+
+        1
+    }
+
+    // A DMA out cycle: the byte at R0 goes to the requesting device.
+    pub fn dma_out(&mut self, bus: &mut impl Bus) -> u8 {
+        let byte = bus.read(self.registers[0]);
+        self.registers[0] = self.registers[0].wrapping_add(1);
+        self.idle = false;
+
+        byte
+    }
+
+    // Executes one instruction and returns the machine cycles it took. While
+    // idle after IDL, each call waits one cycle.
+    pub fn step(&mut self, bus: &mut impl Bus) -> u64 {
+        if self.idle {
+            return 1;
+        }
+
+        let instruction = self.fetch(bus);
+        let n = instruction & 0xF;
+        let register = n as usize;
+
+        match instruction >> 4 {
+            0x0 => {
+                if n == 0 {
+                    self.idle = true;
+                } else {
+                    self.d = bus.read(self.registers[register]);
+                }
+            }
+            0x1 => self.registers[register] = self.registers[register].wrapping_add(1),
+            0x2 => self.registers[register] = self.registers[register].wrapping_sub(1),
+            0x3 => {
+                let condition = match n & 0x7 {
+                    0x0 => true,
+                    0x1 => self.q,
+                    0x2 => self.d == 0,
+                    0x3 => self.df,
+                    flag => bus.flag(flag - 3),
+                };
+
+                // 38 is SKP rather than a branch that is never taken.
+                if n == 0x8 {
+                    self.short_branch(bus, false);
+                } else {
+                    self.short_branch(bus, condition == (n < 0x8));
+                }
+            }
+            0x4 => {
+                self.d = bus.read(self.registers[register]);
+                self.registers[register] = self.registers[register].wrapping_add(1);
+            }
+            0x5 => bus.write(self.registers[register], self.d),
+            0x6 => match n {
+                0x0 => self.increment_x(),
+                0x1..=0x7 => {
+                    let value = self.read_x(bus);
+                    bus.output(n, value);
+                    self.increment_x();
+                }
+                // 68 does nothing on the 1802.
+                0x8 => {}
+                _ => {
+                    let value = bus.input(n - 8);
+                    bus.write(self.registers[self.x as usize], value);
+                    self.d = value;
+                }
+            },
+            0x7 => match n {
+                0x0 => self.return_from_interrupt(bus, true),
+                0x1 => self.return_from_interrupt(bus, false),
+                0x2 => {
+                    self.d = self.read_x(bus);
+                    self.increment_x();
+                }
+                0x3 => {
+                    let x = self.x as usize;
+                    bus.write(self.registers[x], self.d);
+                    self.registers[x] = self.registers[x].wrapping_sub(1);
+                }
+                0x4 => {
+                    let value = self.read_x(bus);
+                    self.add(value, self.d, self.df);
+                }
+                0x5 => {
+                    let value = self.read_x(bus);
+                    self.subtract(value, self.d, !self.df);
+                }
+                0x6 => {
+                    let carry = self.df;
+                    self.df = self.d & 1 != 0;
+                    self.d = (self.d >> 1) | ((carry as u8) << 7);
+                }
+                0x7 => {
+                    let value = self.read_x(bus);
+                    self.subtract(self.d, value, !self.df);
+                }
+                0x8 => bus.write(self.registers[self.x as usize], self.t),
+                0x9 => {
+                    self.t = (self.x << 4) | self.p;
+                    bus.write(self.registers[2], self.t);
+                    self.x = self.p;
+                    self.registers[2] = self.registers[2].wrapping_sub(1);
+                }
+                0xA => self.q = false,
+                0xB => self.q = true,
+                0xC => {
+                    let value = self.fetch(bus);
+                    self.add(value, self.d, self.df);
+                }
+                0xD => {
+                    let value = self.fetch(bus);
+                    self.subtract(value, self.d, !self.df);
+                }
+                0xE => {
+                    let carry = self.df;
+                    self.df = self.d & 0x80 != 0;
+                    self.d = (self.d << 1) | carry as u8;
+                }
+                _ => {
+                    let value = self.fetch(bus);
+                    self.subtract(self.d, value, !self.df);
+                }
+            },
+            0x8 => self.d = self.registers[register] as u8,
+            0x9 => self.d = (self.registers[register] >> 8) as u8,
+            0xA => self.registers[register] = (self.registers[register] & 0xFF00) | self.d as u16,
+            0xB => {
+                self.registers[register] =
+                    (self.registers[register] & 0x00FF) | ((self.d as u16) << 8)
+            }
+            0xC => {
+                match n {
+                    0x0 => self.long_branch(bus, true),
+                    0x1 => self.long_branch(bus, self.q),
+                    0x2 => self.long_branch(bus, self.d == 0),
+                    0x3 => self.long_branch(bus, self.df),
+                    0x4 => {}
+                    0x5 => self.long_skip(!self.q),
+                    0x6 => self.long_skip(self.d != 0),
+                    0x7 => self.long_skip(!self.df),
+                    0x8 => self.long_skip(true),
+                    0x9 => self.long_branch(bus, !self.q),
+                    0xA => self.long_branch(bus, self.d != 0),
+                    0xB => self.long_branch(bus, !self.df),
+                    0xC => self.long_skip(self.ie),
+                    0xD => self.long_skip(self.q),
+                    0xE => self.long_skip(self.d == 0),
+                    _ => self.long_skip(self.df),
+                }
+
+                return 3;
+            }
+            0xD => self.p = n,
+            0xE => self.x = n,
+            _ => {
+                // F0-F7 take their operand from R(X), F8-FF from the
+                // instruction stream, except for the shifts.
+                let operand = match n {
+                    0x6 | 0xE => 0,
+                    0x0..=0x7 => self.read_x(bus),
+                    _ => self.fetch(bus),
+                };
+
+                match n & 0x7 {
+                    0x0 => self.d = operand,
+                    0x1 => self.d |= operand,
+                    0x2 => self.d &= operand,
+                    0x3 => self.d ^= operand,
+                    0x4 => self.add(operand, self.d, false),
+                    0x5 => self.subtract(operand, self.d, false),
+                    0x6 => {
+                        if n == 0x6 {
+                            self.df = self.d & 1 != 0;
+                            self.d >>= 1;
+                        } else {
+                            self.df = self.d & 0x80 != 0;
+                            self.d <<= 1;
+                        }
+                    }
+                    _ => self.subtract(self.d, operand, false),
+                }
+            }
+        }
+
+        2
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 64K of RAM, fixed flag inputs and a record of what was output.
+    struct TestBus {
+        memory: Vec<u8>,
+        flags: [bool; 4],
+        input: u8,
+        outputs: Vec<(u8, u8)>,
+    }
+
+    impl Bus for TestBus {
+        fn read(&mut self, address: u16) -> u8 {
+            self.memory[address as usize]
+        }
+
+        fn write(&mut self, address: u16, value: u8) {
+            self.memory[address as usize] = value;
+        }
+
+        fn input(&mut self, _port: u8) -> u8 {
+            self.input
+        }
+
+        fn output(&mut self, port: u8, value: u8) {
+            self.outputs.push((port, value));
+        }
+
+        fn flag(&mut self, number: u8) -> bool {
+            self.flags[number as usize - 1]
+        }
+    }
+
+    // A processor fresh from reset, so P and X are 0, with `program` at 0.
+    fn setup(program: &[u8]) -> (Cdp1802, TestBus) {
+        let mut bus = TestBus {
+            memory: vec![0; 0x10000],
+            flags: [false; 4],
+            input: 0,
+            outputs: Vec::new(),
+        };
+        bus.memory[..program.len()].copy_from_slice(program);
+
+        (Cdp1802::new(), bus)
+    }
+
+    fn run(cpu: &mut Cdp1802, bus: &mut TestBus, steps: usize) -> u64 {
+        (0..steps).map(|_| cpu.step(bus)).sum()
+    }
+
+    // Loads D with `d`, points X at R2 holding `operand`, then runs
+    // `instruction`. SHL on 0x80 or 0x00 sets DF beforehand.
+    fn subtract(instruction: u8, d: u8, operand: u8, df: bool) -> (u8, bool) {
+        let (mut cpu, mut bus) = setup(&[
+            0xF8,
+            if df { 0x80 } else { 0x00 },
+            0xFE,
+            0xE2,
+            0xF8,
+            d,
+            instruction,
+        ]);
+        cpu.set_register(2, 0x80);
+        bus.memory[0x80] = operand;
+
+        run(&mut cpu, &mut bus, 5);
+
+        (cpu.get_d(), cpu.get_df())
+    }
+
+    #[test]
+    fn subtraction_sets_df_when_there_is_no_borrow() {
+        // SM: D - M.
+        assert_eq!(subtract(0xF7, 5, 3, false), (2, true));
+        assert_eq!(subtract(0xF7, 3, 5, false), (0xFE, false));
+        assert_eq!(subtract(0xF7, 3, 3, false), (0, true));
+        // SD: M - D.
+        assert_eq!(subtract(0xF5, 3, 5, false), (2, true));
+        assert_eq!(subtract(0xF5, 5, 3, false), (0xFE, false));
+        // SMB and SDB also take away the borrow, which is DF clear.
+        assert_eq!(subtract(0x77, 5, 3, false), (1, true));
+        assert_eq!(subtract(0x77, 5, 3, true), (2, true));
+        assert_eq!(subtract(0x77, 3, 3, false), (0xFF, false));
+        assert_eq!(subtract(0x75, 3, 5, false), (1, true));
+        assert_eq!(subtract(0x75, 3, 3, false), (0xFF, false));
+        assert_eq!(subtract(0x75, 3, 3, true), (0, true));
+    }
+
+    #[test]
+    fn shifts_through_df() {
+        // LDI 81, SHRC: DF was clear, bit 0 goes to DF.
+        let (mut cpu, mut bus) = setup(&[0xF8, 0x81, 0x76, 0x7E, 0x7E]);
+
+        run(&mut cpu, &mut bus, 2);
+        assert_eq!((cpu.get_d(), cpu.get_df()), (0x40, true));
+
+        // SHLC brings DF back in at the bottom.
+        run(&mut cpu, &mut bus, 1);
+        assert_eq!((cpu.get_d(), cpu.get_df()), (0x81, false));
+
+        run(&mut cpu, &mut bus, 1);
+        assert_eq!((cpu.get_d(), cpu.get_df()), (0x02, true));
+    }
+
+    #[test]
+    fn short_branches_replace_the_low_byte() {
+        // BZ taken with D clear, BNZ not taken skips its address byte.
+        let (mut cpu, mut bus) = setup(&[0x32, 0x10]);
+        bus.memory[0x10..0x13].copy_from_slice(&[0x3A, 0x40, 0x00]);
+
+        assert_eq!(run(&mut cpu, &mut bus, 1), 2);
+        assert_eq!(cpu.get_register(0), 0x10);
+
+        run(&mut cpu, &mut bus, 1);
+        assert_eq!(cpu.get_register(0), 0x12);
+    }
+
+    #[test]
+    fn short_branch_at_the_end_of_a_page_goes_to_the_next_page() {
+        // The address byte is at 0x100, so the branch lands in page 1.
+        let (mut cpu, mut bus) = setup(&[0x30, 0xFF]);
+        bus.memory[0xFF..0x101].copy_from_slice(&[0x30, 0x20]);
+
+        run(&mut cpu, &mut bus, 2);
+
+        assert_eq!(cpu.get_register(0), 0x120);
+    }
+
+    #[test]
+    fn short_branches_test_the_flag_inputs() {
+        // B3 taken, then BN1 taken.
+        let (mut cpu, mut bus) = setup(&[0x36, 0x10]);
+        bus.memory[0x10..0x12].copy_from_slice(&[0x3C, 0x20]);
+        bus.flags = [false, false, true, false];
+
+        run(&mut cpu, &mut bus, 2);
+
+        assert_eq!(cpu.get_register(0), 0x20);
+    }
+
+    #[test]
+    fn skp_and_lskp_skip_one_and_two_bytes() {
+        let (mut cpu, mut bus) = setup(&[0x38, 0xFF, 0xC8, 0xFF, 0xFF, 0xC4]);
+
+        assert_eq!(run(&mut cpu, &mut bus, 1), 2);
+        assert_eq!(cpu.get_register(0), 2);
+
+        assert_eq!(run(&mut cpu, &mut bus, 1), 3);
+        assert_eq!(cpu.get_register(0), 5);
+
+        // NOP also takes three cycles.
+        assert_eq!(run(&mut cpu, &mut bus, 1), 3);
+        assert_eq!(cpu.get_register(0), 6);
+    }
+
+    #[test]
+    fn long_branches_and_skips_take_three_cycles() {
+        // LBR 1234; there LDI 1, LBZ not taken, LSZ not taken, LSNZ taken.
+        let (mut cpu, mut bus) = setup(&[0xC0, 0x12, 0x34]);
+        bus.memory[0x1234..0x123D]
+            .copy_from_slice(&[0xF8, 0x01, 0xC2, 0xFF, 0xFF, 0xCE, 0xC6, 0xFF, 0xFF]);
+
+        assert_eq!(run(&mut cpu, &mut bus, 1), 3);
+        assert_eq!(cpu.get_register(0), 0x1234);
+
+        run(&mut cpu, &mut bus, 2);
+        assert_eq!(cpu.get_register(0), 0x1239);
+
+        assert_eq!(run(&mut cpu, &mut bus, 1), 3);
+        assert_eq!(cpu.get_register(0), 0x123A);
+
+        assert_eq!(run(&mut cpu, &mut bus, 1), 3);
+        assert_eq!(cpu.get_register(0), 0x123D);
+    }
+
+    #[test]
+    fn mark_saves_x_and_p_below_r2() {
+        // SEX 5, MARK.
+        let (mut cpu, mut bus) = setup(&[0xE5, 0x79]);
+        cpu.set_register(2, 0x80);
+
+        run(&mut cpu, &mut bus, 2);
+
+        assert_eq!(bus.memory[0x80], 0x50);
+        assert_eq!(cpu.get_register(2), 0x7F);
+        assert_eq!(cpu.get_x(), 0);
+        assert_eq!(cpu.get_p(), 0);
+    }
+
+    #[test]
+    fn ret_and_dis_restore_x_and_p_and_set_ie() {
+        // DIS with X = P = 0 reads its operand from the program: X 2, P 3.
+        let (mut cpu, mut bus) = setup(&[0x71, 0x23]);
+        cpu.set_register(3, 0x40);
+        bus.memory[0x40..0x42].copy_from_slice(&[0x70, 0x31]);
+        cpu.set_register(2, 0x80);
+        bus.memory[0x80] = 0x31;
+
+        run(&mut cpu, &mut bus, 1);
+        assert_eq!((cpu.get_x(), cpu.get_p()), (2, 3));
+        assert_eq!(cpu.get_register(0), 2);
+        assert!(!cpu.interrupts_enabled());
+
+        // RET reads M(R2).
+        run(&mut cpu, &mut bus, 1);
+        assert_eq!((cpu.get_x(), cpu.get_p()), (3, 1));
+        assert_eq!(cpu.get_register(2), 0x81);
+        assert!(cpu.interrupts_enabled());
+    }
+
+    #[test]
+    fn interrupt_saves_x_and_p_in_t_for_sav() {
+        // SEX 4, SEP 3. The handler at R1 saves T and returns.
+        let (mut cpu, mut bus) = setup(&[0xE4, 0xD3]);
+        cpu.set_register(1, 0x40);
+        cpu.set_register(2, 0x80);
+        cpu.set_register(3, 0x60);
+        bus.memory[0x40..0x42].copy_from_slice(&[0x78, 0x70]);
+
+        run(&mut cpu, &mut bus, 2);
+        assert_eq!(cpu.interrupt(), 1);
+        assert_eq!((cpu.get_x(), cpu.get_p()), (2, 1));
+        assert!(!cpu.interrupts_enabled());
+
+        // Interrupts stay off until RET.
+        assert_eq!(cpu.interrupt(), 0);
+
+        run(&mut cpu, &mut bus, 2);
+        assert_eq!(bus.memory[0x80], 0x43);
+        assert_eq!((cpu.get_x(), cpu.get_p()), (4, 3));
+        assert_eq!(cpu.get_register(3), 0x60);
+        assert!(cpu.interrupts_enabled());
+    }
+
+    #[test]
+    fn dma_reads_through_r0_and_ends_idle() {
+        // SEP 3, then IDL at 0x40 until a DMA cycle, then INC 5.
+        let (mut cpu, mut bus) = setup(&[0xD3]);
+        cpu.set_register(3, 0x40);
+        bus.memory[0x40..0x42].copy_from_slice(&[0x00, 0x15]);
+        bus.memory[0x01..0x03].copy_from_slice(&[0xAA, 0xBB]);
+
+        run(&mut cpu, &mut bus, 2);
+        assert_eq!(run(&mut cpu, &mut bus, 3), 3);
+        assert_eq!(cpu.get_register(3), 0x41);
+
+        assert_eq!(cpu.dma_out(&mut bus), 0xAA);
+        assert_eq!(cpu.dma_out(&mut bus), 0xBB);
+        assert_eq!(cpu.get_register(0), 3);
+
+        run(&mut cpu, &mut bus, 1);
+        assert_eq!(cpu.get_register(5), 1);
+    }
+
+    #[test]
+    fn out_and_inp_go_through_r_x() {
+        // SEX 2, OUT 4, INP 1 (69).
+        let (mut cpu, mut bus) = setup(&[0xE2, 0x64, 0x69]);
+        cpu.set_register(2, 0x80);
+        bus.memory[0x80] = 0x12;
+        bus.input = 0x34;
+
+        run(&mut cpu, &mut bus, 3);
+
+        assert_eq!(bus.outputs, [(4, 0x12)]);
+        assert_eq!(cpu.get_register(2), 0x81);
+        assert_eq!(bus.memory[0x81], 0x34);
+        assert_eq!(cpu.get_d(), 0x34);
+    }
+}
@@ -0,0 +1,523 @@
+use clap::Parser;
+
+use crate::audio::DEFAULT_VOLUME;
+use crate::graphics::DEFAULT_SCALE;
+use chip_8_emulator::config::Settings;
+use chip_8_emulator::cpu::DEFAULT_INSTRUCTIONS_PER_FRAME;
+use chip_8_emulator::display_filter::{FilterError, FilterMode};
+use chip_8_emulator::gamepad::{GamepadError, GamepadMap, GAMEPAD_BUTTONS};
+use chip_8_emulator::keymap::{Keymap, KeymapError, KEYMAP_NAMES};
+use chip_8_emulator::palette::{Palette, PaletteError, PALETTE_NAMES};
+use chip_8_emulator::platform::{Platform, PLATFORM_NAMES};
+use chip_8_emulator::quirks::{Quirks, QuirksError, QUIRK_NAMES, QUIRK_PRESETS};
+use chip_8_emulator::timing::{Timing, TIMING_NAMES};
+use chip_8_emulator::trace::{TraceFormat, TraceFormatError};
+
+#[derive(Parser)]
+#[command(version, about = "A CHIP-8 emulator")]
+pub struct Args {
+    #[arg(help = "ROM to run: a binary, a hex dump (.hex, .txt), a .zip archive or - for stdin")]
+    pub rom: String,
+
+    #[arg(
+        long,
+        value_name = "NAME",
+        help = "ROM to run from a .zip archive holding several"
+    )]
+    pub entry: Option<String>,
+
+    #[arg(
+        long,
+        value_name = "N",
+        value_parser = clap::value_parser!(u32).range(60..),
+        help = format!(
+            "Instructions executed per second [default: {}]",
+            DEFAULT_INSTRUCTIONS_PER_FRAME * 60
+        )
+    )]
+    pub ips: Option<u32>,
+
+    #[arg(
+        long,
+        value_name = "LIST",
+        value_parser = parse_quirks,
+        help = format!(
+            "Interpreter quirks: a preset ({}) and/or quirks to enable ({}), \
+             prefixed with no- to disable, e.g. schip,no-clip",
+            QUIRK_PRESETS.join(", "),
+            QUIRK_NAMES.join(", ")
+        )
+    )]
+    pub quirks: Option<Quirks>,
+
+    #[arg(
+        long,
+        value_name = "PLATFORM",
+        value_parser = parse_platform,
+        help = format!(
+            "CHIP-8 variant: {}. hires has a 64x64 display and starts at 0x2C0, \
+             chip8x adds colour and a second keypad [default: chip8]",
+            PLATFORM_NAMES.join(", ")
+        )
+    )]
+    pub platform: Option<Platform>,
+
+    #[arg(
+        long,
+        value_name = "TIMING",
+        value_parser = parse_timing,
+        help = format!(
+            "Timing model: {}. vip charges each instruction its COSMAC VIP cost and \
+             ignores --ips [default: fixed]",
+            TIMING_NAMES.join(", ")
+        )
+    )]
+    pub timing: Option<Timing>,
+
+    #[arg(
+        long,
+        value_name = "FILE",
+        conflicts_with_all = ["debug", "gdb", "trace", "profile", "profile_folded", "coverage"],
+        help = "Run the ROM through the original COSMAC VIP interpreter image in FILE on an emulated CDP1802"
+    )]
+    pub vip: Option<String>,
+
+    #[arg(
+        long,
+        value_name = "FILE",
+        requires = "vip",
+        help = "Boot the VIP through the monitor ROM image in FILE"
+    )]
+    pub vip_monitor: Option<String>,
+
+    #[arg(
+        long,
+        value_name = "N",
+        value_parser = clap::value_parser!(u32).range(1..),
+        help = "Initial window size as a multiple of the display resolution [default: 16]"
+    )]
+    pub scale: Option<u32>,
+
+    #[arg(
+        long,
+        value_name = "PALETTE",
+        value_parser = parse_palette,
+        help = format!(
+            "Colour palette: {} or a list of hex colours such as \"#000000,#FFB000\" \
+             [default: classic]",
+            PALETTE_NAMES.join(", ")
+        )
+    )]
+    pub palette: Option<Palette>,
+
+    #[arg(
+        long,
+        value_name = "FILTER",
+        value_parser = parse_filter,
+        help = "Display filter: none, phosphor[:DECAY] or blend[:FRAMES] [default: none]"
+    )]
+    pub filter: Option<FilterMode>,
+
+    #[arg(long, help = "Start in borderless fullscreen")]
+    pub fullscreen: bool,
+
+    #[arg(long, help = "Draw pixel grid lines")]
+    pub grid: bool,
+
+    #[arg(long, help = "Do not allow the window to be resized")]
+    pub fixed_size: bool,
+
+    #[arg(
+        long,
+        help = "Show the on-screen keypad, which can be clicked or touched"
+    )]
+    pub keypad: bool,
+
+    #[arg(
+        long,
+        value_name = "KEYMAP",
+        value_parser = parse_keymap,
+        help = format!(
+            "Keyboard layout: {} or 16 keys for CHIP-8 keys 0-F in order [default: qwerty]",
+            KEYMAP_NAMES.join(", ")
+        )
+    )]
+    pub keymap: Option<Keymap>,
+
+    #[arg(
+        long,
+        value_name = "MAP",
+        value_parser = parse_gamepad,
+        help = format!(
+            "Controller mapping as BUTTON=KEY pairs, e.g. dpup=5,a=6, optionally starting with \
+             default to extend the default mapping. Buttons: {}",
+            GAMEPAD_BUTTONS.join(", ")
+        )
+    )]
+    pub gamepad: Option<GamepadMap>,
+
+    #[arg(
+        long,
+        value_name = "MS",
+        default_value_t = 0,
+        help = "Delay every key change by MS milliseconds of emulated time"
+    )]
+    pub input_latency: u64,
+
+    #[arg(
+        long = "min-hold",
+        value_name = "MS",
+        default_value_t = 0,
+        help = "Keep keys down for at least MS milliseconds so fast taps register"
+    )]
+    pub minimum_hold: u64,
+
+    #[arg(long, value_name = "N", help = "Seed for the random number generator")]
+    pub seed: Option<u64>,
+
+    #[arg(long, requires = "frames", help = "Run without a window or audio")]
+    pub headless: bool,
+
+    #[arg(long, value_name = "N", help = "Quit after emulating N frames")]
+    pub frames: Option<u64>,
+
+    #[arg(
+        long,
+        value_name = "FILE",
+        requires = "headless",
+        help = "Save the last frame as a PNG image to FILE"
+    )]
+    pub screenshot: Option<String>,
+
+    #[arg(
+        long,
+        value_name = "FILE",
+        num_args = 0..=1,
+        default_missing_value = "-",
+        help = "Log every executed instruction to FILE, or to stdout if omitted"
+    )]
+    pub trace: Option<String>,
+
+    #[arg(
+        long,
+        value_name = "START-END",
+        value_parser = parse_range,
+        requires = "trace",
+        help = "Only trace instructions at addresses from START to END inclusive"
+    )]
+    pub trace_addresses: Option<(u16, u16)>,
+
+    #[arg(
+        long,
+        value_name = "FROM-TO",
+        value_parser = parse_cycle_range,
+        requires = "trace",
+        help = "Only trace instructions FROM to TO inclusive, counting from 1"
+    )]
+    pub trace_cycles: Option<(u64, u64)>,
+
+    #[arg(
+        long,
+        value_name = "FORMAT",
+        value_parser = parse_trace_format,
+        requires = "trace",
+        help = "Lay trace lines out as FORMAT, e.g. \"{pc:x} {opcode:x}\""
+    )]
+    pub trace_format: Option<TraceFormat>,
+
+    #[arg(long, conflicts_with_all = ["headless", "gdb"], help = "Run in the terminal debugger")]
+    pub debug: bool,
+
+    #[arg(
+        long,
+        value_name = "PORT",
+        conflicts_with = "headless",
+        help = "Wait for a GDB connection on PORT"
+    )]
+    pub gdb: Option<u16>,
+
+    #[arg(
+        long,
+        conflicts_with_all = ["headless", "debug", "gdb"],
+        help = "Reload the ROM whenever the file changes"
+    )]
+    pub watch: bool,
+
+    #[arg(
+        long,
+        value_name = "START-END",
+        value_parser = parse_range,
+        requires = "watch",
+        help = "Carry the memory from START to END inclusive over when the ROM is reloaded"
+    )]
+    pub keep_memory: Option<(u16, u16)>,
+
+    #[arg(
+        long,
+        requires = "watch",
+        help = "Restore the last save state (F5) when the ROM is reloaded"
+    )]
+    pub restore_state: bool,
+
+    #[arg(long, help = "Disable sound")]
+    pub mute: bool,
+
+    #[arg(
+        long,
+        value_name = "FILE",
+        help = "Read settings from FILE instead of the user configuration file"
+    )]
+    pub config: Option<String>,
+
+    #[arg(
+        long,
+        value_name = "DIRECTORY",
+        help = "Use the CHIP-8 database JSON files in DIRECTORY instead of the built-in copy"
+    )]
+    pub database: Option<String>,
+
+    #[arg(long, help = "Ignore the ROM database")]
+    pub no_database: bool,
+
+    #[arg(
+        long,
+        value_name = "ADDRESS",
+        value_parser = parse_address,
+        help = "Address to load the ROM at and start executing from [default: 0x200, 0x300 for chip8x]"
+    )]
+    pub start_address: Option<u16>,
+
+    #[arg(long, value_name = "FILE", help = "Record keypad input to FILE")]
+    pub record: Option<String>,
+
+    #[arg(
+        long,
+        value_name = "FILE",
+        conflicts_with_all = ["record", "seed"],
+        help = "Replay keypad input recorded with --record"
+    )]
+    pub replay: Option<String>,
+
+    #[arg(long, value_name = "FILE", help = "Record an animated GIF to FILE")]
+    pub gif: Option<String>,
+
+    #[arg(
+        long,
+        value_name = "PREFIX",
+        help = "Record raw video and sound to PREFIX.y4m and PREFIX.wav"
+    )]
+    pub video: Option<String>,
+
+    #[arg(
+        long,
+        value_name = "FILE",
+        help = "Write an execution profile to FILE on exit"
+    )]
+    pub profile: Option<String>,
+
+    #[arg(
+        long,
+        value_name = "FILE",
+        help = "Write folded call stacks for flame graph tools to FILE on exit"
+    )]
+    pub profile_folded: Option<String>,
+
+    #[arg(long, value_name = "FILE", help = "Accumulate code coverage in FILE")]
+    pub coverage: Option<String>,
+
+    #[arg(
+        long,
+        value_name = "FILE",
+        requires = "coverage",
+        help = "Write the ROM annotated with execution counts to FILE on exit"
+    )]
+    pub coverage_listing: Option<String>,
+
+    #[arg(
+        long,
+        value_name = "FILE",
+        requires = "coverage",
+        help = "Write the coverage in lcov format to FILE on exit"
+    )]
+    pub coverage_lcov: Option<String>,
+
+    #[arg(
+        long,
+        value_name = "FILE",
+        requires = "coverage_lcov",
+        help = "Attribute lcov coverage to the source lines listed in FILE"
+    )]
+    pub source_map: Option<String>,
+}
+
+// The settings in effect for this run. Command-line options win over the ROM's
+// section of the config file, then the ROM database, then the file's global
+// defaults.
+pub struct Options {
+    pub title: Option<String>,
+    pub ips: u32,
+    pub quirks: Quirks,
+    pub timing: Timing,
+    pub platform: Platform,
+    pub start_address: Option<u16>,
+    pub palette: Palette,
+    pub filter: FilterMode,
+    pub keymap: Keymap,
+    pub gamepad: GamepadMap,
+    pub mute: bool,
+    pub volume: f32,
+    pub scale: u32,
+    pub fullscreen: bool,
+    pub grid: bool,
+    pub fixed_size: bool,
+}
+
+impl Options {
+    pub fn resolve(args: &Args, settings: &Settings) -> Result<Options, String> {
+        Ok(Options {
+            title: None,
+            ips: args
+                .ips
+                .or(settings.ips)
+                .unwrap_or(DEFAULT_INSTRUCTIONS_PER_FRAME * 60),
+            quirks: resolve(&args.quirks, &settings.quirks, parse_quirks)?.unwrap_or_default(),
+            timing: resolve(&args.timing, &settings.timing, parse_timing)?.unwrap_or_default(),
+            platform: resolve(&args.platform, &settings.platform, parse_platform)?
+                .unwrap_or_default(),
+            start_address: resolve(&args.start_address, &settings.start_address, parse_address)?,
+            palette: resolve(&args.palette, &settings.palette, parse_palette)?.unwrap_or_default(),
+            filter: resolve(&args.filter, &settings.filter, parse_filter)?
+                .unwrap_or(FilterMode::None),
+            keymap: resolve(&args.keymap, &settings.keymap, parse_keymap)?.unwrap_or_default(),
+            gamepad: resolve(&args.gamepad, &settings.gamepad, parse_gamepad)?.unwrap_or_default(),
+            mute: args.mute || settings.mute.unwrap_or(false),
+            volume: settings.volume.unwrap_or(DEFAULT_VOLUME).clamp(0.0, 1.0),
+            scale: match args.scale.or(settings.scale) {
+                Some(0) => return Err("in settings: scale must be at least 1".to_string()),
+                scale => scale.unwrap_or(DEFAULT_SCALE),
+            },
+            fullscreen: args.fullscreen || settings.fullscreen.unwrap_or(false),
+            grid: args.grid || settings.grid.unwrap_or(false),
+            fixed_size: args.fixed_size || settings.fixed_size.unwrap_or(false),
+        })
+    }
+}
+
+fn resolve<T: Clone>(
+    argument: &Option<T>,
+    setting: &Option<String>,
+    parse: fn(&str) -> Result<T, String>,
+) -> Result<Option<T>, String> {
+    match (argument, setting) {
+        (Some(value), _) => Ok(Some(value.clone())),
+        (None, Some(text)) => parse(text)
+            .map(Some)
+            .map_err(|error| format!("in settings: {}", error)),
+        (None, None) => Ok(None),
+    }
+}
+
+fn parse_quirks(text: &str) -> Result<Quirks, String> {
+    Quirks::parse(text).map_err(|error| match error {
+        QuirksError::UnknownQuirk(name) => format!("unknown quirk '{}'", name),
+    })
+}
+
+fn parse_palette(text: &str) -> Result<Palette, String> {
+    Palette::parse(text).map_err(|error| match error {
+        PaletteError::UnknownPalette(name) => format!("unknown palette '{}'", name),
+        PaletteError::InvalidColor(color) => format!("invalid colour '{}'", color),
+        PaletteError::TooFewColors => "a palette needs at least two colours".to_string(),
+    })
+}
+
+fn parse_keymap(text: &str) -> Result<Keymap, String> {
+    Keymap::parse(text).map_err(|error| match error {
+        KeymapError::UnknownKeymap(name) => format!("unknown keymap '{}'", name),
+        KeymapError::DuplicateKey(key) => format!("key '{}' is mapped twice", key),
+    })
+}
+
+fn parse_gamepad(text: &str) -> Result<GamepadMap, String> {
+    GamepadMap::parse(text).map_err(|error| match error {
+        GamepadError::UnknownButton(name) => format!("unknown controller button '{}'", name),
+        GamepadError::InvalidKey(key) => format!("invalid CHIP-8 key '{}'", key),
+    })
+}
+
+fn parse_filter(text: &str) -> Result<FilterMode, String> {
+    FilterMode::parse(text).map_err(|error| match error {
+        FilterError::UnknownFilter(name) => format!("unknown filter '{}'", name),
+        FilterError::InvalidParameter(filter) => format!(
+            "invalid filter '{}', expected phosphor:DECAY from 0 to 1 or blend:FRAMES",
+            filter
+        ),
+    })
+}
+
+fn parse_trace_format(text: &str) -> Result<TraceFormat, String> {
+    TraceFormat::parse(text).map_err(|error| match error {
+        TraceFormatError::InvalidField(field) => format!("unknown trace field '{{{}}}'", field),
+        TraceFormatError::Unterminated => "trace format has an unclosed '{'".to_string(),
+    })
+}
+
+fn parse_timing(text: &str) -> Result<Timing, String> {
+    match text {
+        "fixed" => Ok(Timing::Fixed),
+        "vip" => Ok(Timing::Vip),
+        _ => Err(format!("unknown timing '{}'", text)),
+    }
+}
+
+fn parse_platform(text: &str) -> Result<Platform, String> {
+    match text {
+        "chip8" => Ok(Platform::Chip8),
+        "hires" => Ok(Platform::HiRes),
+        "chip8x" => Ok(Platform::Chip8X),
+        _ => Err(format!("unknown platform '{}'", text)),
+    }
+}
+
+fn parse_address(text: &str) -> Result<u16, String> {
+    let result = match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        Some(digits) => u16::from_str_radix(digits, 16),
+        None => text.parse(),
+    };
+
+    match result {
+        Ok(address) if (address as usize) < chip_8_emulator::memory::MEMORY_SIZE => Ok(address),
+        _ => Err(format!("invalid address '{}'", text)),
+    }
+}
+
+fn parse_range(text: &str) -> Result<(u16, u16), String> {
+    let (start, end) = text
+        .split_once('-')
+        .ok_or_else(|| format!("invalid range '{}', expected START-END", text))?;
+    let (start, end) = (parse_address(start)?, parse_address(end)?);
+
+    if start > end {
+        return Err(format!("range '{}' ends before it starts", text));
+    }
+
+    Ok((start, end))
+}
+
+fn parse_cycle_range(text: &str) -> Result<(u64, u64), String> {
+    let (from, to) = text
+        .split_once('-')
+        .ok_or_else(|| format!("invalid range '{}', expected FROM-TO", text))?;
+    let parse = |number: &str| {
+        number
+            .parse::<u64>()
+            .map_err(|_| format!("invalid cycle '{}'", number))
+    };
+    let (from, to) = (parse(from)?, parse(to)?);
+
+    if from > to {
+        return Err(format!("range '{}' ends before it starts", text));
+    }
+
+    Ok((from, to))
+}
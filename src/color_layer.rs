@@ -0,0 +1,95 @@
+use crate::palette::Color;
+
+// The VP-590 colour board's colours, indexed by the 3-bit value programs
+// write to its colour RAM.
+pub const CHIP8X_COLORS: [Color; 8] = [
+    Color::new(0x00, 0x00, 0x00), // Black
+    Color::new(0xFF, 0x00, 0x00), // Red
+    Color::new(0x00, 0x00, 0xFF), // Blue
+    Color::new(0xFF, 0x00, 0xFF), // Violet
+    Color::new(0x00, 0xFF, 0x00), // Green
+    Color::new(0xFF, 0xFF, 0x00), // Yellow
+    Color::new(0x00, 0xFF, 0xFF), // Aqua
+    Color::new(0xFF, 0xFF, 0xFF), // White
+];
+
+// 02A0 steps the background through blue, black, green and red.
+const BACKGROUND_CYCLE: [u8; 4] = [2, 0, 4, 1];
+const DEFAULT_FOREGROUND: u8 = 1;
+
+const ZONE_WIDTH: usize = 8;
+// BXY0 zones are four rows high.
+const ZONE_HEIGHT: usize = 4;
+
+// CHIP-8X colour attributes over the monochrome screen: one background
+// colour for the whole display and a foreground colour for every row of
+// each 8 pixel wide column.
+#[derive(Clone)]
+pub struct ColorLayer {
+    background: usize,
+    foreground: Vec<Vec<u8>>,
+}
+
+impl ColorLayer {
+    pub fn new(width: usize, height: usize) -> ColorLayer {
+        ColorLayer {
+            background: 0,
+            foreground: vec![vec![DEFAULT_FOREGROUND; width.div_ceil(ZONE_WIDTH)]; height],
+        }
+    }
+
+    pub fn cycle_background(&mut self) {
+        self.background = (self.background + 1) % BACKGROUND_CYCLE.len();
+    }
+
+    pub fn background(&self) -> Color {
+        CHIP8X_COLORS[BACKGROUND_CYCLE[self.background] as usize]
+    }
+
+    pub fn foreground(&self, x: usize, y: usize) -> Color {
+        let row = &self.foreground[y % self.foreground.len()];
+        CHIP8X_COLORS[row[x / ZONE_WIDTH % row.len()] as usize]
+    }
+
+    fn fill(&mut self, columns: std::ops::Range<usize>, rows: std::ops::Range<usize>, color: u8) {
+        for row in self.foreground.iter_mut().take(rows.end).skip(rows.start) {
+            for zone in row.iter_mut().take(columns.end).skip(columns.start) {
+                *zone = color & 0x7;
+            }
+        }
+    }
+
+    // BXY0: the low nibbles of `vx` and `vy` give the first column and zone
+    // row, the high nibbles how many more to colour.
+    pub fn fill_zones(&mut self, vx: u8, vy: u8, color: u8) {
+        let column = (vx & 0xF) as usize;
+        let row = (vy & 0xF) as usize * ZONE_HEIGHT;
+        let columns = (vx >> 4) as usize + 1;
+        let rows = ((vy >> 4) as usize + 1) * ZONE_HEIGHT;
+
+        self.fill(column..column + columns, row..row + rows, color);
+    }
+
+    // BXYN: the column holding pixel `x`, from row `y` down `height` rows.
+    pub fn fill_rows(&mut self, x: u8, y: u8, height: u8, color: u8) {
+        let column = x as usize / ZONE_WIDTH;
+        let row = y as usize;
+
+        self.fill(column..column + 1, row..row + height as usize, color);
+    }
+
+    // Colours per-pixel intensities, row by row, where 0 shows the
+    // background and 255 the pixel's foreground.
+    pub fn intensities_to_rgba(&self, intensities: &[u8], width: usize) -> Vec<u8> {
+        let background = self.background();
+
+        intensities
+            .iter()
+            .enumerate()
+            .flat_map(|(i, &intensity)| {
+                let color = background.blend(self.foreground(i % width, i / width), intensity);
+                [color.red, color.green, color.blue, 255]
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,317 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+const CONFIG_DIRECTORY: &str = "chip-8-emulator";
+const CONFIG_FILENAME: &str = "config.toml";
+
+#[derive(Debug)]
+pub enum ConfigError {
+    IoError(std::io::Error),
+    ParseError(toml::de::Error),
+    EditError(toml_edit::TomlError),
+}
+
+impl From<std::io::Error> for ConfigError {
+    fn from(error: std::io::Error) -> Self {
+        ConfigError::IoError(error)
+    }
+}
+
+impl From<toml::de::Error> for ConfigError {
+    fn from(error: toml::de::Error) -> Self {
+        ConfigError::ParseError(error)
+    }
+}
+
+impl From<toml_edit::TomlError> for ConfigError {
+    fn from(error: toml_edit::TomlError) -> Self {
+        ConfigError::EditError(error)
+    }
+}
+
+// Every setting is optional so that a ROM section only overrides what it
+// names. Values use the same syntax as the matching command-line options.
+#[derive(Deserialize, Clone, PartialEq, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Settings {
+    pub keymap: Option<String>,
+    pub palette: Option<String>,
+    pub filter: Option<String>,
+    pub ips: Option<u32>,
+    pub quirks: Option<String>,
+    pub mute: Option<bool>,
+    pub volume: Option<f32>,
+    pub gamepad: Option<String>,
+    pub timing: Option<String>,
+    pub platform: Option<String>,
+    pub start_address: Option<String>,
+    pub scale: Option<u32>,
+    pub fullscreen: Option<bool>,
+    pub grid: Option<bool>,
+    pub fixed_size: Option<bool>,
+}
+
+impl Settings {
+    // Settings from `self` win, anything it leaves unset comes from `fallback`.
+    pub fn or(&self, fallback: &Settings) -> Settings {
+        Settings {
+            keymap: self.keymap.clone().or_else(|| fallback.keymap.clone()),
+            palette: self.palette.clone().or_else(|| fallback.palette.clone()),
+            filter: self.filter.clone().or_else(|| fallback.filter.clone()),
+            ips: self.ips.or(fallback.ips),
+            quirks: self.quirks.clone().or_else(|| fallback.quirks.clone()),
+            mute: self.mute.or(fallback.mute),
+            volume: self.volume.or(fallback.volume),
+            gamepad: self.gamepad.clone().or_else(|| fallback.gamepad.clone()),
+            timing: self.timing.clone().or_else(|| fallback.timing.clone()),
+            platform: self.platform.clone().or_else(|| fallback.platform.clone()),
+            start_address: self
+                .start_address
+                .clone()
+                .or_else(|| fallback.start_address.clone()),
+            scale: self.scale.or(fallback.scale),
+            fullscreen: self.fullscreen.or(fallback.fullscreen),
+            grid: self.grid.or(fallback.grid),
+            fixed_size: self.fixed_size.or(fallback.fixed_size),
+        }
+    }
+}
+
+// The global defaults live at the top level of the file, per-ROM overrides
+// in sections keyed by the SHA-1 of the ROM:
+//
+//   palette = "amber"
+//   ips = 700
+//
+//   [rom.0123456789abcdef0123456789abcdef01234567]
+//   quirks = "schip"
+//   ips = 1000
+#[derive(Deserialize, Clone, PartialEq, Debug, Default)]
+#[serde(default)]
+pub struct Config {
+    #[serde(flatten)]
+    pub defaults: Settings,
+    pub rom: BTreeMap<String, Settings>,
+}
+
+pub fn rom_sha1(rom: &[u8]) -> String {
+    sha1_smol::Sha1::from(rom).digest().to_string()
+}
+
+impl Config {
+    // $XDG_CONFIG_HOME/chip-8-emulator/config.toml, falling back to
+    // ~/.config when XDG_CONFIG_HOME is unset or not absolute.
+    pub fn default_path() -> Option<PathBuf> {
+        let config_home = std::env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .filter(|path| path.is_absolute())
+            .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+
+        Some(config_home.join(CONFIG_DIRECTORY).join(CONFIG_FILENAME))
+    }
+
+    pub fn parse(text: &str) -> Result<Config, ConfigError> {
+        let mut config: Config = toml::from_str(text)?;
+
+        // Flattening lets unknown top-level keys through, so the defaults
+        // are checked again on their own.
+        let mut defaults: toml::Table = text.parse()?;
+        defaults.remove("rom");
+        toml::Value::Table(defaults).try_into::<Settings>()?;
+
+        config.rom = config
+            .rom
+            .into_iter()
+            .map(|(sha1, settings)| (sha1.to_ascii_lowercase(), settings))
+            .collect();
+
+        Ok(config)
+    }
+
+    pub fn load(path: &Path) -> Result<Config, ConfigError> {
+        Config::parse(&std::fs::read_to_string(path)?)
+    }
+
+    pub fn load_or_default(path: &Path) -> Result<Config, ConfigError> {
+        match Config::load(path) {
+            Err(ConfigError::IoError(error)) if error.kind() == std::io::ErrorKind::NotFound => {
+                Ok(Config::default())
+            }
+            result => result,
+        }
+    }
+
+    // Sets `key` in the ROM's section of the file at `path` and in `self`,
+    // creating the file and the section as needed. The rest of the file,
+    // comments included, is left as it was.
+    pub fn save_rom_setting(
+        &mut self,
+        path: &Path,
+        sha1: &str,
+        key: &str,
+        value: &str,
+    ) -> Result<(), ConfigError> {
+        let text = match std::fs::read_to_string(path) {
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => String::new(),
+            result => result?,
+        };
+
+        // Only files that load as a config are touched.
+        Config::parse(&text)?;
+        let mut document: toml_edit::DocumentMut = text.parse()?;
+
+        // Sections are matched whatever the case of their SHA-1.
+        let roms = document.entry("rom").or_insert_with(|| {
+            let mut roms = toml_edit::Table::new();
+            roms.set_implicit(true);
+            toml_edit::Item::Table(roms)
+        });
+        let section = roms
+            .as_table_like()
+            .and_then(|roms| {
+                roms.iter()
+                    .map(|(name, _)| name.to_string())
+                    .find(|name| name.eq_ignore_ascii_case(sha1))
+            })
+            .unwrap_or_else(|| sha1.to_ascii_lowercase());
+
+        if roms.get(&section).is_none() {
+            roms[&section] = if roms.is_inline_table() {
+                toml_edit::value(toml_edit::InlineTable::new())
+            } else {
+                toml_edit::table()
+            };
+        }
+        roms[&section][key] = toml_edit::value(value);
+
+        let text = document.to_string();
+        let mut updated = Config::parse(&text)?;
+
+        if let Some(directory) = path.parent() {
+            std::fs::create_dir_all(directory)?;
+        }
+        std::fs::write(path, text)?;
+
+        let sha1 = sha1.to_ascii_lowercase();
+        if let Some(settings) = updated.rom.remove(&sha1) {
+            self.rom.insert(sha1, settings);
+        }
+
+        Ok(())
+    }
+
+    // The ROM's own section layered over `known`, which holds what is known
+    // about the ROM from elsewhere, layered over the global defaults.
+    pub fn settings_for(&self, rom: &[u8], known: &Settings) -> Settings {
+        let known = known.or(&self.defaults);
+
+        match self.rom.get(&rom_sha1(rom)) {
+            Some(settings) => settings.or(&known),
+            None => known,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SHA1: &str = "0123456789abcdef0123456789abcdef01234567";
+
+    #[test]
+    fn save_rom_setting_keeps_the_rest_of_the_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        let text = "# My settings\npalette = \"amber\"\n\n[rom.0123456789ABCDEF0123456789ABCDEF01234567]\nips = 1000\n";
+        std::fs::write(&path, text).unwrap();
+        let mut config = Config::load(&path).unwrap();
+
+        config
+            .save_rom_setting(&path, SHA1, "gamepad", "default,a=5")
+            .unwrap();
+        let saved = std::fs::read_to_string(&path).unwrap();
+        let reloaded = Config::load(&path).unwrap();
+
+        assert!(saved.starts_with("# My settings\npalette = \"amber\"\n"));
+        assert_eq!(reloaded, config);
+        assert_eq!(reloaded.rom[SHA1].ips, Some(1000));
+        assert_eq!(reloaded.rom[SHA1].gamepad.as_deref(), Some("default,a=5"));
+    }
+
+    #[test]
+    fn save_rom_setting_creates_the_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("new").join("config.toml");
+        let mut config = Config::default();
+
+        config
+            .save_rom_setting(&path, SHA1, "gamepad", "default")
+            .unwrap();
+        let saved = std::fs::read_to_string(&path).unwrap();
+
+        assert_eq!(saved, format!("[rom.{}]\ngamepad = \"default\"\n", SHA1));
+        assert_eq!(config.rom[SHA1].gamepad.as_deref(), Some("default"));
+    }
+
+    #[test]
+    fn parse_reads_defaults_and_rom_sections() {
+        let config = Config::parse(
+            "palette = \"amber\"\nips = 700\n\n[rom.0123456789ABCDEF0123456789ABCDEF01234567]\nquirks = \"schip\"\nips = 1000\n",
+        )
+        .unwrap();
+
+        assert_eq!(config.defaults.palette.as_deref(), Some("amber"));
+        assert_eq!(config.defaults.ips, Some(700));
+        assert_eq!(config.rom[SHA1].quirks.as_deref(), Some("schip"));
+        assert_eq!(config.rom[SHA1].ips, Some(1000));
+    }
+
+    #[test]
+    fn rom_sections_override_what_is_known_which_overrides_defaults() {
+        let rom = [0x12, 0x00];
+        let mut config =
+            Config::parse("palette = \"amber\"\nips = 700\nmute = true\nscale = 8\n").unwrap();
+        config.rom.insert(
+            rom_sha1(&rom),
+            Settings {
+                ips: Some(1000),
+                grid: Some(true),
+                ..Settings::default()
+            },
+        );
+        let known = Settings {
+            ips: Some(15),
+            palette: Some(String::from("green")),
+            ..Settings::default()
+        };
+
+        let settings = config.settings_for(&rom, &known);
+        assert_eq!(settings.ips, Some(1000));
+        assert_eq!(settings.palette.as_deref(), Some("green"));
+        assert_eq!(settings.mute, Some(true));
+        assert_eq!(settings.quirks, None);
+        assert_eq!(settings.scale, Some(8));
+        assert_eq!(settings.grid, Some(true));
+
+        let other = config.settings_for(&[0x00, 0xE0], &Settings::default());
+        assert_eq!(other.ips, Some(700));
+        assert_eq!(other.palette.as_deref(), Some("amber"));
+    }
+
+    #[test]
+    fn parse_rejects_unknown_settings() {
+        for text in [
+            "pallete = \"amber\"\n",
+            "[rom.0123456789abcdef0123456789abcdef01234567]\nspeed = 10\n",
+            "ips = \"fast\"\n",
+        ] {
+            assert!(
+                matches!(Config::parse(text), Err(ConfigError::ParseError(_))),
+                "{}",
+                text
+            );
+        }
+    }
+}
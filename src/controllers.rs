@@ -0,0 +1,107 @@
+use sdl2::controller::Button;
+
+// Open SDL game controllers. SDL reports controllers connected at start-up
+// as added devices too, so hot-plugging and start-up go through `add`.
+pub struct Controllers {
+    subsystem: sdl2::GameControllerSubsystem,
+    open: Vec<sdl2::controller::GameController>,
+}
+
+impl Controllers {
+    pub fn new(sdl_context: &sdl2::Sdl) -> Result<Controllers, String> {
+        Ok(Controllers {
+            subsystem: sdl_context.game_controller()?,
+            open: Vec::new(),
+        })
+    }
+
+    // Opens the controller at a joystick index and returns its name.
+    pub fn add(&mut self, joystick_index: u32) -> Result<String, String> {
+        let controller = self
+            .subsystem
+            .open(joystick_index)
+            .map_err(|error| error.to_string())?;
+        let name = controller.name();
+
+        self.open.push(controller);
+
+        Ok(name)
+    }
+
+    // Closes the controller with a joystick instance id and returns its name.
+    pub fn remove(&mut self, instance_id: u32) -> Option<String> {
+        let position = self
+            .open
+            .iter()
+            .position(|controller| controller.instance_id() == instance_id)?;
+
+        Some(self.open.remove(position).name())
+    }
+}
+
+// The name a button has in controller mappings, or None for buttons that
+// cannot be mapped, such as the guide button or stick clicks.
+pub fn button_name(button: Button) -> Option<&'static str> {
+    match button {
+        Button::DPadUp => Some("dpup"),
+        Button::DPadDown => Some("dpdown"),
+        Button::DPadLeft => Some("dpleft"),
+        Button::DPadRight => Some("dpright"),
+        Button::A => Some("a"),
+        Button::B => Some("b"),
+        Button::X => Some("x"),
+        Button::Y => Some("y"),
+        Button::LeftShoulder => Some("leftshoulder"),
+        Button::RightShoulder => Some("rightshoulder"),
+        Button::Back => Some("back"),
+        Button::Start => Some("start"),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use chip_8_emulator::gamepad::{GamepadMap, GAMEPAD_BUTTONS};
+
+    const BUTTONS: [Button; 15] = [
+        Button::DPadUp,
+        Button::DPadDown,
+        Button::DPadLeft,
+        Button::DPadRight,
+        Button::A,
+        Button::B,
+        Button::X,
+        Button::Y,
+        Button::LeftShoulder,
+        Button::RightShoulder,
+        Button::Back,
+        Button::Start,
+        Button::Guide,
+        Button::LeftStick,
+        Button::RightStick,
+    ];
+
+    #[test]
+    fn every_mappable_button_has_its_mapping_name() {
+        let names: Vec<&str> = BUTTONS
+            .iter()
+            .filter_map(|&button| button_name(button))
+            .collect();
+
+        assert_eq!(names, GAMEPAD_BUTTONS);
+    }
+
+    #[test]
+    fn button_names_match_sdl_and_parse_as_mappings() {
+        for button in BUTTONS {
+            if let Some(name) = button_name(button) {
+                assert_eq!(name, button.string());
+
+                let map = GamepadMap::parse(&format!("{}=a", name)).unwrap();
+                assert_eq!(map.lookup(name), Some(0xA));
+            }
+        }
+    }
+}
@@ -0,0 +1,520 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::Write;
+
+use crate::analysis::ControlFlowGraph;
+use crate::opcode::Opcode;
+use crate::platform::Platform;
+
+#[derive(Debug)]
+pub enum CoverageError {
+    IoError(std::io::Error),
+    ParseError(usize),
+}
+
+impl From<std::io::Error> for CoverageError {
+    fn from(error: std::io::Error) -> Self {
+        CoverageError::IoError(error)
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+pub struct BranchCoverage {
+    pub taken: u64,
+    pub not_taken: u64,
+}
+
+pub struct Coverage {
+    executed: BTreeMap<u16, u64>,
+    branches: BTreeMap<u16, BranchCoverage>,
+}
+
+pub struct SourceMap {
+    locations: BTreeMap<u16, (String, usize)>,
+}
+
+fn parse_address(text: &str) -> Option<u16> {
+    let digits = text.trim_start_matches("0x").trim_start_matches("0X");
+
+    u16::from_str_radix(digits, 16).ok()
+}
+
+fn in_rom(rom: &[u8], start: u16, address: u16) -> bool {
+    address
+        .checked_sub(start)
+        .is_some_and(|offset| (offset as usize) < rom.len())
+}
+
+// A line of the listing: an instruction, or up to two bytes of data.
+struct ListingRow<'a> {
+    address: u16,
+    bytes: &'a [u8],
+    opcode: Option<Opcode>,
+}
+
+impl Default for Coverage {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Coverage {
+    pub fn new() -> Coverage {
+        Coverage {
+            executed: BTreeMap::new(),
+            branches: BTreeMap::new(),
+        }
+    }
+
+    pub fn record(&mut self, address: u16, opcode: Opcode, next_program_counter: u16) {
+        *self.executed.entry(address).or_insert(0) += 1;
+
+        if opcode.is_skip() {
+            let branch = self.branches.entry(address).or_default();
+
+            if next_program_counter == address + 4 {
+                branch.taken += 1;
+            } else {
+                branch.not_taken += 1;
+            }
+        }
+    }
+
+    pub fn executed(&self) -> &BTreeMap<u16, u64> {
+        &self.executed
+    }
+
+    pub fn branches(&self) -> &BTreeMap<u16, BranchCoverage> {
+        &self.branches
+    }
+
+    pub fn merge(&mut self, other: &Coverage) {
+        for (&address, &count) in &other.executed {
+            *self.executed.entry(address).or_insert(0) += count;
+        }
+
+        for (&address, branch) in &other.branches {
+            let entry = self.branches.entry(address).or_default();
+            entry.taken += branch.taken;
+            entry.not_taken += branch.not_taken;
+        }
+    }
+
+    pub fn load(filename: &str) -> Result<Coverage, CoverageError> {
+        let text = std::fs::read_to_string(filename)?;
+        let mut coverage = Coverage::new();
+
+        for (i, line) in text.lines().enumerate() {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+
+            match fields.as_slice() {
+                [] => {}
+                [comment, ..] if comment.starts_with('#') => {}
+                ["E", address, count] => {
+                    let address = parse_address(address).ok_or(CoverageError::ParseError(i + 1))?;
+                    let count: u64 = count
+                        .parse()
+                        .map_err(|_| CoverageError::ParseError(i + 1))?;
+
+                    *coverage.executed.entry(address).or_insert(0) += count;
+                }
+                ["B", address, taken, not_taken] => {
+                    let address = parse_address(address).ok_or(CoverageError::ParseError(i + 1))?;
+                    let taken: u64 = taken
+                        .parse()
+                        .map_err(|_| CoverageError::ParseError(i + 1))?;
+                    let not_taken: u64 = not_taken
+                        .parse()
+                        .map_err(|_| CoverageError::ParseError(i + 1))?;
+
+                    let branch = coverage.branches.entry(address).or_default();
+                    branch.taken += taken;
+                    branch.not_taken += not_taken;
+                }
+                _ => return Err(CoverageError::ParseError(i + 1)),
+            }
+        }
+
+        Ok(coverage)
+    }
+
+    pub fn load_or_new(filename: &str) -> Result<Coverage, CoverageError> {
+        match Coverage::load(filename) {
+            Err(CoverageError::IoError(error)) if error.kind() == std::io::ErrorKind::NotFound => {
+                Ok(Coverage::new())
+            }
+            result => result,
+        }
+    }
+
+    pub fn save(&self, filename: &str) -> Result<(), CoverageError> {
+        let mut file = std::fs::File::create(filename)?;
+
+        writeln!(file, "# chip-8 coverage")?;
+        for (address, count) in &self.executed {
+            writeln!(file, "E 0x{:03X} {}", address, count)?;
+        }
+        for (address, branch) in &self.branches {
+            writeln!(
+                file,
+                "B 0x{:03X} {} {}",
+                address, branch.taken, branch.not_taken
+            )?;
+        }
+
+        Ok(())
+    }
+
+    // Instructions the analysis finds in the ROM or that were executed
+    // there. Code run from anywhere else has no place in the listing.
+    fn instruction_addresses(&self, rom: &[u8], platform: Platform, start: u16) -> BTreeSet<u16> {
+        let cfg = ControlFlowGraph::build_on(rom, platform, start);
+
+        cfg.instructions()
+            .keys()
+            .chain(self.executed.keys())
+            .filter(|&&address| in_rom(rom, start, address))
+            .cloned()
+            .collect()
+    }
+
+    // Instructions get a line each wherever they are, so code at odd
+    // addresses is listed too. The data around them is split into words.
+    fn listing_rows<'a>(
+        &self,
+        rom: &'a [u8],
+        platform: Platform,
+        start: u16,
+    ) -> Vec<ListingRow<'a>> {
+        let instructions = self.instruction_addresses(rom, platform, start);
+        let mut rows = Vec::new();
+        let mut offset = 0;
+
+        while offset < rom.len() {
+            let address = start + offset as u16;
+            let opcode = match rom.get(offset..offset + 2) {
+                Some(&[byte1, byte2]) if instructions.contains(&address) => {
+                    Opcode::decode_on((byte1 as u16) << 8 | byte2 as u16, platform)
+                }
+                _ => None,
+            };
+            let length = match opcode {
+                None if instructions.contains(&(address + 1)) => 1,
+                _ => 2,
+            };
+            let end = (offset + length).min(rom.len());
+
+            rows.push(ListingRow {
+                address,
+                bytes: &rom[offset..end],
+                opcode,
+            });
+            offset = end;
+        }
+
+        rows
+    }
+
+    fn branch_summary(&self, address: u16) -> String {
+        match self.branches.get(&address) {
+            Some(branch) => format!(
+                "  [skip taken {}, not taken {}]{}",
+                branch.taken,
+                branch.not_taken,
+                if branch.taken == 0 || branch.not_taken == 0 {
+                    " partial"
+                } else {
+                    ""
+                }
+            ),
+            None => String::new(),
+        }
+    }
+
+    // The ROM loaded at `start`, one instruction or data word per line. The
+    // lcov output refers to these lines.
+    pub fn write_listing<W: Write>(
+        &self,
+        rom: &[u8],
+        platform: Platform,
+        start: u16,
+        writer: &mut W,
+    ) -> std::io::Result<()> {
+        for row in self.listing_rows(rom, platform, start) {
+            let address = row.address;
+            let raw = row
+                .bytes
+                .iter()
+                .fold(0u16, |raw, &byte| (raw << 8) | byte as u16);
+
+            match row.opcode {
+                Some(opcode) => {
+                    let hits = match self.executed.get(&address) {
+                        Some(count) => count.to_string(),
+                        None => String::from("#####"),
+                    };
+
+                    let line = format!(
+                        "{:>10}  0x{:03X}  {:04X}  {:<20}{}",
+                        hits,
+                        address,
+                        raw,
+                        opcode.to_string(),
+                        self.branch_summary(address)
+                    );
+
+                    writeln!(writer, "{}", line.trim_end())?;
+                }
+                None => {
+                    let bytes: Vec<String> =
+                        row.bytes.iter().map(|b| format!("0x{:02X}", b)).collect();
+
+                    writeln!(
+                        writer,
+                        "{:>10}  0x{:03X}  {:<4}  DB {}",
+                        "-",
+                        address,
+                        "",
+                        bytes.join(", ")
+                    )?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // Lines are those of the source map's files, or without one those of the
+    // listing above, saved as `listing_name`.
+    pub fn write_lcov<W: Write>(
+        &self,
+        rom: &[u8],
+        platform: Platform,
+        start: u16,
+        listing_name: &str,
+        source_map: Option<&SourceMap>,
+        writer: &mut W,
+    ) -> std::io::Result<()> {
+        let mut files: BTreeMap<String, BTreeMap<usize, Vec<u16>>> = BTreeMap::new();
+        let listing_lines: BTreeMap<u16, usize> = self
+            .listing_rows(rom, platform, start)
+            .iter()
+            .enumerate()
+            .map(|(i, row)| (row.address, i + 1))
+            .collect();
+
+        for address in self.instruction_addresses(rom, platform, start) {
+            // An instruction overlapping the one before it shares its line.
+            let location = match source_map {
+                Some(map) => map.locations.get(&address).cloned(),
+                None => listing_lines
+                    .range(..=address)
+                    .next_back()
+                    .map(|(_, &line)| (listing_name.to_string(), line)),
+            };
+
+            if let Some((file, line)) = location {
+                files
+                    .entry(file)
+                    .or_default()
+                    .entry(line)
+                    .or_default()
+                    .push(address);
+            }
+        }
+
+        writeln!(writer, "TN:")?;
+
+        for (file, lines) in files {
+            writeln!(writer, "SF:{}", file)?;
+
+            let mut lines_hit = 0;
+            let mut branches_found = 0;
+            let mut branches_hit = 0;
+
+            for (&line, addresses) in &lines {
+                let count: u64 = addresses
+                    .iter()
+                    .map(|address| self.executed.get(address).cloned().unwrap_or(0))
+                    .sum();
+
+                for (block, address) in addresses.iter().enumerate() {
+                    let Some(branch) = self.branches.get(address) else {
+                        continue;
+                    };
+
+                    for (number, taken) in [branch.not_taken, branch.taken].iter().enumerate() {
+                        writeln!(writer, "BRDA:{},{},{},{}", line, block, number, taken)?;
+
+                        branches_found += 1;
+                        if *taken > 0 {
+                            branches_hit += 1;
+                        }
+                    }
+                }
+
+                writeln!(writer, "DA:{},{}", line, count)?;
+                if count > 0 {
+                    lines_hit += 1;
+                }
+            }
+
+            writeln!(writer, "BRF:{}", branches_found)?;
+            writeln!(writer, "BRH:{}", branches_hit)?;
+            writeln!(writer, "LF:{}", lines.len())?;
+            writeln!(writer, "LH:{}", lines_hit)?;
+            writeln!(writer, "end_of_record")?;
+        }
+
+        Ok(())
+    }
+}
+
+impl SourceMap {
+    // Each non-empty line maps an address to a source location:
+    //   0x200 game.8o:12
+    pub fn load(filename: &str) -> Result<SourceMap, CoverageError> {
+        let text = std::fs::read_to_string(filename)?;
+        let mut locations = BTreeMap::new();
+
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let (address, location) = line
+                .split_once(char::is_whitespace)
+                .ok_or(CoverageError::ParseError(i + 1))?;
+            let (file, source_line) = location
+                .trim()
+                .rsplit_once(':')
+                .ok_or(CoverageError::ParseError(i + 1))?;
+
+            let address = parse_address(address).ok_or(CoverageError::ParseError(i + 1))?;
+            let source_line = source_line
+                .parse()
+                .map_err(|_| CoverageError::ParseError(i + 1))?;
+
+            locations.insert(address, (file.to_string(), source_line));
+        }
+
+        Ok(SourceMap { locations })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // LD V0 5, SE V0 5, a skipped jump, then a jump to itself.
+    const ROM: [u8; 8] = [0x60, 0x05, 0x30, 0x05, 0x13, 0x00, 0x13, 0x06];
+
+    fn recorded(start: u16) -> Coverage {
+        let mut coverage = Coverage::new();
+
+        coverage.record(start, Opcode::decode(0x6005).unwrap(), start + 2);
+        coverage.record(start + 2, Opcode::decode(0x3005).unwrap(), start + 6);
+        coverage.record(start + 6, Opcode::decode(0x1306).unwrap(), start + 6);
+        coverage.record(start + 6, Opcode::decode(0x1306).unwrap(), start + 6);
+
+        coverage
+    }
+
+    #[test]
+    fn save_and_load_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("coverage.txt");
+        let filename = path.to_str().unwrap();
+        let coverage = recorded(0x200);
+
+        coverage.save(filename).unwrap();
+        let loaded = Coverage::load(filename).unwrap();
+
+        assert_eq!(loaded.executed(), coverage.executed());
+        assert_eq!(loaded.branches(), coverage.branches());
+        assert_eq!(loaded.executed()[&0x206], 2);
+        assert_eq!(
+            loaded.branches()[&0x202],
+            BranchCoverage {
+                taken: 1,
+                not_taken: 0
+            }
+        );
+    }
+
+    #[test]
+    fn load_reports_the_bad_line() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("bad-coverage.txt");
+        std::fs::write(&path, "# chip-8 coverage\nE 0x200 1\nE 0x202 many\n").unwrap();
+
+        let result = Coverage::load(path.to_str().unwrap());
+
+        assert!(matches!(result, Err(CoverageError::ParseError(3))));
+    }
+
+    #[test]
+    fn lcov_counts_lines_of_a_rom_loaded_at_0x300() {
+        let mut coverage = recorded(0x300);
+        // Executed outside the ROM, e.g. code copied below it.
+        coverage.record(0x100, Opcode::decode(0x6005).unwrap(), 0x102);
+
+        let mut lcov = Vec::new();
+        coverage
+            .write_lcov(&ROM, Platform::Chip8, 0x300, "rom.lst", None, &mut lcov)
+            .unwrap();
+        let lcov = String::from_utf8(lcov).unwrap();
+
+        assert!(lcov.contains("SF:rom.lst\n"));
+        assert!(lcov.contains("DA:1,1\n"));
+        assert!(lcov.contains("BRDA:2,0,0,0\nBRDA:2,0,1,1\nDA:2,1\n"));
+        assert!(lcov.contains("DA:3,0\n"));
+        assert!(lcov.contains("DA:4,2\n"));
+        assert!(lcov.contains("LF:4\nLH:3\n"));
+    }
+
+    #[test]
+    fn listing_marks_instructions_never_executed() {
+        let mut listing = Vec::new();
+        recorded(0x200)
+            .write_listing(&ROM, Platform::Chip8, 0x200, &mut listing)
+            .unwrap();
+        let listing = String::from_utf8(listing).unwrap();
+        let lines: Vec<&str> = listing.lines().collect();
+
+        assert_eq!(lines.len(), 4);
+        assert!(lines[1].contains("[skip taken 1, not taken 0] partial"));
+        assert!(lines[2].starts_with("     #####  0x204  1300"));
+    }
+
+    #[test]
+    fn listing_shows_code_at_odd_addresses() {
+        // A data byte, then LD V0 5 and a jump to itself at odd addresses,
+        // reached through JP V0 so only the executed addresses show them.
+        let rom = [0xB2, 0x03, 0xAA, 0x60, 0x05, 0x12, 0x05];
+        let mut coverage = Coverage::new();
+        coverage.record(0x200, Opcode::decode(0xB203).unwrap(), 0x203);
+        coverage.record(0x203, Opcode::decode(0x6005).unwrap(), 0x205);
+        coverage.record(0x205, Opcode::decode(0x1205).unwrap(), 0x205);
+
+        let mut listing = Vec::new();
+        coverage
+            .write_listing(&rom, Platform::Chip8, 0x200, &mut listing)
+            .unwrap();
+        let listing = String::from_utf8(listing).unwrap();
+        let lines: Vec<&str> = listing.lines().collect();
+
+        assert_eq!(lines.len(), 4);
+        assert!(lines[1].starts_with("         -  0x202        DB 0xAA"));
+        assert!(lines[2].starts_with("         1  0x203  6005"));
+        assert!(lines[3].starts_with("         1  0x205  1205"));
+
+        let mut lcov = Vec::new();
+        coverage
+            .write_lcov(&rom, Platform::Chip8, 0x200, "rom.lst", None, &mut lcov)
+            .unwrap();
+        let lcov = String::from_utf8(lcov).unwrap();
+
+        assert!(lcov.contains("DA:1,1\nDA:3,1\nDA:4,1\n"));
+    }
+}
@@ -1,15 +1,33 @@
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use crate::coverage::Coverage;
+use crate::input_queue::InputQueue;
 use crate::keyboard::{Keyboard, KeyboardError};
 use crate::memory::{Memory, MemoryError};
 use crate::opcode::Opcode;
+use crate::platform::Platform;
+use crate::profiler::Profiler;
+use crate::quirks::Quirks;
 use crate::registers::{RegisterError, Registers};
 use crate::screen::Screen;
-use crate::stack::Stack;
+use crate::stack::{Stack, StackError};
 use crate::timers::{DelayTimer, SoundTimer, Timer};
+use crate::timing::{self, Timing, VIP_CYCLES_PER_FRAME, VIP_INTERRUPT_CYCLES};
+use crate::trace::Tracer;
+use crate::vip::Vip;
 
 pub const PROGRAM_START: u16 = 0x200;
+pub const DEFAULT_INSTRUCTIONS_PER_FRAME: u32 = 10;
 
 pub struct Cpu {
     opcode: u16,
+    cycles: u64,
+    instructions_per_frame: u32,
+    timing: Timing,
+    // Instructions, or VIP machine cycles, spent so far in the current frame.
+    frame_cycles: u64,
+    frame_ended: bool,
     memory: Memory,
     registers: Registers,
     index: u16,
@@ -23,13 +41,55 @@ pub struct Cpu {
     pub screen: Screen,
 
     keyboard_state: Keyboard,
+    input_queue: InputQueue,
+    // The key FX0A saw go down and is waiting to be released.
+    key_wait: Option<u8>,
+
+    // CHIP-8X I/O port: the last byte FXF8 wrote and what FXFB reads.
+    port_output: u8,
+    port_input: u8,
+
+    // The loaded image, kept so a power cycle can restore it.
+    rom: Vec<u8>,
+    start_address: u16,
+
+    platform: Platform,
+    quirks: Quirks,
+    rng: StdRng,
+
+    profiler: Option<Profiler>,
+    coverage: Option<Coverage>,
+    tracer: Option<Tracer>,
+
+    // Low-level mode: the original interpreter runs on an emulated VIP and
+    // the high-level state above is unused.
+    vip: Option<Vip>,
+}
+
+// A snapshot of the machine state a program can observe. Emulator settings
+// such as quirks and the random number generator are not included.
+#[derive(Clone)]
+pub struct SaveState {
+    memory: Memory,
+    registers: Registers,
+    index: u16,
+    program_counter: u16,
+    delay_timer: u8,
+    sound_timer: u8,
+    stack: Stack,
+    screen: Screen,
+    key_wait: Option<u8>,
+    vip: Option<Vip>,
 }
 
 #[derive(Debug)]
+#[allow(clippy::enum_variant_names)]
 pub enum CpuError {
     MemoryError(MemoryError),
     KeyboardError(KeyboardError),
     RegisterError(RegisterError),
+    StackError(StackError),
+    IoError(std::io::Error),
 }
 
 impl From<MemoryError> for CpuError {
@@ -50,10 +110,33 @@ impl From<RegisterError> for CpuError {
     }
 }
 
+impl From<StackError> for CpuError {
+    fn from(error: StackError) -> Self {
+        CpuError::StackError(error)
+    }
+}
+
+impl From<std::io::Error> for CpuError {
+    fn from(error: std::io::Error) -> Self {
+        CpuError::IoError(error)
+    }
+}
+
+impl Default for Cpu {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Cpu {
     pub fn new() -> Cpu {
-        let chip = Cpu {
+        Cpu {
             opcode: 0,
+            cycles: 0,
+            instructions_per_frame: DEFAULT_INSTRUCTIONS_PER_FRAME,
+            timing: Timing::Fixed,
+            frame_cycles: 0,
+            frame_ended: false,
             memory: Memory::new(),
             registers: Registers::new(),
             index: 0,
@@ -63,29 +146,67 @@ impl Cpu {
             stack: Stack::new(),
             screen: Screen::new(),
             keyboard_state: Keyboard::new(),
-        };
-
-        chip
+            input_queue: InputQueue::new(),
+            key_wait: None,
+            port_output: 0,
+            port_input: 0,
+            rom: Vec::new(),
+            start_address: PROGRAM_START,
+            platform: Platform::default(),
+            quirks: Quirks::default(),
+            rng: StdRng::from_entropy(),
+            profiler: None,
+            coverage: None,
+            tracer: None,
+            vip: None,
+        }
     }
 
     fn increment_program_counter(&mut self, times: u16) {
         self.program_counter += 2 * times;
     }
 
-    fn fetch_opcode(&mut self) -> Result<u16, MemoryError> {
+    fn fetch_opcode(&self) -> Result<u16, MemoryError> {
         let byte1 = self.memory.get_byte(self.program_counter)?;
         let byte2 = self.memory.get_byte(self.program_counter + 1)?;
 
         Ok((byte1 as u16) << 8 | (byte2 as u16))
     }
 
+    pub fn next_opcode(&self) -> Option<Opcode> {
+        self.fetch_opcode()
+            .ok()
+            .and_then(|opcode| Opcode::decode_on(opcode, self.platform))
+    }
+
     pub fn emulate_cycle(&mut self) -> Result<(), CpuError> {
+        self.input_queue
+            .apply(self.cycles, &mut self.keyboard_state)?;
+
         self.opcode = self.fetch_opcode()?;
+        self.cycles += 1;
 
-        let decoded_opcode = Opcode::decode(self.opcode).unwrap_or_else(|| {
+        let decoded_opcode = Opcode::decode_on(self.opcode, self.platform).unwrap_or_else(|| {
             panic!("Unknown opcode: 0x{:X}", self.opcode);
         });
 
+        let address = self.program_counter;
+        let vx = match decoded_opcode {
+            Opcode::DrawSprite { register1, .. } => self.registers.read(register1)?,
+            Opcode::StoreBCD { register } => self.registers.read(register)?,
+            _ => 0,
+        };
+
+        if let Some(mut tracer) = self.tracer.take() {
+            let result = tracer.trace(self, decoded_opcode);
+            self.tracer = Some(tracer);
+            result?;
+        }
+
+        if let Some(profiler) = self.profiler.as_mut() {
+            profiler.record(address, decoded_opcode);
+        }
+
         match decoded_opcode {
             Opcode::ClearScreen => {
                 self.screen.clear();
@@ -93,7 +214,7 @@ impl Cpu {
                 self.increment_program_counter(1);
             }
             Opcode::ReturnFromSubroutine => {
-                self.program_counter = self.stack.pop();
+                self.program_counter = self.stack.pop()?;
 
                 self.increment_program_counter(1);
             }
@@ -101,7 +222,7 @@ impl Cpu {
                 self.program_counter = address;
             }
             Opcode::CallAddress { address } => {
-                self.stack.push(self.program_counter);
+                self.stack.push(self.program_counter)?;
 
                 self.program_counter = address;
             }
@@ -147,6 +268,7 @@ impl Cpu {
                 register2,
             } => {
                 self.registers.or(register1, register2)?;
+                self.reset_flag_for_logic()?;
 
                 self.increment_program_counter(1);
             }
@@ -155,6 +277,7 @@ impl Cpu {
                 register2,
             } => {
                 self.registers.and(register1, register2)?;
+                self.reset_flag_for_logic()?;
 
                 self.increment_program_counter(1);
             }
@@ -163,6 +286,7 @@ impl Cpu {
                 register2,
             } => {
                 self.registers.xor(register1, register2)?;
+                self.reset_flag_for_logic()?;
 
                 self.increment_program_counter(1);
             }
@@ -183,8 +307,15 @@ impl Cpu {
 
                 self.increment_program_counter(1);
             }
-            Opcode::ShiftRegisterRight { register } => {
-                self.registers.shift_right(register)?;
+            Opcode::ShiftRegisterRight {
+                register1,
+                register2,
+            } => {
+                if self.quirks.shift_uses_vy {
+                    self.registers.copy(register1, register2)?;
+                }
+
+                self.registers.shift_right(register1)?;
 
                 self.increment_program_counter(1);
             }
@@ -197,8 +328,15 @@ impl Cpu {
 
                 self.increment_program_counter(1);
             }
-            Opcode::ShiftRegisterLeft { register } => {
-                self.registers.shift_left(register)?;
+            Opcode::ShiftRegisterLeft {
+                register1,
+                register2,
+            } => {
+                if self.quirks.shift_uses_vy {
+                    self.registers.copy(register1, register2)?;
+                }
+
+                self.registers.shift_left(register1)?;
 
                 self.increment_program_counter(1);
             }
@@ -217,11 +355,17 @@ impl Cpu {
                 self.increment_program_counter(1);
             }
             Opcode::JumpToAddressPlusRegister0 { address } => {
-                self.program_counter = address + self.registers.read(0)? as u16;
+                let register = if self.quirks.jump_uses_vx {
+                    (address >> 8) as u8 & 0xF
+                } else {
+                    0
+                };
+
+                self.program_counter = address + self.registers.read(register)? as u16;
             }
             Opcode::SetRegisterToRandAndByte { register, byte } => {
                 self.registers
-                    .write(register, rand::random::<u8>() & byte)?;
+                    .write(register, self.rng.gen::<u8>() & byte)?;
 
                 self.increment_program_counter(1);
             }
@@ -235,17 +379,22 @@ impl Cpu {
 
                 let sprite = self.memory.get_bytes(self.index, size as u16)?;
 
-                self.registers
-                    .write(0xF, self.screen.draw(x as usize, y as usize, &sprite) as u8)?;
+                let collision =
+                    self.screen
+                        .draw(x as usize, y as usize, &sprite, self.quirks.clip_sprites);
+
+                self.registers.write(0xF, collision as u8)?;
 
                 self.increment_program_counter(1);
             }
-            Opcode::SkipIfKeyPressed { key } => {
+            Opcode::SkipIfKeyPressed { register } => {
+                let key = self.registers.read(register)?;
                 let is_pressed = self.keyboard_state.is_key_pressed(key)?;
 
                 self.increment_program_counter(1 + is_pressed as u16);
             }
-            Opcode::SkipIfKeyNotPressed { key } => {
+            Opcode::SkipIfKeyNotPressed { register } => {
+                let key = self.registers.read(register)?;
                 let is_pressed = self.keyboard_state.is_key_pressed(key)?;
 
                 self.increment_program_counter(1 + (!is_pressed) as u16);
@@ -256,14 +405,28 @@ impl Cpu {
 
                 self.increment_program_counter(1);
             }
-            Opcode::WaitForKeyPress { register } => {
-                let pressed_key = self.keyboard_state.get_pressed_key();
-
-                if let Some(key) = pressed_key {
-                    self.registers.write(register, key)?;
-                    self.increment_program_counter(1);
+            // Only a key going down while waiting counts, so a key held
+            // since before the wait cannot complete it straight away. The
+            // press is used up, so one press cannot answer two waits.
+            Opcode::WaitForKeyPress { register } => match self.key_wait {
+                None => {
+                    if let Some(key) = self.keyboard_state.take_just_pressed_key() {
+                        if self.quirks.wait_for_key_release {
+                            self.key_wait = Some(key);
+                        } else {
+                            self.registers.write(register, key)?;
+                            self.increment_program_counter(1);
+                        }
+                    }
                 }
-            }
+                Some(key) => {
+                    if !self.keyboard_state.is_key_pressed(key)? {
+                        self.key_wait = None;
+                        self.registers.write(register, key)?;
+                        self.increment_program_counter(1);
+                    }
+                }
+            },
             Opcode::SetDelayTimerToRegister { register } => {
                 let x = self.registers.read(register)?;
 
@@ -305,6 +468,10 @@ impl Cpu {
 
                 self.memory.set_bytes(self.index, bytes)?;
 
+                if self.quirks.load_store_increments_index {
+                    self.index += last_index as u16 + 1;
+                }
+
                 self.increment_program_counter(1);
             }
             Opcode::LoadRegisters {
@@ -316,17 +483,950 @@ impl Cpu {
 
                 self.registers.write_multiple(0, &bytes)?;
 
+                if self.quirks.load_store_increments_index {
+                    self.index += last_memory_address as u16 + 1;
+                }
+
+                self.increment_program_counter(1);
+            }
+            Opcode::CycleBackgroundColor => {
+                if let Some(colors) = self.screen.colors_mut() {
+                    colors.cycle_background();
+                }
+
+                self.increment_program_counter(1);
+            }
+            // The colour comes from the register after X.
+            Opcode::SetColorZones {
+                register1,
+                register2,
+            } => {
+                let x = self.registers.read(register1)?;
+                let y = self.registers.read(register2)?;
+                let color = self.registers.read((register1 + 1) & 0xF)?;
+
+                if let Some(colors) = self.screen.colors_mut() {
+                    colors.fill_zones(x, y, color);
+                }
+
+                self.increment_program_counter(1);
+            }
+            Opcode::SetColorRows {
+                register1,
+                register2,
+                height,
+            } => {
+                let x = self.registers.read(register1)?;
+                let y = self.registers.read(register2)?;
+                let color = self.registers.read((register1 + 1) & 0xF)?;
+
+                if let Some(colors) = self.screen.colors_mut() {
+                    colors.fill_rows(x, y, height, color);
+                }
+
+                self.increment_program_counter(1);
+            }
+            Opcode::SkipIfSecondPadKeyPressed { register } => {
+                let key = self.registers.read(register)?;
+                let is_pressed = self.keyboard_state.is_key_pressed_on(1, key)?;
+
+                self.increment_program_counter(1 + is_pressed as u16);
+            }
+            Opcode::SkipIfSecondPadKeyNotPressed { register } => {
+                let key = self.registers.read(register)?;
+                let is_pressed = self.keyboard_state.is_key_pressed_on(1, key)?;
+
+                self.increment_program_counter(1 + (!is_pressed) as u16);
+            }
+            Opcode::OutputToPort { register } => {
+                self.port_output = self.registers.read(register)?;
+
+                self.increment_program_counter(1);
+            }
+            // Nothing is attached to the input port to wait for, so the
+            // value set with set_port_input is read straight away.
+            Opcode::InputFromPort { register } => {
+                self.registers.write(register, self.port_input)?;
+
                 self.increment_program_counter(1);
             }
         }
 
+        if let Some(coverage) = self.coverage.as_mut() {
+            coverage.record(address, decoded_opcode, self.program_counter);
+        }
+
+        let cost = match self.timing {
+            Timing::Fixed => 1,
+            Timing::Vip => {
+                let skipped = self.program_counter == address.wrapping_add(4);
+                timing::vip_cycles(decoded_opcode, vx, skipped)
+            }
+        };
+        self.frame_cycles += cost;
+
+        let display_wait =
+            self.quirks.display_wait && matches!(decoded_opcode, Opcode::DrawSprite { .. });
+
+        // The VIP draws once the display interrupt is over, so the sprite
+        // takes up the start of the next frame.
+        if display_wait {
+            self.frame_cycles = match self.timing {
+                Timing::Fixed => 0,
+                Timing::Vip => cost,
+            };
+            self.end_frame();
+        } else if self.frame_cycles >= self.frame_budget() {
+            self.frame_cycles -= self.frame_budget();
+            self.end_frame();
+        }
+
+        Ok(())
+    }
+
+    // Instructions or machine cycles available to the program each frame.
+    fn frame_budget(&self) -> u64 {
+        match self.timing {
+            Timing::Fixed => self.instructions_per_frame as u64,
+            Timing::Vip => VIP_CYCLES_PER_FRAME - VIP_INTERRUPT_CYCLES,
+        }
+    }
+
+    // The display interrupt: timers run at 60 Hz, so they tick once per
+    // frame rather than on every cycle. Key edges last one frame.
+    fn end_frame(&mut self) {
+        let waiting_for_key = matches!(self.next_opcode(), Some(Opcode::WaitForKeyPress { .. }));
+
+        if !(waiting_for_key && self.quirks.key_wait_stops_timers) {
+            self.update_timers();
+        }
+
+        self.keyboard_state.clear_edges();
+        self.frame_ended = true;
+    }
+
+    fn reset_flag_for_logic(&mut self) -> Result<(), RegisterError> {
+        if self.quirks.logic_resets_vf {
+            self.registers.write(0xF, 0)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn emulate_frame(&mut self) -> Result<(), CpuError> {
+        self.frame_ended = false;
+
+        // The VIP keeps its own time, so queued input is applied a frame's
+        // worth of instructions at a time.
+        if let Some(vip) = &mut self.vip {
+            self.cycles += self.instructions_per_frame as u64;
+            self.input_queue
+                .apply(self.cycles, &mut self.keyboard_state)?;
+            vip.run_frame(&mut self.memory, &self.keyboard_state, &mut self.screen);
+            self.keyboard_state.clear_edges();
+            self.frame_ended = true;
+
+            return Ok(());
+        }
+
+        // An instruction that ran on past the end of the last frame, such as
+        // a VIP screen clear, can use up this one as well.
+        if self.frame_cycles >= self.frame_budget() {
+            self.frame_cycles -= self.frame_budget();
+            self.end_frame();
+        }
+
+        while !self.frame_ended {
+            self.emulate_cycle()?;
+        }
+
+        Ok(())
+    }
+
+    pub fn update_timers(&mut self) {
         self.delay_timer.update();
         self.sound_timer.update();
+    }
+
+    pub fn get_instructions_per_frame(&self) -> u32 {
+        self.instructions_per_frame
+    }
+
+    pub fn set_instructions_per_frame(&mut self, instructions_per_frame: u32) {
+        self.instructions_per_frame = instructions_per_frame.max(1);
+        self.frame_cycles = 0;
+    }
+
+    pub fn timing(&self) -> Timing {
+        self.timing
+    }
+
+    pub fn set_timing(&mut self, timing: Timing) {
+        self.timing = timing;
+        self.frame_cycles = 0;
+    }
+
+    pub fn load_rom(&mut self, filename: &str) -> Result<Vec<u8>, MemoryError> {
+        self.load_rom_at(filename, PROGRAM_START)
+    }
+
+    // Loads a ROM at a non-standard address and starts executing there.
+    pub fn load_rom_at(&mut self, filename: &str, address: u16) -> Result<Vec<u8>, MemoryError> {
+        let rom = self.memory.load_rom_from_file_at(filename, address)?;
+        self.rom = rom.clone();
+        self.start_address = address;
+        self.program_counter = self.entry_point();
+
+        Ok(rom)
+    }
+
+    pub fn load_rom_bytes_at(&mut self, rom: &[u8], address: u16) -> Result<(), MemoryError> {
+        self.memory.load_rom(rom, address)?;
+        self.rom = rom.to_vec();
+        self.start_address = address;
+        self.program_counter = self.entry_point();
+
+        Ok(())
+    }
+
+    // Fresh memory holding the fontset, or the VIP interpreter, and `rom`.
+    fn fresh_memory(&self, rom: &[u8], address: u16) -> Result<Memory, MemoryError> {
+        let mut memory = Memory::new();
+
+        if let Some(vip) = &self.vip {
+            vip.load(&mut memory)?;
+        }
+
+        memory.load_rom(rom, address)?;
+
+        Ok(memory)
+    }
+
+    // Power cycles into a different image.
+    pub fn reload_rom(&mut self, rom: &[u8], address: u16) -> Result<(), MemoryError> {
+        self.memory = self.fresh_memory(rom, address)?;
+        self.rom = rom.to_vec();
+        self.start_address = address;
+        self.reset();
+
+        Ok(())
+    }
+
+    // Restarts the program as the reset switch would: registers, stack,
+    // timers and screen are cleared but memory is left alone, so anything the
+    // program wrote there survives. Settings and held keys are kept.
+    pub fn reset(&mut self) {
+        self.registers = Registers::new();
+        self.index = 0;
+        self.program_counter = self.entry_point();
+        self.key_wait = None;
+        self.delay_timer = DelayTimer::new();
+        self.sound_timer = SoundTimer::new();
+        self.stack = Stack::new();
+        self.screen = self.fresh_screen();
+
+        if let Some(vip) = &mut self.vip {
+            vip.reset();
+        }
+    }
+
+    // A reset that also starts from fresh memory holding only the fontset and
+    // the loaded ROM. Nothing changes if the ROM no longer fits.
+    pub fn power_cycle(&mut self) -> Result<(), MemoryError> {
+        self.memory = self.fresh_memory(&self.rom, self.start_address)?;
+        self.reset();
 
         Ok(())
     }
 
-    pub fn load_rom(&mut self, filename: &str) -> Result<(), MemoryError> {
-        self.memory.load_rom_from_file(filename)
+    pub fn save_state(&self) -> SaveState {
+        SaveState {
+            memory: self.memory.clone(),
+            registers: self.registers.clone(),
+            index: self.index,
+            program_counter: self.program_counter,
+            delay_timer: self.delay_timer.get_value(),
+            sound_timer: self.sound_timer.get_value(),
+            stack: self.stack.clone(),
+            screen: self.screen.clone(),
+            key_wait: self.key_wait,
+            vip: self.vip.clone(),
+        }
+    }
+
+    pub fn load_state(&mut self, state: &SaveState) {
+        self.memory = state.memory.clone();
+        self.registers = state.registers.clone();
+        self.index = state.index;
+        self.program_counter = state.program_counter;
+        self.delay_timer.set_value(state.delay_timer);
+        self.sound_timer.set_value(state.sound_timer);
+        self.stack = state.stack.clone();
+        self.screen = state.screen.clone();
+        self.key_wait = state.key_wait;
+        self.vip = state.vip.clone();
+    }
+
+    // Restores `state` with a different image loaded over its memory, as if
+    // the program had been rewritten in place. The image becomes the one a
+    // power cycle restores. Nothing changes if it does not fit.
+    pub fn load_state_with_rom(
+        &mut self,
+        state: &SaveState,
+        rom: &[u8],
+        address: u16,
+    ) -> Result<(), MemoryError> {
+        let mut memory = state.memory.clone();
+        memory.load_rom(rom, address)?;
+
+        self.load_state(state);
+        self.memory = memory;
+        self.rom = rom.to_vec();
+        self.start_address = address;
+
+        Ok(())
+    }
+
+    pub fn start_address(&self) -> u16 {
+        self.start_address
+    }
+
+    pub fn rom(&self) -> &[u8] {
+        &self.rom
+    }
+
+    // Where execution starts: the load address, moved past any interpreter
+    // patch the platform's images begin with.
+    fn entry_point(&self) -> u16 {
+        self.start_address + (self.platform.program_start() - self.platform.load_address())
+    }
+
+    fn fresh_screen(&self) -> Screen {
+        let mut screen =
+            Screen::with_size(self.platform.screen_width(), self.platform.screen_height());
+
+        if self.platform.has_colors() {
+            screen.enable_colors();
+        }
+
+        screen
+    }
+
+    pub fn platform(&self) -> Platform {
+        self.platform
+    }
+
+    // Resizes the screen for `platform` and moves the program counter to its
+    // entry point, so it should be set before the program runs.
+    pub fn set_platform(&mut self, platform: Platform) {
+        self.platform = platform;
+        self.screen = self.fresh_screen();
+        self.program_counter = self.entry_point();
+    }
+
+    pub fn quirks(&self) -> Quirks {
+        self.quirks
+    }
+
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.quirks = quirks;
+    }
+
+    pub fn set_seed(&mut self, seed: u64) {
+        self.rng = StdRng::seed_from_u64(seed);
+    }
+
+    pub fn opcode(&self) -> u16 {
+        self.opcode
+    }
+
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    pub fn program_counter(&self) -> u16 {
+        self.program_counter
+    }
+
+    pub fn index(&self) -> u16 {
+        self.index
+    }
+
+    pub fn registers(&self) -> &Registers {
+        &self.registers
+    }
+
+    pub fn stack(&self) -> &Stack {
+        &self.stack
+    }
+
+    pub fn registers_mut(&mut self) -> &mut Registers {
+        &mut self.registers
+    }
+
+    pub fn set_index(&mut self, index: u16) {
+        self.index = index;
+    }
+
+    pub fn set_program_counter(&mut self, program_counter: u16) {
+        self.program_counter = program_counter;
+    }
+
+    pub fn memory(&self) -> &Memory {
+        &self.memory
+    }
+
+    pub fn memory_mut(&mut self) -> &mut Memory {
+        &mut self.memory
+    }
+
+    pub fn stack_mut(&mut self) -> &mut Stack {
+        &mut self.stack
+    }
+
+    pub fn keyboard(&self) -> &Keyboard {
+        &self.keyboard_state
+    }
+
+    pub fn keyboard_mut(&mut self) -> &mut Keyboard {
+        &mut self.keyboard_state
+    }
+
+    pub fn input_queue_mut(&mut self) -> &mut InputQueue {
+        &mut self.input_queue
+    }
+
+    // Queues a key change to take effect `delay` instructions from now.
+    pub fn queue_key(&mut self, key: u8, pressed: bool, delay: u64) -> Result<(), KeyboardError> {
+        self.queue_key_on(0, key, pressed, delay)
+    }
+
+    pub fn queue_key_on(
+        &mut self,
+        pad: u8,
+        key: u8,
+        pressed: bool,
+        delay: u64,
+    ) -> Result<(), KeyboardError> {
+        self.input_queue
+            .push_on(self.cycles + delay, pad, key, pressed)
+    }
+
+    pub fn delay_timer(&self) -> u8 {
+        self.delay_timer.get_value()
+    }
+
+    pub fn sound_timer(&self) -> u8 {
+        self.sound_timer.get_value()
+    }
+
+    // Whether the buzzer should sound: the sound timer is running, or in
+    // low-level mode the VIP's Q output is set.
+    pub fn sound_active(&self) -> bool {
+        match &self.vip {
+            Some(vip) => vip.sound(),
+            None => self.sound_timer() > 0,
+        }
+    }
+
+    // Switches to low-level mode. The interpreter replaces the fontset and
+    // the VIP starts from reset.
+    pub fn enable_vip(&mut self, mut vip: Vip) -> Result<(), MemoryError> {
+        vip.load(&mut self.memory)?;
+        vip.reset();
+        self.vip = Some(vip);
+
+        Ok(())
+    }
+
+    pub fn vip(&self) -> Option<&Vip> {
+        self.vip.as_ref()
+    }
+
+    pub fn port_output(&self) -> u8 {
+        self.port_output
+    }
+
+    pub fn set_port_input(&mut self, value: u8) {
+        self.port_input = value;
+    }
+
+    pub fn set_delay_timer(&mut self, value: u8) {
+        self.delay_timer.set_value(value);
+    }
+
+    pub fn set_sound_timer(&mut self, value: u8) {
+        self.sound_timer.set_value(value);
+    }
+
+    pub fn enable_profiler(&mut self) {
+        self.profiler = Some(Profiler::new(self.entry_point()));
+    }
+
+    pub fn profiler(&self) -> Option<&Profiler> {
+        self.profiler.as_ref()
+    }
+
+    pub fn enable_coverage(&mut self, coverage: Coverage) {
+        self.coverage = Some(coverage);
+    }
+
+    pub fn coverage(&self) -> Option<&Coverage> {
+        self.coverage.as_ref()
+    }
+
+    pub fn enable_tracer(&mut self, tracer: Tracer) {
+        self.tracer = Some(tracer);
+    }
+
+    pub fn tracer_mut(&mut self) -> Option<&mut Tracer> {
+        self.tracer.as_mut()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color_layer::CHIP8X_COLORS;
+
+    // Sets V0 and I, stores V0 at 0x300, starts the delay timer, draws the
+    // stored byte, then calls a subroutine that loops forever.
+    const PROGRAM: [u8; 14] = [
+        0x60, 0x05, 0xA3, 0x00, 0xF0, 0x55, 0xF0, 0x15, 0xD0, 0x01, 0x22, 0x0C, 0x12, 0x0C,
+    ];
+
+    fn run() -> Cpu {
+        let mut cpu = Cpu::new();
+        cpu.load_rom_bytes_at(&PROGRAM, PROGRAM_START).unwrap();
+
+        for _ in 0..6 {
+            cpu.emulate_cycle().unwrap();
+        }
+
+        cpu
+    }
+
+    #[test]
+    fn reset_clears_machine_state() {
+        let mut cpu = run();
+
+        assert_eq!(cpu.registers().read(0).unwrap(), 5);
+        assert_eq!(cpu.index(), 0x300);
+        assert_eq!(cpu.delay_timer(), 5);
+        assert_eq!(cpu.stack().get_pointer(), 1);
+        assert!(cpu.screen.get_pixel(10, 5));
+
+        cpu.reset();
+
+        assert_eq!(cpu.registers().read_all(), [0; 16]);
+        assert_eq!(cpu.index(), 0);
+        assert_eq!(cpu.program_counter(), PROGRAM_START);
+        assert_eq!(cpu.delay_timer(), 0);
+        assert_eq!(cpu.sound_timer(), 0);
+        assert_eq!(cpu.stack().get_pointer(), 0);
+        assert!(!cpu.screen.get_pixel(10, 5));
+    }
+
+    #[test]
+    fn reset_keeps_memory() {
+        let mut cpu = run();
+
+        cpu.reset();
+
+        assert_eq!(cpu.memory().get_byte(0x300).unwrap(), 5);
+        assert_eq!(
+            cpu.memory().get_bytes(PROGRAM_START, 14).unwrap(),
+            PROGRAM.to_vec()
+        );
+    }
+
+    #[test]
+    fn reset_returns_to_load_address() {
+        let mut cpu = Cpu::new();
+        cpu.load_rom_bytes_at(&PROGRAM, 0x400).unwrap();
+        cpu.emulate_cycle().unwrap();
+
+        cpu.reset();
+
+        assert_eq!(cpu.program_counter(), 0x400);
+    }
+
+    #[test]
+    fn power_cycle_restores_fontset_and_rom() {
+        let mut cpu = run();
+        let fontset = Memory::new().get_bytes(0, 80).unwrap();
+
+        cpu.memory_mut().set_bytes(0, &[0xFF; 80]).unwrap();
+        cpu.memory_mut().set_bytes(PROGRAM_START, &[0; 14]).unwrap();
+
+        cpu.power_cycle().unwrap();
+
+        assert_eq!(cpu.memory().get_bytes(0, 80).unwrap(), fontset);
+        assert_eq!(
+            cpu.memory().get_bytes(PROGRAM_START, 14).unwrap(),
+            PROGRAM.to_vec()
+        );
+        assert_eq!(cpu.memory().get_byte(0x300).unwrap(), 0);
+        assert_eq!(cpu.program_counter(), PROGRAM_START);
+        assert_eq!(cpu.registers().read(0).unwrap(), 0);
+    }
+
+    #[test]
+    fn power_cycle_runs_the_program_again() {
+        let mut cpu = run();
+
+        cpu.power_cycle().unwrap();
+        for _ in 0..6 {
+            cpu.emulate_cycle().unwrap();
+        }
+
+        assert_eq!(cpu.registers().read(0).unwrap(), 5);
+        assert_eq!(cpu.memory().get_byte(0x300).unwrap(), 5);
+        assert_eq!(cpu.program_counter(), 0x20C);
+    }
+
+    #[test]
+    fn rom_loaded_over_a_state_is_kept_for_power_cycles() {
+        let mut cpu = run();
+        let state = cpu.save_state();
+        let new_rom = [0x61, 0x09, 0x12, 0x02];
+
+        cpu.reset();
+        cpu.load_state_with_rom(&state, &new_rom, PROGRAM_START)
+            .unwrap();
+
+        assert_eq!(cpu.registers().read(0).unwrap(), 5);
+        assert_eq!(cpu.rom(), new_rom);
+
+        cpu.power_cycle().unwrap();
+
+        assert_eq!(
+            cpu.memory().get_bytes(PROGRAM_START, 4).unwrap(),
+            new_rom.to_vec()
+        );
+        assert_eq!(cpu.memory().get_byte(PROGRAM_START + 4).unwrap(), 0);
+    }
+
+    // F00A followed by a jump to itself.
+    const WAIT_FOR_KEY: [u8; 4] = [0xF0, 0x0A, 0x12, 0x02];
+
+    fn waiting_cpu(quirks: &str) -> Cpu {
+        let mut cpu = Cpu::new();
+        cpu.set_quirks(Quirks::parse(quirks).unwrap());
+        cpu.load_rom_bytes_at(&WAIT_FOR_KEY, PROGRAM_START).unwrap();
+
+        cpu
+    }
+
+    #[test]
+    fn key_wait_ignores_key_held_before_the_wait() {
+        let mut cpu = waiting_cpu("no-key-release");
+
+        // Held since an earlier frame, so the edge has already gone.
+        cpu.keyboard_mut().press_key(3).unwrap();
+        cpu.keyboard_mut().clear_edges();
+        cpu.emulate_frame().unwrap();
+
+        assert_eq!(cpu.program_counter(), PROGRAM_START);
+    }
+
+    #[test]
+    fn key_wait_completes_on_press_without_release_quirk() {
+        let mut cpu = waiting_cpu("no-key-release");
+
+        cpu.emulate_frame().unwrap();
+        cpu.keyboard_mut().press_key(7).unwrap();
+        cpu.emulate_cycle().unwrap();
+
+        assert_eq!(cpu.program_counter(), PROGRAM_START + 2);
+        assert_eq!(cpu.registers().read(0).unwrap(), 7);
+    }
+
+    #[test]
+    fn key_wait_completes_on_release_with_release_quirk() {
+        let mut cpu = waiting_cpu("key-release");
+
+        cpu.keyboard_mut().press_key(7).unwrap();
+        cpu.emulate_frame().unwrap();
+        assert_eq!(cpu.program_counter(), PROGRAM_START);
+
+        cpu.keyboard_mut().release_key(7).unwrap();
+        cpu.emulate_cycle().unwrap();
+
+        assert_eq!(cpu.program_counter(), PROGRAM_START + 2);
+        assert_eq!(cpu.registers().read(0).unwrap(), 7);
+    }
+
+    #[test]
+    fn key_wait_sees_taps_shorter_than_a_frame() {
+        let mut cpu = waiting_cpu("key-release");
+
+        cpu.keyboard_mut().press_key(9).unwrap();
+        cpu.keyboard_mut().release_key(9).unwrap();
+        cpu.emulate_cycle().unwrap();
+        cpu.emulate_cycle().unwrap();
+
+        assert_eq!(cpu.program_counter(), PROGRAM_START + 2);
+        assert_eq!(cpu.registers().read(0).unwrap(), 9);
+    }
+
+    #[test]
+    fn one_press_answers_only_one_key_wait() {
+        // F00A, F10A, then a jump to itself.
+        let program = [0xF0, 0x0A, 0xF1, 0x0A, 0x12, 0x04];
+
+        for quirks in ["key-release", "no-key-release"] {
+            let mut cpu = Cpu::new();
+            cpu.set_quirks(Quirks::parse(quirks).unwrap());
+            cpu.load_rom_bytes_at(&program, PROGRAM_START).unwrap();
+
+            cpu.keyboard_mut().press_key(5).unwrap();
+            cpu.emulate_cycle().unwrap();
+            cpu.keyboard_mut().release_key(5).unwrap();
+            for _ in 0..4 {
+                cpu.emulate_cycle().unwrap();
+            }
+
+            assert_eq!(cpu.program_counter(), PROGRAM_START + 2, "{}", quirks);
+            assert_eq!(cpu.registers().read(0).unwrap(), 5, "{}", quirks);
+            assert_eq!(cpu.registers().read(1).unwrap(), 0, "{}", quirks);
+        }
+    }
+
+    #[test]
+    fn key_wait_waits_for_release_by_default() {
+        let mut cpu = Cpu::new();
+        cpu.load_rom_bytes_at(&WAIT_FOR_KEY, PROGRAM_START).unwrap();
+
+        cpu.keyboard_mut().press_key(2).unwrap();
+        cpu.emulate_cycle().unwrap();
+        cpu.emulate_cycle().unwrap();
+        assert_eq!(cpu.program_counter(), PROGRAM_START);
+
+        cpu.keyboard_mut().release_key(2).unwrap();
+        cpu.emulate_cycle().unwrap();
+        assert_eq!(cpu.program_counter(), PROGRAM_START + 2);
+    }
+
+    #[test]
+    fn key_wait_stops_timers_with_quirk() {
+        let mut cpu = waiting_cpu("key-wait-timers");
+        cpu.set_delay_timer(10);
+        cpu.emulate_frame().unwrap();
+        assert_eq!(cpu.delay_timer(), 10);
+
+        let mut cpu = waiting_cpu("no-key-wait-timers");
+        cpu.set_delay_timer(10);
+        cpu.emulate_frame().unwrap();
+        assert_eq!(cpu.delay_timer(), 9);
+    }
+
+    // A jump to itself.
+    const IDLE: [u8; 2] = [0x12, 0x00];
+
+    #[test]
+    fn queued_keys_apply_at_their_cycle() {
+        let mut cpu = Cpu::new();
+        cpu.load_rom_bytes_at(&IDLE, PROGRAM_START).unwrap();
+        cpu.queue_key(4, true, 3).unwrap();
+
+        for _ in 0..3 {
+            cpu.emulate_cycle().unwrap();
+        }
+        assert!(!cpu.keyboard().is_key_pressed(4).unwrap());
+
+        cpu.emulate_cycle().unwrap();
+        assert!(cpu.keyboard().is_key_pressed(4).unwrap());
+    }
+
+    #[test]
+    fn minimum_hold_and_latency_delay_key_changes() {
+        let mut cpu = Cpu::new();
+        cpu.load_rom_bytes_at(&IDLE, PROGRAM_START).unwrap();
+        cpu.input_queue_mut().set_latency(2);
+        cpu.input_queue_mut().set_minimum_hold(5);
+        cpu.queue_key(4, true, 0).unwrap();
+        cpu.queue_key(4, false, 1).unwrap();
+
+        let held: Vec<bool> = (0..9)
+            .map(|_| {
+                cpu.emulate_cycle().unwrap();
+                cpu.keyboard().is_key_pressed(4).unwrap()
+            })
+            .collect();
+
+        assert_eq!(
+            held,
+            [false, false, true, true, true, true, true, false, false]
+        );
+    }
+
+    #[test]
+    fn vip_timing_fills_frame_with_machine_cycles() {
+        let mut cpu = Cpu::new();
+        cpu.set_timing(Timing::Vip);
+        cpu.load_rom_bytes_at(&IDLE, PROGRAM_START).unwrap();
+
+        cpu.emulate_frame().unwrap();
+
+        // Each jump costs 68 + 12 machine cycles.
+        let budget = VIP_CYCLES_PER_FRAME - VIP_INTERRUPT_CYCLES;
+        assert_eq!(cpu.cycles(), budget.div_ceil(80));
+    }
+
+    #[test]
+    fn vip_screen_clear_runs_into_the_next_frame() {
+        let mut cpu = Cpu::new();
+        cpu.set_timing(Timing::Vip);
+        cpu.load_rom_bytes_at(&[0x00, 0xE0, 0x12, 0x02], PROGRAM_START)
+            .unwrap();
+        cpu.set_delay_timer(10);
+
+        cpu.emulate_frame().unwrap();
+        assert_eq!(cpu.cycles(), 1);
+
+        // The clear's 3146 cycles leave 526 of the second frame for jumps.
+        cpu.emulate_frame().unwrap();
+        assert_eq!(cpu.cycles(), 1 + 526_u64.div_ceil(80));
+        assert_eq!(cpu.delay_timer(), 8);
+    }
+
+    #[test]
+    fn display_wait_ends_the_frame() {
+        let mut cpu = Cpu::new();
+        cpu.set_quirks(Quirks::parse("display-wait").unwrap());
+        cpu.load_rom_bytes_at(&[0xD0, 0x01, 0x12, 0x00], PROGRAM_START)
+            .unwrap();
+
+        cpu.emulate_frame().unwrap();
+        assert_eq!(cpu.cycles(), 1);
+
+        cpu.emulate_frame().unwrap();
+        assert_eq!(cpu.cycles(), 3);
+    }
+
+    #[test]
+    fn vip_display_wait_draws_in_the_next_frame() {
+        let mut cpu = Cpu::new();
+        cpu.set_timing(Timing::Vip);
+        cpu.set_quirks(Quirks::parse("display-wait").unwrap());
+        cpu.load_rom_bytes_at(&[0xD0, 0x0F, 0x12, 0x02], PROGRAM_START)
+            .unwrap();
+
+        cpu.emulate_frame().unwrap();
+        assert_eq!(cpu.cycles(), 1);
+
+        // The 15 row sprite's 604 cycles leave 1232 of the frame for jumps.
+        cpu.emulate_frame().unwrap();
+        let budget = VIP_CYCLES_PER_FRAME - VIP_INTERRUPT_CYCLES;
+        assert_eq!(cpu.cycles(), 1 + (budget - 604).div_ceil(80));
+    }
+
+    #[test]
+    fn vip_displays_memory_over_dma() {
+        // Moves the program counter off R0, which the display needs, turns
+        // the display on and sets Q, then spins. The interrupt routine at
+        // 0020 points R0 at 0F00 and returns.
+        let mut interpreter = [0; 0x28];
+        interpreter[..4].copy_from_slice(&[0xF8, 0x08, 0xA3, 0xD3]);
+        interpreter[0x08..0x13].copy_from_slice(&[
+            0xF8, 0x0E, 0xB2, 0xE2, 0xF8, 0x20, 0xA1, 0x69, 0x7B, 0x30, 0x11,
+        ]);
+        interpreter[0x20..].copy_from_slice(&[0xF8, 0x0F, 0xB0, 0xF8, 0x00, 0xA0, 0x78, 0x70]);
+
+        let mut cpu = Cpu::new();
+        cpu.load_rom_bytes_at(&IDLE, PROGRAM_START).unwrap();
+        cpu.enable_vip(Vip::new(&interpreter, None).unwrap())
+            .unwrap();
+        cpu.memory_mut().set_byte(0x0F00, 0xF0).unwrap();
+
+        cpu.emulate_frame().unwrap();
+
+        assert!(cpu.sound_active());
+        assert!((0..4).all(|x| cpu.screen.get_pixel(x, 0)));
+        assert!(!cpu.screen.get_pixel(4, 0));
+        assert_eq!(cpu.memory().get_byte(PROGRAM_START).unwrap(), 0x12);
+    }
+
+    #[test]
+    fn hires_platform_starts_past_the_patch_on_a_tall_screen() {
+        // The image's jump to its interpreter patch is never run. At 0x2C0 it
+        // draws a font row at (0, 40), then clears the screen with 0230.
+        let mut rom = vec![0; 0xC8];
+        rom[..2].copy_from_slice(&[0x12, 0x60]);
+        rom[0xC0..].copy_from_slice(&[0x61, 0x28, 0xD0, 0x11, 0x02, 0x30, 0x12, 0xC6]);
+
+        let mut cpu = Cpu::new();
+        cpu.load_rom_bytes_at(&rom, PROGRAM_START).unwrap();
+        cpu.set_platform(Platform::HiRes);
+
+        assert_eq!(cpu.program_counter(), 0x2C0);
+        assert_eq!(cpu.screen.get_height(), 64);
+
+        for _ in 0..2 {
+            cpu.emulate_cycle().unwrap();
+        }
+        assert!(cpu.screen.get_pixel(0, 40));
+
+        cpu.emulate_cycle().unwrap();
+        assert!(!cpu.screen.get_pixel(0, 40));
+
+        cpu.reset();
+        assert_eq!(cpu.program_counter(), 0x2C0);
+        assert_eq!(cpu.screen.get_height(), 64);
+    }
+
+    #[test]
+    fn chip8x_colours_second_keypad_and_port() {
+        // Cycles the background, colours zones 1-2 of the top zone row
+        // yellow, skips on second pad key V4 = 7, then writes V4 to the port
+        // and reads it back into V5.
+        let program = [
+            0x02, 0xA0, 0x61, 0x11, 0x62, 0x05, 0x63, 0x00, 0x64, 0x07, 0xB1, 0x30, 0xE4, 0xF2,
+            0x00, 0x00, 0xF4, 0xF8, 0xF5, 0xFB, 0x13, 0x14,
+        ];
+
+        let mut cpu = Cpu::new();
+        cpu.set_platform(Platform::Chip8X);
+        cpu.load_rom_bytes_at(&program, Platform::Chip8X.load_address())
+            .unwrap();
+        cpu.keyboard_mut().set_key_on(1, 7, true).unwrap();
+        cpu.set_port_input(0x42);
+
+        for _ in 0..9 {
+            cpu.emulate_cycle().unwrap();
+        }
+
+        let colors = cpu.screen.colors().unwrap();
+        assert_eq!(colors.background(), CHIP8X_COLORS[0]);
+        assert_eq!(colors.foreground(8, 3), CHIP8X_COLORS[5]);
+        assert_eq!(colors.foreground(23, 0), CHIP8X_COLORS[5]);
+        assert_eq!(colors.foreground(24, 0), CHIP8X_COLORS[1]);
+        assert_eq!(colors.foreground(8, 4), CHIP8X_COLORS[1]);
+        assert_eq!(cpu.port_output(), 7);
+        assert_eq!(cpu.registers().read(5).unwrap(), 0x42);
+        assert_eq!(cpu.program_counter(), 0x314);
+    }
+
+    #[test]
+    fn key_skips_read_the_key_from_vx_on_both_pads() {
+        // V3 = A, then SKP V3 and SKNP V3 on the second pad, which skip, and
+        // SKP V3 on the second pad and SKNP V3, which don't.
+        let program = [
+            0x63, 0x0A, 0xE3, 0x9E, 0x00, 0x00, 0xE3, 0xF2, 0xE3, 0xF5, 0x00, 0x00, 0xE3, 0xA1,
+        ];
+
+        let mut cpu = Cpu::new();
+        cpu.set_platform(Platform::Chip8X);
+        cpu.load_rom_bytes_at(&program, Platform::Chip8X.load_address())
+            .unwrap();
+        cpu.keyboard_mut().set_key_on(0, 0xA, true).unwrap();
+        cpu.keyboard_mut().set_key_on(1, 0x3, true).unwrap();
+
+        for _ in 0..5 {
+            cpu.emulate_cycle().unwrap();
+        }
+
+        assert_eq!(cpu.program_counter(), 0x30E);
     }
 }
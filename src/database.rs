@@ -0,0 +1,407 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use serde::Deserialize;
+
+use crate::config::{rom_sha1, Settings};
+use crate::gamepad::GamepadMap;
+use crate::quirks::Quirks;
+
+const PROGRAMS: &str = include_str!("../data/chip-8-database/programs.json");
+const SHA1_HASHES: &str = include_str!("../data/chip-8-database/sha1-hashes.json");
+const PLATFORMS: &str = include_str!("../data/chip-8-database/platforms.json");
+
+// Host keys the database's named game inputs are bound to.
+const KEY_BINDINGS: [(&str, &str); 6] = [
+    ("up", "Up"),
+    ("down", "Down"),
+    ("left", "Left"),
+    ("right", "Right"),
+    ("a", "Space"),
+    ("b", "Left Shift"),
+];
+
+// Controller buttons for the same named inputs.
+const GAMEPAD_BINDINGS: [(&str, &str); 6] = [
+    ("up", "dpup"),
+    ("down", "dpdown"),
+    ("left", "dpleft"),
+    ("right", "dpright"),
+    ("a", "a"),
+    ("b", "b"),
+];
+
+#[derive(Debug)]
+pub enum DatabaseError {
+    IoError(std::io::Error),
+    ParseError(serde_json::Error),
+}
+
+impl From<std::io::Error> for DatabaseError {
+    fn from(error: std::io::Error) -> Self {
+        DatabaseError::IoError(error)
+    }
+}
+
+impl From<serde_json::Error> for DatabaseError {
+    fn from(error: serde_json::Error) -> Self {
+        DatabaseError::ParseError(error)
+    }
+}
+
+// The structures below follow the JSON files of the community CHIP-8
+// database (https://github.com/chip-8/chip-8-database). Fields this emulator
+// has no use for are ignored.
+#[derive(Deserialize, Clone, PartialEq, Eq, Debug, Default)]
+#[serde(default, rename_all = "camelCase")]
+pub struct PlatformQuirks {
+    pub shift: Option<bool>,
+    pub memory_increment_by_x: Option<bool>,
+    pub memory_leave_i_unchanged: Option<bool>,
+    pub wrap: Option<bool>,
+    pub jump: Option<bool>,
+    pub vblank: Option<bool>,
+    pub logic: Option<bool>,
+}
+
+#[derive(Deserialize, Clone, PartialEq, Eq, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Platform {
+    pub id: String,
+    pub name: String,
+    #[serde(default)]
+    pub display_resolutions: Vec<String>,
+    pub default_tickrate: u32,
+    #[serde(default)]
+    pub quirks: PlatformQuirks,
+}
+
+#[derive(Deserialize, Clone, PartialEq, Eq, Debug, Default)]
+#[serde(default)]
+pub struct Colors {
+    pub pixels: Vec<String>,
+    pub buzzer: Option<String>,
+    pub silence: Option<String>,
+}
+
+#[derive(Deserialize, Clone, PartialEq, Eq, Debug, Default)]
+#[serde(default, rename_all = "camelCase")]
+pub struct Rom {
+    pub file: Option<String>,
+    pub platforms: Vec<String>,
+    pub quirky_platforms: BTreeMap<String, PlatformQuirks>,
+    pub tickrate: Option<u32>,
+    pub start_address: Option<u16>,
+    pub keys: BTreeMap<String, u8>,
+    pub colors: Option<Colors>,
+}
+
+#[derive(Deserialize, Clone, PartialEq, Eq, Debug, Default)]
+#[serde(default)]
+pub struct Program {
+    pub title: String,
+    pub description: Option<String>,
+    pub authors: Vec<String>,
+    pub roms: BTreeMap<String, Rom>,
+}
+
+// Everything the database knows about one ROM image.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Entry<'a> {
+    pub program: &'a Program,
+    pub rom: &'a Rom,
+    pub platform: Option<&'a Platform>,
+}
+
+pub struct Database {
+    programs: Vec<Program>,
+    hashes: BTreeMap<String, usize>,
+    platforms: Vec<Platform>,
+}
+
+impl PlatformQuirks {
+    // Quirks missing from `self` are taken from `base`.
+    fn or(&self, base: &PlatformQuirks) -> PlatformQuirks {
+        PlatformQuirks {
+            shift: self.shift.or(base.shift),
+            memory_increment_by_x: self.memory_increment_by_x.or(base.memory_increment_by_x),
+            memory_leave_i_unchanged: self
+                .memory_leave_i_unchanged
+                .or(base.memory_leave_i_unchanged),
+            wrap: self.wrap.or(base.wrap),
+            jump: self.jump.or(base.jump),
+            vblank: self.vblank.or(base.vblank),
+            logic: self.logic.or(base.logic),
+        }
+    }
+
+    // The database's "shift" and "wrap" describe the opposite behaviour to
+    // ours. Incrementing I by X alone (CHIP-48) is treated as incrementing by
+    // X + 1. The database does not describe FX0A, so the original wait for
+    // the key to be released is used.
+    pub fn to_quirks(&self) -> Quirks {
+        Quirks {
+            shift_uses_vy: !self.shift.unwrap_or(false),
+            load_store_increments_index: !self.memory_leave_i_unchanged.unwrap_or(false),
+            jump_uses_vx: self.jump.unwrap_or(false),
+            logic_resets_vf: self.logic.unwrap_or(false),
+            clip_sprites: !self.wrap.unwrap_or(false),
+            wait_for_key_release: true,
+            key_wait_stops_timers: false,
+            display_wait: self.vblank.unwrap_or(false),
+        }
+    }
+}
+
+impl Database {
+    pub fn builtin() -> Database {
+        Database::parse(PROGRAMS, SHA1_HASHES, PLATFORMS).expect("Built-in ROM database is invalid")
+    }
+
+    // Reads programs.json, sha1-hashes.json and platforms.json from a
+    // checkout of the community database.
+    pub fn load(directory: &Path) -> Result<Database, DatabaseError> {
+        let read = |name: &str| std::fs::read_to_string(directory.join(name));
+
+        Database::parse(
+            &read("programs.json")?,
+            &read("sha1-hashes.json")?,
+            &read("platforms.json")?,
+        )
+    }
+
+    pub fn parse(programs: &str, hashes: &str, platforms: &str) -> Result<Database, DatabaseError> {
+        let hashes: BTreeMap<String, usize> = serde_json::from_str(hashes)?;
+
+        Ok(Database {
+            programs: serde_json::from_str(programs)?,
+            hashes: hashes
+                .into_iter()
+                .map(|(sha1, index)| (sha1.to_ascii_lowercase(), index))
+                .collect(),
+            platforms: serde_json::from_str(platforms)?,
+        })
+    }
+
+    pub fn programs(&self) -> &[Program] {
+        &self.programs
+    }
+
+    pub fn platform(&self, id: &str) -> Option<&Platform> {
+        self.platforms.iter().find(|platform| platform.id == id)
+    }
+
+    pub fn lookup(&self, rom: &[u8]) -> Option<Entry<'_>> {
+        let sha1 = rom_sha1(rom);
+        let program = self.programs.get(*self.hashes.get(&sha1)?)?;
+        let rom = program
+            .roms
+            .iter()
+            .find(|(hash, _)| hash.eq_ignore_ascii_case(&sha1))
+            .map(|(_, rom)| rom)?;
+
+        Some(Entry {
+            program,
+            rom,
+            platform: rom.platforms.first().and_then(|id| self.platform(id)),
+        })
+    }
+}
+
+impl Entry<'_> {
+    pub fn quirks(&self) -> Quirks {
+        let platform_quirks = self
+            .platform
+            .map(|platform| platform.quirks.clone())
+            .unwrap_or_default();
+
+        let quirks = match self
+            .platform
+            .and_then(|platform| self.rom.quirky_platforms.get(&platform.id))
+        {
+            Some(overrides) => overrides.or(&platform_quirks),
+            None => platform_quirks,
+        };
+
+        quirks.to_quirks()
+    }
+
+    pub fn tickrate(&self) -> Option<u32> {
+        self.rom
+            .tickrate
+            .or(self.platform.map(|platform| platform.default_tickrate))
+    }
+
+    // Host key names for the named inputs the ROM uses, e.g. ("Up", 5).
+    pub fn key_bindings(&self) -> Vec<(String, u8)> {
+        KEY_BINDINGS
+            .iter()
+            .filter_map(|&(input, host)| {
+                self.rom.keys.get(input).map(|&key| (host.to_string(), key))
+            })
+            .collect()
+    }
+
+    // The default controller mapping with the D-pad and A/B moved onto the
+    // ROM's named inputs.
+    pub fn gamepad_map(&self) -> GamepadMap {
+        let mut map = GamepadMap::default();
+
+        for &(input, button) in GAMEPAD_BINDINGS.iter() {
+            if let Some(&key) = self.rom.keys.get(input) {
+                map.bind(button, key);
+            }
+        }
+
+        map
+    }
+
+    // The database's recommendations in the same form as a config file
+    // section, so they can be layered under the user's own settings.
+    pub fn settings(&self) -> Settings {
+        let palette = self
+            .rom
+            .colors
+            .as_ref()
+            .filter(|colors| colors.pixels.len() >= 2)
+            .map(|colors| colors.pixels.join(","));
+
+        Settings {
+            palette,
+            ips: self.tickrate().map(|tickrate| tickrate * 60),
+            quirks: self.platform.map(|_| self.quirks().to_string()),
+            platform: self
+                .platform
+                .filter(|platform| platform.id == "chip8x")
+                .map(|_| "chip8x".to_string()),
+            start_address: self
+                .rom
+                .start_address
+                .map(|address| format!("0x{:03X}", address)),
+            gamepad: Some(self.gamepad_map().to_string()).filter(|_| !self.rom.keys.is_empty()),
+            ..Settings::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ROM: [u8; 4] = [0x00, 0xE0, 0x12, 0x02];
+    const PLATFORMS: &str = r#"[
+        {"id": "originalChip8", "name": "Cosmac VIP CHIP-8", "defaultTickrate": 15,
+         "quirks": {"shift": false, "memoryIncrementByX": false, "memoryLeaveIUnchanged": false,
+                    "wrap": false, "jump": false, "vblank": true, "logic": true}},
+        {"id": "chip48", "name": "CHIP-48", "defaultTickrate": 30,
+         "quirks": {"shift": true, "memoryIncrementByX": true, "memoryLeaveIUnchanged": false,
+                    "wrap": false, "jump": true, "vblank": false, "logic": false}},
+        {"id": "chip8x", "name": "CHIP-8X", "defaultTickrate": 15}
+    ]"#;
+
+    // One program with this ROM on `platforms`, and `rom` giving any other
+    // fields of its entry.
+    fn database(platforms: &str, rom: &str) -> Database {
+        let sha1 = rom_sha1(&ROM).to_ascii_uppercase();
+        let programs = format!(
+            r#"[{{"title": "Other", "roms": {{}}}},
+                {{"title": "Test", "authors": ["Someone"],
+                  "roms": {{"{}": {{"file": "test.ch8", "platforms": {}{}}}}}}}]"#,
+            sha1, platforms, rom
+        );
+
+        Database::parse(&programs, &format!(r#"{{"{}": 1}}"#, sha1), PLATFORMS).unwrap()
+    }
+
+    #[test]
+    fn roms_are_found_by_sha1() {
+        let database = database(r#"["chip48"]"#, "");
+
+        let entry = database.lookup(&ROM).unwrap();
+        assert_eq!(entry.program.title, "Test");
+        assert_eq!(entry.rom.file.as_deref(), Some("test.ch8"));
+        assert_eq!(entry.platform.unwrap().id, "chip48");
+        assert!(database.lookup(&[0x12, 0x00]).is_none());
+    }
+
+    #[test]
+    fn to_quirks_inverts_shift_and_wrap() {
+        let vip = Database::parse("[]", "{}", PLATFORMS).unwrap();
+        let vip = vip.platform("originalChip8").unwrap().quirks.to_quirks();
+        assert!(vip.shift_uses_vy);
+        assert!(vip.load_store_increments_index);
+        assert!(!vip.jump_uses_vx);
+        assert!(vip.logic_resets_vf);
+        assert!(vip.clip_sprites);
+        assert!(vip.wait_for_key_release);
+        assert!(vip.display_wait);
+
+        let quirks = PlatformQuirks {
+            shift: Some(true),
+            memory_leave_i_unchanged: Some(true),
+            wrap: Some(true),
+            jump: Some(true),
+            ..PlatformQuirks::default()
+        }
+        .to_quirks();
+        assert!(!quirks.shift_uses_vy);
+        assert!(!quirks.load_store_increments_index);
+        assert!(quirks.jump_uses_vx);
+        assert!(!quirks.logic_resets_vf);
+        assert!(!quirks.clip_sprites);
+        assert!(!quirks.display_wait);
+    }
+
+    #[test]
+    fn the_first_platform_is_used_with_the_rom_overrides() {
+        let database = database(
+            r#"["originalChip8", "chip48"]"#,
+            r#", "quirkyPlatforms": {"originalChip8": {"vblank": false}}"#,
+        );
+
+        let entry = database.lookup(&ROM).unwrap();
+        assert_eq!(entry.platform.unwrap().id, "originalChip8");
+        assert!(!entry.quirks().display_wait);
+        assert!(entry.quirks().logic_resets_vf);
+        assert_eq!(entry.tickrate(), Some(15));
+        assert_eq!(entry.settings().platform, None);
+    }
+
+    #[test]
+    fn settings_carry_the_rom_recommendations() {
+        let database = database(
+            r#"["chip8x"]"#,
+            r##", "tickrate": 20, "startAddress": 768, "keys": {"up": 5, "a": 6},
+               "colors": {"pixels": ["#000000", "#ffffff"]}"##,
+        );
+
+        let settings = database.lookup(&ROM).unwrap().settings();
+        assert_eq!(settings.platform.as_deref(), Some("chip8x"));
+        assert_eq!(settings.ips, Some(1200));
+        assert_eq!(settings.start_address.as_deref(), Some("0x300"));
+        assert_eq!(settings.palette.as_deref(), Some("#000000,#ffffff"));
+        assert!(settings.quirks.is_some());
+
+        let entry = database.lookup(&ROM).unwrap();
+        assert_eq!(
+            entry.key_bindings(),
+            [("Up".to_string(), 5), ("Space".to_string(), 6)]
+        );
+        assert_eq!(entry.gamepad_map().lookup("dpup"), Some(5));
+    }
+
+    #[test]
+    fn a_rom_without_a_known_platform_has_no_quirks_setting() {
+        let database = database(r#"["unknownPlatform"]"#, "");
+
+        let entry = database.lookup(&ROM).unwrap();
+        assert!(entry.platform.is_none());
+        assert_eq!(entry.settings().quirks, None);
+        assert_eq!(entry.tickrate(), None);
+    }
+
+    #[test]
+    fn the_builtin_database_parses() {
+        assert!(Database::builtin().platform("chip8x").is_some());
+    }
+}
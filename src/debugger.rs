@@ -0,0 +1,566 @@
+use std::collections::BTreeSet;
+use std::time::Duration;
+
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, Paragraph};
+use ratatui::{DefaultTerminal, Frame};
+
+use crate::cpu::Cpu;
+use crate::keymap::Keymap;
+use crate::memory::MEMORY_SIZE;
+use crate::opcode::Opcode;
+use crate::registers::NUM_REGISTERS;
+
+const FRAME_DURATION: Duration = Duration::from_millis(16);
+const CYCLES_PER_FRAME: usize = 1000;
+const MEMORY_ROWS: u16 = 8;
+// Terminals only report key presses, so a key sent to the keypad is released
+// again after about 100 ms of running.
+const KEY_HOLD_CYCLES: u64 = 6 * CYCLES_PER_FRAME as u64;
+const KEYPAD_LAYOUT: [[u8; 4]; 4] = [
+    [0x1, 0x2, 0x3, 0xC],
+    [0x4, 0x5, 0x6, 0xD],
+    [0x7, 0x8, 0x9, 0xE],
+    [0xA, 0x0, 0xB, 0xF],
+];
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+enum StopCondition {
+    Breakpoint,
+    Address {
+        address: u16,
+        stack_pointer: Option<u16>,
+    },
+    StackBelow(u16),
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+enum RunMode {
+    Paused,
+    Running(StopCondition),
+}
+
+pub struct Debugger<'a> {
+    cpu: &'a mut Cpu,
+    previous_registers: [u8; NUM_REGISTERS],
+    breakpoints: BTreeSet<u16>,
+    cursor: u16,
+    mode: RunMode,
+    message: String,
+    keymap: Keymap,
+    keypad_input: bool,
+}
+
+pub fn run(cpu: &mut Cpu, keymap: &Keymap) -> std::io::Result<()> {
+    let mut terminal = ratatui::init();
+    let result = Debugger::new(cpu, keymap).run(&mut terminal);
+    ratatui::restore();
+
+    result
+}
+
+impl<'a> Debugger<'a> {
+    pub fn new(cpu: &'a mut Cpu, keymap: &Keymap) -> Debugger<'a> {
+        let previous_registers = cpu.registers().read_all();
+        let cursor = cpu.program_counter();
+
+        Debugger {
+            cpu,
+            previous_registers,
+            breakpoints: BTreeSet::new(),
+            cursor,
+            mode: RunMode::Paused,
+            message: String::from("Paused"),
+            keymap: keymap.clone(),
+            keypad_input: false,
+        }
+    }
+
+    pub fn run(&mut self, terminal: &mut DefaultTerminal) -> std::io::Result<()> {
+        loop {
+            terminal.draw(|frame| self.draw(frame))?;
+
+            if event::poll(FRAME_DURATION)? {
+                if let Event::Key(key) = event::read()? {
+                    if key.kind == KeyEventKind::Press && !self.handle_key(key.code) {
+                        return Ok(());
+                    }
+                }
+            }
+
+            if let RunMode::Running(condition) = self.mode {
+                self.run_frame(condition);
+            }
+        }
+    }
+
+    fn handle_key(&mut self, code: KeyCode) -> bool {
+        if self.keypad_input {
+            self.handle_keypad_key(code);
+            return true;
+        }
+
+        match code {
+            KeyCode::Char('q') | KeyCode::Esc => return false,
+            KeyCode::Char('s') => {
+                self.mode = RunMode::Paused;
+                self.previous_registers = self.cpu.registers().read_all();
+                if self.execute() {
+                    self.message = String::from("Stepped");
+                }
+                self.cursor = self.cpu.program_counter();
+            }
+            KeyCode::Char('n') => match self.cpu.next_opcode() {
+                Some(Opcode::CallAddress { .. }) => self.start(StopCondition::Address {
+                    address: self.cpu.program_counter() + 2,
+                    stack_pointer: Some(self.cpu.stack().get_pointer()),
+                }),
+                _ => return self.handle_key(KeyCode::Char('s')),
+            },
+            KeyCode::Char('o') => {
+                if self.cpu.stack().get_pointer() == 0 {
+                    self.message = String::from("Not inside a subroutine");
+                } else {
+                    self.start(StopCondition::StackBelow(self.cpu.stack().get_pointer()));
+                }
+            }
+            KeyCode::Char('c') => self.start(StopCondition::Address {
+                address: self.cursor,
+                stack_pointer: None,
+            }),
+            KeyCode::Char('r') => self.start(StopCondition::Breakpoint),
+            KeyCode::Char('p') | KeyCode::Char(' ') => {
+                self.mode = RunMode::Paused;
+                self.message = String::from("Paused");
+                self.cursor = self.cpu.program_counter();
+            }
+            KeyCode::Char('b') => {
+                if self.breakpoints.contains(&self.cursor) {
+                    self.breakpoints.remove(&self.cursor);
+                } else {
+                    self.breakpoints.insert(self.cursor);
+                }
+            }
+            KeyCode::Tab => {
+                self.keypad_input = true;
+                self.message = String::from("Keypad input, Tab to leave");
+            }
+            KeyCode::Up => self.cursor = self.cursor.saturating_sub(2),
+            KeyCode::Down => self.cursor = (self.cursor + 2).min(MEMORY_SIZE as u16 - 2),
+            KeyCode::Home => self.cursor = self.cpu.program_counter(),
+            _ => {}
+        }
+
+        true
+    }
+
+    // The debugger's own keys overlap the keymap, so keypad input is a
+    // separate mode.
+    fn handle_keypad_key(&mut self, code: KeyCode) {
+        let name = match code {
+            KeyCode::Tab | KeyCode::Esc => {
+                self.keypad_input = false;
+                self.message = match self.mode {
+                    RunMode::Paused => String::from("Paused"),
+                    RunMode::Running(_) => String::from("Running"),
+                };
+                return;
+            }
+            KeyCode::Char(' ') => String::from("Space"),
+            KeyCode::Char(c) => c.to_string(),
+            KeyCode::Up => String::from("Up"),
+            KeyCode::Down => String::from("Down"),
+            KeyCode::Left => String::from("Left"),
+            KeyCode::Right => String::from("Right"),
+            _ => return,
+        };
+
+        if let Some(key) = self.keymap.lookup(&name) {
+            let queued = self
+                .cpu
+                .queue_key(key, true, 0)
+                .and_then(|_| self.cpu.queue_key(key, false, KEY_HOLD_CYCLES));
+
+            if let Err(error) = queued {
+                self.message = format!("Error: {:?}", error);
+            }
+        }
+    }
+
+    fn start(&mut self, condition: StopCondition) {
+        self.previous_registers = self.cpu.registers().read_all();
+        self.mode = RunMode::Running(condition);
+        self.message = String::from("Running");
+
+        // Leave the current breakpoint before checking for the next one.
+        if self.execute() && self.should_stop(condition) {
+            self.stop();
+        }
+    }
+
+    fn stop(&mut self) {
+        self.mode = RunMode::Paused;
+        self.cursor = self.cpu.program_counter();
+        self.message = format!("Stopped at 0x{:03X}", self.cpu.program_counter());
+    }
+
+    fn execute(&mut self) -> bool {
+        if self.cpu.next_opcode().is_none() {
+            self.mode = RunMode::Paused;
+            self.message = format!(
+                "Unknown opcode 0x{:04X} at 0x{:03X}",
+                self.cpu
+                    .memory()
+                    .get_bytes(self.cpu.program_counter(), 2)
+                    .map(|bytes| (bytes[0] as u16) << 8 | bytes[1] as u16)
+                    .unwrap_or(0),
+                self.cpu.program_counter()
+            );
+            return false;
+        }
+
+        if let Err(error) = self.cpu.emulate_cycle() {
+            self.mode = RunMode::Paused;
+            self.message = format!("Error: {:?}", error);
+            return false;
+        }
+
+        true
+    }
+
+    fn should_stop(&self, condition: StopCondition) -> bool {
+        let program_counter = self.cpu.program_counter();
+        let stack_pointer = self.cpu.stack().get_pointer();
+
+        if self.breakpoints.contains(&program_counter) {
+            return true;
+        }
+
+        match condition {
+            StopCondition::Breakpoint => false,
+            StopCondition::Address {
+                address,
+                stack_pointer: expected,
+            } => {
+                program_counter == address
+                    && expected.is_none_or(|expected| stack_pointer == expected)
+            }
+            StopCondition::StackBelow(depth) => stack_pointer < depth,
+        }
+    }
+
+    fn run_frame(&mut self, condition: StopCondition) {
+        for _ in 0..CYCLES_PER_FRAME {
+            if !self.execute() {
+                return;
+            }
+
+            if self.should_stop(condition) {
+                self.stop();
+                return;
+            }
+        }
+    }
+
+    fn draw(&self, frame: &mut Frame) {
+        let [main, memory, status] = Layout::vertical([
+            Constraint::Min(18),
+            Constraint::Length(MEMORY_ROWS + 2),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+
+        let [disassembly, state, display] = Layout::horizontal([
+            Constraint::Min(36),
+            Constraint::Length(28),
+            Constraint::Length(self.cpu.screen.get_width() as u16 + 2),
+        ])
+        .areas(main);
+
+        let [registers, stack] =
+            Layout::vertical([Constraint::Length(13), Constraint::Min(4)]).areas(state);
+
+        let [screen, keypad] = Layout::vertical([
+            Constraint::Length(self.cpu.screen.get_height() as u16 / 2 + 2),
+            Constraint::Min(6),
+        ])
+        .areas(display);
+
+        self.draw_disassembly(frame, disassembly);
+        self.draw_registers(frame, registers);
+        self.draw_stack(frame, stack);
+        self.draw_screen(frame, screen);
+        self.draw_keypad(frame, keypad);
+        self.draw_memory(frame, memory);
+
+        let help = if self.keypad_input {
+            " keys go to the CHIP-8 keypad  Tab or Esc back to the debugger"
+        } else {
+            " s step  n step over  o step out  c run to cursor  r run  p pause  b breakpoint  Tab keypad  q quit"
+        };
+        frame.render_widget(
+            Paragraph::new(Line::from(vec![
+                Span::styled(
+                    format!(" {} ", self.message),
+                    Style::default().add_modifier(Modifier::REVERSED),
+                ),
+                Span::raw(help),
+            ])),
+            status,
+        );
+    }
+
+    fn draw_disassembly(&self, frame: &mut Frame, area: Rect) {
+        let rows = area.height.saturating_sub(2);
+        let first = self.cursor.saturating_sub(rows / 2 * 2);
+        let program_counter = self.cpu.program_counter();
+
+        let lines: Vec<Line> = (0..rows)
+            .map(|row| first + row * 2)
+            .take_while(|&address| (address as usize) + 1 < MEMORY_SIZE)
+            .map(|address| {
+                let raw = self
+                    .cpu
+                    .memory()
+                    .get_bytes(address, 2)
+                    .map(|bytes| (bytes[0] as u16) << 8 | bytes[1] as u16)
+                    .unwrap_or(0);
+                let mnemonic = match Opcode::decode_on(raw, self.cpu.platform()) {
+                    Some(opcode) => opcode.to_string(),
+                    None => format!("DW 0x{:04X}", raw),
+                };
+
+                let marker = match (
+                    self.breakpoints.contains(&address),
+                    address == program_counter,
+                ) {
+                    (true, true) => "*>",
+                    (true, false) => "* ",
+                    (false, true) => " >",
+                    (false, false) => "  ",
+                };
+
+                let mut style = Style::default();
+                if address == program_counter {
+                    style = style.fg(Color::Yellow).add_modifier(Modifier::BOLD);
+                }
+                if self.breakpoints.contains(&address) {
+                    style = style.fg(Color::Red);
+                }
+                if address == self.cursor {
+                    style = style.add_modifier(Modifier::REVERSED);
+                }
+
+                Line::styled(
+                    format!("{} 0x{:03X}  {:04X}  {}", marker, address, raw, mnemonic),
+                    style,
+                )
+            })
+            .collect();
+
+        frame.render_widget(
+            Paragraph::new(lines).block(Block::bordered().title(" Disassembly ")),
+            area,
+        );
+    }
+
+    fn draw_registers(&self, frame: &mut Frame, area: Rect) {
+        let values = self.cpu.registers().read_all();
+        let mut lines = Vec::new();
+
+        for row in 0..NUM_REGISTERS / 2 {
+            let spans: Vec<Span> = [row, row + NUM_REGISTERS / 2]
+                .iter()
+                .map(|&i| {
+                    let style = if values[i] != self.previous_registers[i] {
+                        Style::default()
+                            .fg(Color::Yellow)
+                            .add_modifier(Modifier::BOLD)
+                    } else {
+                        Style::default()
+                    };
+                    Span::styled(format!(" V{:X}: {:02X}     ", i, values[i]), style)
+                })
+                .collect();
+            lines.push(Line::from(spans));
+        }
+
+        lines.push(Line::raw(format!(
+            " I:  {:04X}    PC: {:04X}",
+            self.cpu.index(),
+            self.cpu.program_counter()
+        )));
+        lines.push(Line::raw(format!(
+            " DT: {:02X}      ST: {:02X}",
+            self.cpu.delay_timer(),
+            self.cpu.sound_timer()
+        )));
+        lines.push(Line::raw(format!(" Cycles: {}", self.cpu.cycles())));
+
+        frame.render_widget(
+            Paragraph::new(lines).block(Block::bordered().title(" Registers ")),
+            area,
+        );
+    }
+
+    fn draw_stack(&self, frame: &mut Frame, area: Rect) {
+        let pointer = self.cpu.stack().get_pointer() as usize;
+        let values = self.cpu.stack().get_values();
+
+        let mut lines: Vec<Line> = values[..pointer]
+            .iter()
+            .enumerate()
+            .rev()
+            .map(|(i, value)| Line::raw(format!(" {:2}: {:04X}", i, value)))
+            .collect();
+        if lines.is_empty() {
+            lines.push(Line::raw(" (empty)"));
+        }
+
+        frame.render_widget(
+            Paragraph::new(lines)
+                .block(Block::bordered().title(format!(" Stack (SP={}) ", pointer))),
+            area,
+        );
+    }
+
+    fn draw_screen(&self, frame: &mut Frame, area: Rect) {
+        let screen = &self.cpu.screen;
+
+        let lines: Vec<Line> = (0..screen.get_height() / 2)
+            .map(|row| {
+                let text: String = (0..screen.get_width())
+                    .map(|x| {
+                        match (
+                            screen.get_pixel(x, row * 2),
+                            screen.get_pixel(x, row * 2 + 1),
+                        ) {
+                            (true, true) => '█',
+                            (true, false) => '▀',
+                            (false, true) => '▄',
+                            (false, false) => ' ',
+                        }
+                    })
+                    .collect();
+                Line::raw(text)
+            })
+            .collect();
+
+        frame.render_widget(
+            Paragraph::new(lines).block(Block::bordered().title(" Screen ")),
+            area,
+        );
+    }
+
+    fn draw_keypad(&self, frame: &mut Frame, area: Rect) {
+        let lines: Vec<Line> = KEYPAD_LAYOUT
+            .iter()
+            .map(|row| {
+                let spans: Vec<Span> = row
+                    .iter()
+                    .map(|&key| {
+                        let pressed = self.cpu.keyboard().is_key_pressed(key).unwrap_or(false);
+                        let style = if pressed {
+                            Style::default().add_modifier(Modifier::REVERSED)
+                        } else {
+                            Style::default()
+                        };
+                        Span::styled(format!(" {:X} ", key), style)
+                    })
+                    .collect();
+                Line::from(spans)
+            })
+            .collect();
+
+        frame.render_widget(
+            Paragraph::new(lines).block(Block::bordered().title(" Keypad ")),
+            area,
+        );
+    }
+
+    fn draw_memory(&self, frame: &mut Frame, area: Rect) {
+        let index = self.cpu.index();
+        let last_row = (MEMORY_SIZE as u16 / 16) - MEMORY_ROWS;
+        let first_row = (index / 16).saturating_sub(MEMORY_ROWS / 2).min(last_row);
+
+        let lines: Vec<Line> = (first_row..first_row + MEMORY_ROWS)
+            .map(|row| {
+                let address = row * 16;
+                let bytes = self.cpu.memory().get_bytes(address, 16).unwrap_or_default();
+
+                let mut spans = vec![Span::raw(format!(" {:03X}: ", address))];
+                for (i, byte) in bytes.iter().enumerate() {
+                    let style = if address + i as u16 == index {
+                        Style::default().fg(Color::Black).bg(Color::Cyan)
+                    } else {
+                        Style::default()
+                    };
+                    spans.push(Span::styled(format!("{:02X}", byte), style));
+                    spans.push(Span::raw(" "));
+                }
+
+                let text: String = bytes
+                    .iter()
+                    .map(|&byte| {
+                        if byte.is_ascii_graphic() {
+                            byte as char
+                        } else {
+                            '.'
+                        }
+                    })
+                    .collect();
+                spans.push(Span::raw(format!(" {}", text)));
+
+                Line::from(spans)
+            })
+            .collect();
+
+        frame.render_widget(
+            Paragraph::new(lines)
+                .block(Block::bordered().title(format!(" Memory (I={:03X}) ", index))),
+            area,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn b_toggles_the_breakpoint_at_the_cursor() {
+        let mut cpu = Cpu::new();
+        let mut debugger = Debugger::new(&mut cpu, &Keymap::default());
+
+        debugger.handle_key(KeyCode::Char('b'));
+        assert!(debugger.breakpoints.contains(&0x200));
+
+        debugger.handle_key(KeyCode::Char('b'));
+        assert!(debugger.breakpoints.is_empty());
+    }
+
+    #[test]
+    fn keypad_mode_answers_a_key_wait() {
+        // F50A, then loop.
+        let mut cpu = Cpu::new();
+        cpu.load_rom_bytes_at(&[0xF5, 0x0A, 0x12, 0x02], 0x200)
+            .unwrap();
+        let mut debugger = Debugger::new(&mut cpu, &Keymap::default());
+
+        debugger.handle_key(KeyCode::Tab);
+        // Q is key 4 on the qwerty keymap and would otherwise quit.
+        assert!(debugger.handle_key(KeyCode::Char('q')));
+        debugger.handle_key(KeyCode::Tab);
+        assert!(!debugger.keypad_input);
+
+        for _ in 0..KEY_HOLD_CYCLES + 2 {
+            debugger.handle_key(KeyCode::Char('s'));
+        }
+
+        assert_eq!(debugger.cpu.registers().read(5).unwrap(), 4);
+        assert_eq!(debugger.cpu.program_counter(), 0x202);
+    }
+}
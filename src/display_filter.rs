@@ -0,0 +1,183 @@
+use std::collections::VecDeque;
+
+use crate::screen::Screen;
+
+pub const DEFAULT_DECAY: f32 = 0.6;
+pub const DEFAULT_BLEND_FRAMES: usize = 2;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FilterError {
+    UnknownFilter(String),
+    InvalidParameter(String),
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum FilterMode {
+    None,
+    Phosphor { decay: f32 },
+    FrameBlend { frames: usize },
+}
+
+impl FilterMode {
+    // "none", "phosphor" or "blend", optionally followed by the phosphor's
+    // decay per frame from 0 to 1 or the number of frames to blend, e.g.
+    // "phosphor:0.8" or "blend:3".
+    pub fn parse(text: &str) -> Result<FilterMode, FilterError> {
+        let (name, parameter) = match text.split_once(':') {
+            Some((name, parameter)) => (name, Some(parameter)),
+            None => (text, None),
+        };
+        let invalid = || FilterError::InvalidParameter(text.to_string());
+
+        match (name, parameter) {
+            ("none", None) => Ok(FilterMode::None),
+            ("phosphor", None) => Ok(FilterMode::Phosphor {
+                decay: DEFAULT_DECAY,
+            }),
+            ("phosphor", Some(decay)) => match decay.parse::<f32>() {
+                Ok(decay) if (0.0..=1.0).contains(&decay) => Ok(FilterMode::Phosphor { decay }),
+                _ => Err(invalid()),
+            },
+            ("blend", None) => Ok(FilterMode::FrameBlend {
+                frames: DEFAULT_BLEND_FRAMES,
+            }),
+            ("blend", Some(frames)) => match frames.parse::<usize>() {
+                Ok(frames) if frames > 0 => Ok(FilterMode::FrameBlend { frames }),
+                _ => Err(invalid()),
+            },
+            ("none", Some(_)) => Err(invalid()),
+            _ => Err(FilterError::UnknownFilter(text.to_string())),
+        }
+    }
+
+    pub fn next(self) -> FilterMode {
+        match self {
+            FilterMode::None => FilterMode::Phosphor {
+                decay: DEFAULT_DECAY,
+            },
+            FilterMode::Phosphor { .. } => FilterMode::FrameBlend {
+                frames: DEFAULT_BLEND_FRAMES,
+            },
+            FilterMode::FrameBlend { .. } => FilterMode::None,
+        }
+    }
+}
+
+pub struct DisplayFilter {
+    mode: FilterMode,
+    intensities: Vec<f32>,
+    history: VecDeque<Vec<bool>>,
+}
+
+impl DisplayFilter {
+    pub fn new(mode: FilterMode) -> DisplayFilter {
+        DisplayFilter {
+            mode,
+            intensities: Vec::new(),
+            history: VecDeque::new(),
+        }
+    }
+
+    pub fn get_mode(&self) -> FilterMode {
+        self.mode
+    }
+
+    pub fn set_mode(&mut self, mode: FilterMode) {
+        *self = DisplayFilter::new(mode);
+    }
+
+    // Feeds one emulated frame through the filter and returns one intensity
+    // per pixel, row by row, where 0 is off and 255 is fully lit.
+    pub fn apply(&mut self, screen: &Screen) -> Vec<u8> {
+        let width = screen.get_width();
+        let frame: Vec<bool> = (0..screen.get_height())
+            .flat_map(|y| (0..width).map(move |x| (x, y)))
+            .map(|(x, y)| screen.get_pixel(x, y))
+            .collect();
+
+        // Start over when the screen changes size.
+        if self.intensities.len() != frame.len() {
+            self.intensities = vec![0.0; frame.len()];
+            self.history.clear();
+        }
+
+        match self.mode {
+            FilterMode::None => frame.iter().map(|&on| on as u8 * 255).collect(),
+            FilterMode::Phosphor { decay } => {
+                let decay = decay.clamp(0.0, 1.0);
+
+                for (intensity, &on) in self.intensities.iter_mut().zip(&frame) {
+                    *intensity = if on { 1.0 } else { *intensity * decay };
+                }
+
+                self.intensities
+                    .iter()
+                    .map(|intensity| (intensity * 255.0).round() as u8)
+                    .collect()
+            }
+            FilterMode::FrameBlend { frames } => {
+                let frame_len = frame.len();
+                self.history.push_back(frame);
+                while self.history.len() > frames.max(1) {
+                    self.history.pop_front();
+                }
+
+                (0..frame_len)
+                    .map(|i| self.history.iter().any(|frame| frame[i]) as u8 * 255)
+                    .collect()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_accepts_decay_and_frame_count() {
+        assert_eq!(FilterMode::parse("none"), Ok(FilterMode::None));
+        assert_eq!(
+            FilterMode::parse("phosphor"),
+            Ok(FilterMode::Phosphor {
+                decay: DEFAULT_DECAY
+            })
+        );
+        assert_eq!(
+            FilterMode::parse("phosphor:0.25"),
+            Ok(FilterMode::Phosphor { decay: 0.25 })
+        );
+        assert_eq!(
+            FilterMode::parse("blend:4"),
+            Ok(FilterMode::FrameBlend { frames: 4 })
+        );
+    }
+
+    #[test]
+    fn parse_rejects_bad_parameters() {
+        for text in ["phosphor:1.5", "phosphor:dim", "blend:0", "none:1"] {
+            assert_eq!(
+                FilterMode::parse(text),
+                Err(FilterError::InvalidParameter(text.to_string()))
+            );
+        }
+
+        assert_eq!(
+            FilterMode::parse("crt"),
+            Err(FilterError::UnknownFilter("crt".to_string()))
+        );
+    }
+
+    #[test]
+    fn phosphor_fades_by_the_decay_each_frame() {
+        let mut screen = Screen::new();
+        let mut filter = DisplayFilter::new(FilterMode::Phosphor { decay: 0.5 });
+
+        screen.set_pixel(0, 0, true);
+        assert_eq!(filter.apply(&screen)[0], 255);
+
+        screen.set_pixel(0, 0, false);
+        assert_eq!(filter.apply(&screen)[0], 128);
+        assert_eq!(filter.apply(&screen)[0], 64);
+    }
+}
@@ -0,0 +1,177 @@
+use std::fmt;
+
+// Button names as used by SDL game controller mappings.
+pub const GAMEPAD_BUTTONS: [&str; 12] = [
+    "dpup",
+    "dpdown",
+    "dpleft",
+    "dpright",
+    "a",
+    "b",
+    "x",
+    "y",
+    "leftshoulder",
+    "rightshoulder",
+    "back",
+    "start",
+];
+
+// The D-pad follows the WASD block of the qwerty keymap (5, 7, 8, 9) that
+// most games use for movement; the face buttons cover the keys either side.
+const DEFAULT_MAPPING: &str =
+    "dpup=5,dpdown=8,dpleft=7,dpright=9,a=6,b=4,x=1,y=2,leftshoulder=3,rightshoulder=c,back=f,start=0";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GamepadError {
+    UnknownButton(String),
+    InvalidKey(String),
+}
+
+// Controller buttons bound to CHIP-8 keys. A key may have several buttons,
+// but each button drives only one key.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct GamepadMap {
+    bindings: Vec<(String, u8)>,
+}
+
+impl Default for GamepadMap {
+    fn default() -> Self {
+        GamepadMap::parse(DEFAULT_MAPPING).unwrap()
+    }
+}
+
+impl GamepadMap {
+    pub fn new() -> GamepadMap {
+        GamepadMap {
+            bindings: Vec::new(),
+        }
+    }
+
+    // Parses "dpup=5,a=6,...". A list starting with "default," adds to the
+    // default mapping instead of replacing it.
+    pub fn parse(text: &str) -> Result<GamepadMap, GamepadError> {
+        let mut map = GamepadMap::new();
+
+        for (i, item) in text.split(',').map(str::trim).enumerate() {
+            if i == 0 && item == "default" {
+                map = GamepadMap::default();
+                continue;
+            }
+
+            let (button, key) = item
+                .split_once('=')
+                .ok_or_else(|| GamepadError::InvalidKey(item.to_string()))?;
+            let button = button.trim().to_ascii_lowercase();
+
+            if !GAMEPAD_BUTTONS.contains(&button.as_str()) {
+                return Err(GamepadError::UnknownButton(button));
+            }
+
+            let key = u8::from_str_radix(key.trim(), 16)
+                .ok()
+                .filter(|&key| key < 16)
+                .ok_or_else(|| GamepadError::InvalidKey(key.to_string()))?;
+
+            map.bind(&button, key);
+        }
+
+        Ok(map)
+    }
+
+    pub fn bind(&mut self, button: &str, key: u8) {
+        self.bindings.retain(|(bound, _)| bound != button);
+        self.bindings.push((button.to_string(), key & 0xF));
+    }
+
+    pub fn lookup(&self, button: &str) -> Option<u8> {
+        self.bindings
+            .iter()
+            .find(|(bound, _)| bound == button)
+            .map(|&(_, key)| key)
+    }
+
+    pub fn buttons_for(&self, key: u8) -> Vec<&str> {
+        self.bindings
+            .iter()
+            .filter(|&&(_, bound)| bound == key)
+            .map(|(button, _)| button.as_str())
+            .collect()
+    }
+}
+
+// Writes the mapping in the form `parse` reads, in button order.
+impl fmt::Display for GamepadMap {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let items: Vec<String> = GAMEPAD_BUTTONS
+            .iter()
+            .filter_map(|&button| {
+                self.lookup(button)
+                    .map(|key| format!("{}={:x}", button, key))
+            })
+            .collect();
+
+        write!(f, "{}", items.join(","))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mappings_replace_or_extend_the_default() {
+        let map = GamepadMap::parse("dpup=2, A=f").unwrap();
+        assert_eq!(map.lookup("dpup"), Some(0x2));
+        assert_eq!(map.lookup("a"), Some(0xF));
+        assert_eq!(map.lookup("start"), None);
+
+        let map = GamepadMap::parse("default,a=5").unwrap();
+        assert_eq!(map.lookup("a"), Some(0x5));
+        assert_eq!(map.lookup("start"), Some(0x0));
+    }
+
+    #[test]
+    fn bad_mappings_name_the_problem() {
+        assert_eq!(
+            GamepadMap::parse("guide=1"),
+            Err(GamepadError::UnknownButton("guide".to_string()))
+        );
+        assert_eq!(
+            GamepadMap::parse("a=10"),
+            Err(GamepadError::InvalidKey("10".to_string()))
+        );
+        assert_eq!(
+            GamepadMap::parse("a"),
+            Err(GamepadError::InvalidKey("a".to_string()))
+        );
+    }
+
+    #[test]
+    fn a_button_drives_one_key_but_a_key_has_many_buttons() {
+        let mut map = GamepadMap::new();
+        map.bind("a", 5);
+        map.bind("b", 5);
+        map.bind("a", 6);
+
+        assert_eq!(map.lookup("a"), Some(6));
+        assert_eq!(map.buttons_for(5), ["b"]);
+        assert_eq!(map.buttons_for(6), ["a"]);
+    }
+
+    #[test]
+    fn mappings_are_written_in_button_order_and_read_back() {
+        let mut map = GamepadMap::new();
+        map.bind("start", 0);
+        map.bind("dpup", 0xC);
+
+        assert_eq!(map.to_string(), "dpup=c,start=0");
+
+        for map in [map, GamepadMap::default()] {
+            let read = GamepadMap::parse(&map.to_string()).unwrap();
+
+            for button in GAMEPAD_BUTTONS {
+                assert_eq!(read.lookup(button), map.lookup(button), "{}", button);
+            }
+        }
+    }
+}
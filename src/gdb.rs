@@ -0,0 +1,682 @@
+use std::collections::BTreeSet;
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+
+use crate::cpu::Cpu;
+use crate::registers::NUM_REGISTERS;
+use crate::stack::STACK_SIZE;
+
+const INDEX_REGISTER: usize = NUM_REGISTERS;
+const PROGRAM_COUNTER_REGISTER: usize = NUM_REGISTERS + 1;
+const STACK_POINTER_REGISTER: usize = NUM_REGISTERS + 2;
+const DELAY_TIMER_REGISTER: usize = NUM_REGISTERS + 3;
+const SOUND_TIMER_REGISTER: usize = NUM_REGISTERS + 4;
+const STACK_REGISTERS: usize = NUM_REGISTERS + 5;
+const REGISTER_COUNT: usize = STACK_REGISTERS + STACK_SIZE;
+
+const INTERRUPT: u8 = 0x03;
+const CYCLES_BETWEEN_INTERRUPT_CHECKS: u32 = 1000;
+
+const SIGINT: u8 = 2;
+const SIGILL: u8 = 4;
+const SIGTRAP: u8 = 5;
+
+pub struct GdbStub<'a> {
+    cpu: &'a mut Cpu,
+    stream: TcpStream,
+    breakpoints: BTreeSet<u16>,
+}
+
+pub fn serve(cpu: &mut Cpu, port: u16) -> std::io::Result<()> {
+    let listener = TcpListener::bind(("127.0.0.1", port))?;
+
+    println!("Waiting for gdb on 127.0.0.1:{}", port);
+
+    let (stream, address) = listener.accept()?;
+
+    println!("gdb connected from {}", address);
+
+    GdbStub::new(cpu, stream)?.run()
+}
+
+fn register_width(register: usize) -> usize {
+    match register {
+        INDEX_REGISTER | PROGRAM_COUNTER_REGISTER => 2,
+        r if r >= STACK_REGISTERS => 2,
+        _ => 1,
+    }
+}
+
+fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn decode_hex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+
+    (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(text.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+fn parse_number(text: &str) -> Option<usize> {
+    usize::from_str_radix(text, 16).ok()
+}
+
+// Binary data escapes `#`, `$`, `}` and `*` as `}` followed by the byte
+// XORed with 0x20.
+fn unescape_binary(data: &[u8]) -> Option<Vec<u8>> {
+    let mut bytes = Vec::with_capacity(data.len());
+    let mut data = data.iter();
+
+    while let Some(&byte) = data.next() {
+        match byte {
+            b'}' => bytes.push(data.next()? ^ 0x20),
+            byte => bytes.push(byte),
+        }
+    }
+
+    Some(bytes)
+}
+
+fn target_description() -> String {
+    let mut xml = String::from(
+        "<?xml version=\"1.0\"?>\n\
+         <!DOCTYPE target SYSTEM \"gdb-target.dtd\">\n\
+         <target version=\"1.0\">\n\
+         <feature name=\"org.chip8.core\">\n",
+    );
+
+    for i in 0..NUM_REGISTERS {
+        xml.push_str(&format!(
+            "<reg name=\"v{:x}\" bitsize=\"8\" type=\"uint8\" regnum=\"{}\"/>\n",
+            i, i
+        ));
+    }
+
+    xml.push_str(&format!(
+        "<reg name=\"i\" bitsize=\"16\" type=\"data_ptr\" regnum=\"{}\"/>\n\
+         <reg name=\"pc\" bitsize=\"16\" type=\"code_ptr\" regnum=\"{}\"/>\n\
+         <reg name=\"sp\" bitsize=\"8\" type=\"uint8\" regnum=\"{}\"/>\n\
+         <reg name=\"dt\" bitsize=\"8\" type=\"uint8\" regnum=\"{}\"/>\n\
+         <reg name=\"st\" bitsize=\"8\" type=\"uint8\" regnum=\"{}\"/>\n",
+        INDEX_REGISTER,
+        PROGRAM_COUNTER_REGISTER,
+        STACK_POINTER_REGISTER,
+        DELAY_TIMER_REGISTER,
+        SOUND_TIMER_REGISTER
+    ));
+
+    for i in 0..STACK_SIZE {
+        xml.push_str(&format!(
+            "<reg name=\"stack{}\" bitsize=\"16\" type=\"code_ptr\" regnum=\"{}\" group=\"stack\"/>\n",
+            i,
+            STACK_REGISTERS + i
+        ));
+    }
+
+    xml.push_str("</feature>\n</target>\n");
+
+    xml
+}
+
+impl<'a> GdbStub<'a> {
+    pub fn new(cpu: &'a mut Cpu, stream: TcpStream) -> std::io::Result<GdbStub<'a>> {
+        stream.set_nodelay(true)?;
+
+        Ok(GdbStub {
+            cpu,
+            stream,
+            breakpoints: BTreeSet::new(),
+        })
+    }
+
+    pub fn run(&mut self) -> std::io::Result<()> {
+        while let Some(packet) = self.read_packet()? {
+            match self.handle_packet(&packet)? {
+                Some(response) => self.write_packet(&response)?,
+                None => return Ok(()),
+            }
+        }
+
+        Ok(())
+    }
+
+    fn read_byte(&mut self) -> std::io::Result<Option<u8>> {
+        let mut byte = [0u8; 1];
+
+        match self.stream.read(&mut byte)? {
+            0 => Ok(None),
+            _ => Ok(Some(byte[0])),
+        }
+    }
+
+    fn read_packet(&mut self) -> std::io::Result<Option<Vec<u8>>> {
+        loop {
+            match self.read_byte()? {
+                None => return Ok(None),
+                Some(b'$') => break,
+                Some(INTERRUPT) => return Ok(Some(b"?".to_vec())),
+                Some(_) => {}
+            }
+        }
+
+        let mut data = Vec::new();
+        loop {
+            match self.read_byte()? {
+                None => return Ok(None),
+                Some(b'#') => break,
+                Some(byte) => data.push(byte),
+            }
+        }
+
+        let mut checksum = [0u8; 2];
+        self.stream.read_exact(&mut checksum)?;
+
+        let expected = std::str::from_utf8(&checksum)
+            .ok()
+            .and_then(|text| u8::from_str_radix(text, 16).ok());
+        let actual = data.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte));
+
+        if expected == Some(actual) {
+            self.stream.write_all(b"+")?;
+            Ok(Some(data))
+        } else {
+            self.stream.write_all(b"-")?;
+            self.read_packet()
+        }
+    }
+
+    fn write_packet(&mut self, data: &str) -> std::io::Result<()> {
+        let checksum = data.bytes().fold(0u8, |sum, byte| sum.wrapping_add(byte));
+
+        write!(self.stream, "${}#{:02x}", data, checksum)?;
+        self.stream.flush()?;
+
+        // Wait for the acknowledgement and resend if gdb asks for it.
+        loop {
+            match self.read_byte()? {
+                Some(b'+') | None => return Ok(()),
+                Some(b'-') => {
+                    write!(self.stream, "${}#{:02x}", data, checksum)?;
+                    self.stream.flush()?;
+                }
+                Some(_) => {}
+            }
+        }
+    }
+
+    fn handle_packet(&mut self, packet: &[u8]) -> std::io::Result<Option<String>> {
+        // Only X packets carry binary data, everything else is text.
+        if let Some(data) = packet.strip_prefix(b"X") {
+            return Ok(Some(self.write_binary_memory(data)));
+        }
+
+        let packet = String::from_utf8_lossy(packet);
+        let packet = packet.as_ref();
+
+        let response = match packet.as_bytes().first() {
+            Some(b'?') => format!("S{:02x}", SIGTRAP),
+            Some(b'g') => self.read_registers(),
+            Some(b'G') => self.write_registers(&packet[1..]),
+            Some(b'p') => self.read_register(&packet[1..]),
+            Some(b'P') => self.write_register(&packet[1..]),
+            Some(b'm') => self.read_memory(&packet[1..]),
+            Some(b'M') => self.write_memory(&packet[1..]),
+            Some(b'c') => {
+                self.resume_at(&packet[1..]);
+                self.continue_execution()?
+            }
+            Some(b's') => {
+                self.resume_at(&packet[1..]);
+                self.step()
+            }
+            Some(b'Z') => self.set_breakpoint(&packet[1..], true),
+            Some(b'z') => self.set_breakpoint(&packet[1..], false),
+            Some(b'H') => String::from("OK"),
+            Some(b'k') => return Ok(None),
+            Some(b'D') => {
+                self.write_packet("OK")?;
+                return Ok(None);
+            }
+            Some(b'q') => self.handle_query(packet),
+            _ => String::new(),
+        };
+
+        Ok(Some(response))
+    }
+
+    fn handle_query(&self, packet: &str) -> String {
+        if packet.starts_with("qSupported") {
+            return String::from("PacketSize=4000;qXfer:features:read+;swbreak+;hwbreak+");
+        }
+
+        if let Some(request) = packet.strip_prefix("qXfer:features:read:target.xml:") {
+            let Some((offset, length)) = request.split_once(',') else {
+                return String::from("E01");
+            };
+            let (Some(offset), Some(length)) = (parse_number(offset), parse_number(length)) else {
+                return String::from("E01");
+            };
+
+            let xml = target_description();
+            let start = offset.min(xml.len());
+            let end = (offset + length).min(xml.len());
+            let marker = if end == xml.len() { 'l' } else { 'm' };
+
+            return format!("{}{}", marker, &xml[start..end]);
+        }
+
+        match packet {
+            "qAttached" => String::from("1"),
+            "qC" => String::from("QC1"),
+            "qfThreadInfo" => String::from("m1"),
+            "qsThreadInfo" => String::from("l"),
+            _ => String::new(),
+        }
+    }
+
+    fn register_bytes(&self, register: usize) -> Vec<u8> {
+        let value: u16 = match register {
+            r if r < NUM_REGISTERS => self.cpu.registers().read_all()[r] as u16,
+            INDEX_REGISTER => self.cpu.index(),
+            PROGRAM_COUNTER_REGISTER => self.cpu.program_counter(),
+            STACK_POINTER_REGISTER => self.cpu.stack().get_pointer(),
+            DELAY_TIMER_REGISTER => self.cpu.delay_timer() as u16,
+            SOUND_TIMER_REGISTER => self.cpu.sound_timer() as u16,
+            r => self.cpu.stack().get_values()[r - STACK_REGISTERS],
+        };
+
+        match register_width(register) {
+            2 => value.to_be_bytes().to_vec(),
+            _ => vec![value as u8],
+        }
+    }
+
+    fn set_register_bytes(&mut self, register: usize, bytes: &[u8]) -> bool {
+        let value = match bytes {
+            [byte] if register_width(register) == 1 => *byte as u16,
+            [high, low] if register_width(register) == 2 => u16::from_be_bytes([*high, *low]),
+            _ => return false,
+        };
+
+        match register {
+            r if r < NUM_REGISTERS => self.cpu.registers_mut().write(r as u8, value as u8).is_ok(),
+            INDEX_REGISTER => {
+                self.cpu.set_index(value);
+                true
+            }
+            PROGRAM_COUNTER_REGISTER => {
+                self.cpu.set_program_counter(value);
+                true
+            }
+            STACK_POINTER_REGISTER => self.cpu.stack_mut().set_pointer(value),
+            DELAY_TIMER_REGISTER => {
+                self.cpu.set_delay_timer(value as u8);
+                true
+            }
+            SOUND_TIMER_REGISTER => {
+                self.cpu.set_sound_timer(value as u8);
+                true
+            }
+            r if r < REGISTER_COUNT => {
+                self.cpu.stack_mut().set_value(r - STACK_REGISTERS, value);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn read_registers(&self) -> String {
+        let bytes: Vec<u8> = (0..REGISTER_COUNT)
+            .flat_map(|register| self.register_bytes(register))
+            .collect();
+
+        encode_hex(&bytes)
+    }
+
+    fn write_registers(&mut self, data: &str) -> String {
+        let Some(bytes) = decode_hex(data) else {
+            return String::from("E01");
+        };
+
+        let mut offset = 0;
+        for register in 0..REGISTER_COUNT {
+            let width = register_width(register);
+            let Some(value) = bytes.get(offset..offset + width) else {
+                break;
+            };
+
+            if !self.set_register_bytes(register, value) {
+                return String::from("E01");
+            }
+            offset += width;
+        }
+
+        String::from("OK")
+    }
+
+    fn read_register(&self, data: &str) -> String {
+        match parse_number(data) {
+            Some(register) if register < REGISTER_COUNT => {
+                encode_hex(&self.register_bytes(register))
+            }
+            _ => String::from("E01"),
+        }
+    }
+
+    fn write_register(&mut self, data: &str) -> String {
+        let Some((register, value)) = data.split_once('=') else {
+            return String::from("E01");
+        };
+
+        match (parse_number(register), decode_hex(value)) {
+            (Some(register), Some(bytes)) if self.set_register_bytes(register, &bytes) => {
+                String::from("OK")
+            }
+            _ => String::from("E01"),
+        }
+    }
+
+    fn read_memory(&self, data: &str) -> String {
+        let Some((address, length)) = data.split_once(',') else {
+            return String::from("E01");
+        };
+
+        let (Some(address), Some(length)) = (parse_number(address), parse_number(length)) else {
+            return String::from("E01");
+        };
+
+        match (u16::try_from(address), u16::try_from(length)) {
+            (Ok(address), Ok(length)) => match self.cpu.memory().get_bytes(address, length) {
+                Ok(bytes) => encode_hex(&bytes),
+                Err(_) => String::from("E14"),
+            },
+            _ => String::from("E14"),
+        }
+    }
+
+    fn set_memory(&mut self, address: usize, bytes: &[u8]) -> String {
+        let Ok(address) = u16::try_from(address) else {
+            return String::from("E14");
+        };
+
+        match self.cpu.memory_mut().set_bytes(address, bytes) {
+            Ok(()) => String::from("OK"),
+            Err(_) => String::from("E14"),
+        }
+    }
+
+    fn write_memory(&mut self, data: &str) -> String {
+        let Some((location, bytes)) = data.split_once(':') else {
+            return String::from("E01");
+        };
+        let Some((address, _)) = location.split_once(',') else {
+            return String::from("E01");
+        };
+
+        match (parse_number(address), decode_hex(bytes)) {
+            (Some(address), Some(bytes)) => self.set_memory(address, &bytes),
+            _ => String::from("E01"),
+        }
+    }
+
+    fn write_binary_memory(&mut self, data: &[u8]) -> String {
+        let Some(colon) = data.iter().position(|&byte| byte == b':') else {
+            return String::from("E01");
+        };
+        let location = String::from_utf8_lossy(&data[..colon]);
+        let Some((address, length)) = location.split_once(',') else {
+            return String::from("E01");
+        };
+
+        match (
+            parse_number(address),
+            parse_number(length),
+            unescape_binary(&data[colon + 1..]),
+        ) {
+            (Some(address), Some(length), Some(bytes)) if bytes.len() == length => {
+                self.set_memory(address, &bytes)
+            }
+            _ => String::from("E01"),
+        }
+    }
+
+    fn set_breakpoint(&mut self, data: &str, insert: bool) -> String {
+        let fields: Vec<&str> = data.split(',').collect();
+
+        match fields.as_slice() {
+            ["0" | "1", address, ..] => match parse_number(address).map(u16::try_from) {
+                Some(Ok(address)) => {
+                    if insert {
+                        self.breakpoints.insert(address);
+                    } else {
+                        self.breakpoints.remove(&address);
+                    }
+                    String::from("OK")
+                }
+                _ => String::from("E01"),
+            },
+            _ => String::new(),
+        }
+    }
+
+    fn resume_at(&mut self, data: &str) {
+        if let Some(Ok(address)) = parse_number(data).map(u16::try_from) {
+            self.cpu.set_program_counter(address);
+        }
+    }
+
+    fn step(&mut self) -> String {
+        if self.cpu.next_opcode().is_none() || self.cpu.emulate_cycle().is_err() {
+            return format!("S{:02x}", SIGILL);
+        }
+
+        format!("S{:02x}", SIGTRAP)
+    }
+
+    fn interrupted(&mut self) -> std::io::Result<bool> {
+        let mut byte = [0u8; 1];
+
+        self.stream.set_nonblocking(true)?;
+        let result = self.stream.peek(&mut byte);
+        self.stream.set_nonblocking(false)?;
+
+        match result {
+            Ok(1) if byte[0] == INTERRUPT => {
+                self.stream.read_exact(&mut byte)?;
+                Ok(true)
+            }
+            Ok(_) => Ok(false),
+            Err(error) if error.kind() == std::io::ErrorKind::WouldBlock => Ok(false),
+            Err(error) => Err(error),
+        }
+    }
+
+    fn continue_execution(&mut self) -> std::io::Result<String> {
+        let mut cycles = 0;
+
+        loop {
+            if self.cpu.next_opcode().is_none() || self.cpu.emulate_cycle().is_err() {
+                return Ok(format!("S{:02x}", SIGILL));
+            }
+
+            if self.breakpoints.contains(&self.cpu.program_counter()) {
+                return Ok(format!("T{:02x}swbreak:;", SIGTRAP));
+            }
+
+            cycles += 1;
+            if cycles % CYCLES_BETWEEN_INTERRUPT_CHECKS == 0 && self.interrupted()? {
+                return Ok(format!("S{:02x}", SIGINT));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // LD V0, 2A; JP 202.
+    const PROGRAM: [u8; 4] = [0x60, 0x2A, 0x12, 0x02];
+
+    fn connect(cpu: &mut Cpu) -> (GdbStub<'_>, TcpStream) {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        // A wrong packet would otherwise hang the test.
+        stream
+            .set_read_timeout(Some(std::time::Duration::from_secs(5)))
+            .unwrap();
+
+        (GdbStub::new(cpu, stream).unwrap(), client)
+    }
+
+    fn cpu() -> Cpu {
+        let mut cpu = Cpu::new();
+        cpu.load_rom_bytes_at(&PROGRAM, 0x200).unwrap();
+
+        cpu
+    }
+
+    fn handle(stub: &mut GdbStub, packet: &str) -> String {
+        stub.handle_packet(packet.as_bytes()).unwrap().unwrap()
+    }
+
+    fn read_text(client: &mut TcpStream, length: usize) -> String {
+        let mut text = vec![0; length];
+        client.read_exact(&mut text).unwrap();
+
+        String::from_utf8(text).unwrap()
+    }
+
+    #[test]
+    fn packets_are_checked_and_acknowledged() {
+        let mut cpu = cpu();
+        let (mut stub, mut client) = connect(&mut cpu);
+
+        // The first packet's checksum is wrong, so it is refused.
+        client.write_all(b"$g#00$m200,2#5d").unwrap();
+
+        assert_eq!(stub.read_packet().unwrap().as_deref(), Some(&b"m200,2"[..]));
+        assert_eq!(read_text(&mut client, 2), "-+");
+    }
+
+    #[test]
+    fn an_interrupt_byte_asks_for_the_stop_reason() {
+        let mut cpu = cpu();
+        let (mut stub, mut client) = connect(&mut cpu);
+
+        client.write_all(&[INTERRUPT]).unwrap();
+
+        assert_eq!(stub.read_packet().unwrap().as_deref(), Some(&b"?"[..]));
+    }
+
+    #[test]
+    fn replies_carry_a_checksum_and_are_resent_when_refused() {
+        let mut cpu = cpu();
+        let (mut stub, mut client) = connect(&mut cpu);
+
+        client.write_all(b"-+").unwrap();
+        stub.write_packet("OK").unwrap();
+
+        assert_eq!(read_text(&mut client, 12), "$OK#9a$OK#9a");
+    }
+
+    #[test]
+    fn registers_and_memory_can_be_read_and_written() {
+        let mut cpu = cpu();
+        let (mut stub, _client) = connect(&mut cpu);
+
+        assert_eq!(handle(&mut stub, "P0=7f"), "OK");
+        assert_eq!(handle(&mut stub, "p0"), "7f");
+        assert_eq!(handle(&mut stub, "P10=0300"), "OK");
+        assert_eq!(handle(&mut stub, "p10"), "0300");
+        assert_eq!(handle(&mut stub, "p11"), "0200");
+        assert_eq!(handle(&mut stub, "P10=03"), "E01");
+        assert_eq!(handle(&mut stub, "pff"), "E01");
+        assert_eq!(&handle(&mut stub, "g")[..6], "7f0000");
+
+        assert_eq!(handle(&mut stub, "m200,4"), "602a1202");
+        assert_eq!(handle(&mut stub, "M300,2:abcd"), "OK");
+        assert_eq!(handle(&mut stub, "m300,2"), "abcd");
+        assert_eq!(handle(&mut stub, "M300,1:abc"), "E01");
+    }
+
+    #[test]
+    fn step_and_continue_stop_where_expected() {
+        let mut cpu = cpu();
+        let (mut stub, _client) = connect(&mut cpu);
+
+        assert_eq!(handle(&mut stub, "s"), "S05");
+        assert_eq!(handle(&mut stub, "p0"), "2a");
+
+        assert_eq!(handle(&mut stub, "Z0,202,2"), "OK");
+        assert_eq!(handle(&mut stub, "c200"), "T05swbreak:;");
+        assert_eq!(handle(&mut stub, "p11"), "0202");
+        assert_eq!(handle(&mut stub, "z0,202,2"), "OK");
+        assert_eq!(handle(&mut stub, "Z2,202,2"), "");
+    }
+
+    #[test]
+    fn queries_describe_the_target() {
+        let mut cpu = cpu();
+        let (mut stub, _client) = connect(&mut cpu);
+
+        assert!(handle(&mut stub, "qSupported:multiprocess+").contains("qXfer:features:read+"));
+        assert!(handle(&mut stub, "qXfer:features:read:target.xml:0,10").starts_with("m<?xml"));
+        assert!(handle(&mut stub, "qXfer:features:read:target.xml:0,fffff").starts_with('l'));
+        assert_eq!(handle(&mut stub, "qXfer:features:read:target.xml:0"), "E01");
+        assert_eq!(handle(&mut stub, "vMustReplyEmpty"), "");
+        assert_eq!(stub.handle_packet(b"k").unwrap(), None);
+    }
+
+    #[test]
+    fn addresses_beyond_the_address_space_are_refused() {
+        let mut cpu = cpu();
+        let (mut stub, _client) = connect(&mut cpu);
+
+        assert_eq!(handle(&mut stub, "m10200,2"), "E14");
+        assert_eq!(handle(&mut stub, "m200,10002"), "E14");
+        assert_eq!(handle(&mut stub, "M10300,1:ab"), "E14");
+        assert_eq!(handle(&mut stub, "X10300,1:a"), "E14");
+        assert_eq!(handle(&mut stub, "m300,1"), "00");
+    }
+
+    #[test]
+    fn binary_writes_are_unescaped() {
+        let mut cpu = cpu();
+        let (mut stub, _client) = connect(&mut cpu);
+
+        assert_eq!(
+            stub.handle_packet(b"X300,3:}]}\x04\xff")
+                .unwrap()
+                .as_deref(),
+            Some("OK")
+        );
+        assert_eq!(handle(&mut stub, "m300,3"), "7d24ff");
+
+        // An empty write is how gdb checks that X packets are supported.
+        assert_eq!(handle(&mut stub, "X300,0:"), "OK");
+        assert_eq!(handle(&mut stub, "X300,1:}"), "E01");
+        assert_eq!(handle(&mut stub, "X300,2:a"), "E01");
+    }
+
+    #[test]
+    fn stack_faults_stop_the_program() {
+        let mut cpu = cpu();
+        let (mut stub, _client) = connect(&mut cpu);
+
+        // RET with nothing to return to.
+        assert_eq!(handle(&mut stub, "M300,2:00ee"), "OK");
+        assert_eq!(handle(&mut stub, "s300"), "S04");
+
+        // CALL 300, calling itself until the stack is full.
+        assert_eq!(handle(&mut stub, "M300,2:2300"), "OK");
+        assert_eq!(handle(&mut stub, "c300"), "S04");
+        assert_eq!(handle(&mut stub, "p12"), "10");
+    }
+}
@@ -1,32 +1,131 @@
-use sdl2;
+use chip_8_emulator::gamepad::GamepadMap;
+use chip_8_emulator::keyboard::Keyboard;
+use chip_8_emulator::memory::CHIP8_FONTSET;
+
+use crate::controllers;
+
+pub const DEFAULT_SCALE: u32 = 16;
+const GRID_MINIMUM_PIXEL_SIZE: u32 = 4;
+// SDL reports mouse events synthesised from touches with this mouse id.
+const TOUCH_MOUSE_ID: u32 = u32::MAX;
+// The COSMAC VIP keypad layout.
+const KEYPAD_LAYOUT: [[u8; 4]; 4] = [
+    [0x1, 0x2, 0x3, 0xC],
+    [0x4, 0x5, 0x6, 0xD],
+    [0x7, 0x8, 0x9, 0xE],
+    [0xA, 0x0, 0xB, 0xF],
+];
+// Overlay size as a fraction of the window's shorter side.
+const KEYPAD_SIZE: f64 = 0.4;
+const KEYPAD_MARGIN: i32 = 8;
+// Where the controller mapping editor draws each button, as fractions of its
+// width and height, with the key being mapped shown at the top.
+const GAMEPAD_LAYOUT: [(&str, f64, f64); 12] = [
+    ("leftshoulder", 0.08, 0.06),
+    ("rightshoulder", 0.82, 0.06),
+    ("dpup", 0.18, 0.3),
+    ("dpleft", 0.08, 0.52),
+    ("dpright", 0.28, 0.52),
+    ("dpdown", 0.18, 0.74),
+    ("back", 0.38, 0.52),
+    ("start", 0.52, 0.52),
+    ("y", 0.72, 0.3),
+    ("x", 0.62, 0.52),
+    ("b", 0.82, 0.52),
+    ("a", 0.72, 0.74),
+];
+const GAMEPAD_EDITED_KEY: (f64, f64) = (0.45, 0.06);
+// Editor width as a fraction of the window, and button size as a fraction of
+// the editor's width. The editor is twice as wide as it is high.
+const GAMEPAD_EDITOR_SIZE: f64 = 0.8;
+const GAMEPAD_BUTTON_SIZE: f64 = 0.1;
+
+pub struct WindowOptions {
+    pub scale: u32,
+    pub resizable: bool,
+    pub fullscreen: bool,
+    pub grid: bool,
+    pub keypad: bool,
+}
+
+impl Default for WindowOptions {
+    fn default() -> Self {
+        WindowOptions {
+            scale: DEFAULT_SCALE,
+            resizable: true,
+            fullscreen: false,
+            grid: false,
+            keypad: false,
+        }
+    }
+}
 
 pub struct Graphics {
     event_pump: sdl2::EventPump,
     canvas: sdl2::render::Canvas<sdl2::video::Window>,
     texture_creator: sdl2::render::TextureCreator<sdl2::video::WindowContext>,
+    grid: bool,
+    keypad: bool,
+    // The mapping being edited and the CHIP-8 key a button is wanted for.
+    gamepad_editor: Option<(GamepadMap, u8)>,
+    // Keypad keys held down with the mouse and with each finger.
+    mouse_key: Option<u8>,
+    finger_keys: Vec<(i64, u8)>,
+    // SDL time in milliseconds of the last event returned.
+    event_timestamp: u32,
 }
 
 pub enum Event {
     Quit,
+    CycleFilter,
+    CyclePalette,
+    ToggleFullscreen,
+    ToggleGrid,
+    ToggleKeypad,
+    SaveState,
+    LoadState,
+    Reset,
+    PowerCycle,
+    Screenshot,
+    ToggleGifRecording,
+    ToggleVideoRecording,
+    EditGamepad,
+    KeyDown(String),
+    KeyUp(String),
+    ControllerAdded(u32),
+    ControllerRemoved(u32),
+    ButtonDown(String),
+    ButtonUp(String),
+    KeypadDown(u8),
+    KeypadUp(u8),
 }
 
 impl Graphics {
-    pub fn new(title: &str, width: u32, height: u32) -> Result<Graphics, String> {
-        let sdl_context = match sdl2::init() {
-            Ok(sdl_context) => sdl_context,
-            Err(e) => return Err(e),
-        };
+    pub fn new(
+        sdl_context: &sdl2::Sdl,
+        title: &str,
+        options: &WindowOptions,
+        screen_width: u32,
+        screen_height: u32,
+    ) -> Result<Graphics, String> {
+        let video_subsystem = sdl_context.video()?;
 
-        let video_subsystem = match sdl_context.video() {
-            Ok(video_subsystem) => video_subsystem,
-            Err(error_message) => return Err(error_message),
-        };
+        let mut window_builder = video_subsystem.window(
+            title,
+            screen_width * options.scale,
+            screen_height * options.scale,
+        );
+        window_builder.position_centered();
 
-        let window = match video_subsystem
-            .window(title, width, height)
-            .position_centered()
-            .build()
-        {
+        if options.resizable {
+            window_builder.resizable();
+        }
+
+        if options.fullscreen {
+            window_builder.fullscreen_desktop();
+        }
+
+        let window = match window_builder.build() {
             Ok(window) => window,
             Err(error) => return Err(error.to_string()),
         };
@@ -36,10 +135,7 @@ impl Graphics {
             Err(error) => return Err(error.to_string()),
         };
 
-        let event_pump = match sdl_context.event_pump() {
-            Ok(event_pump) => event_pump,
-            Err(error_message) => return Err(error_message),
-        };
+        let event_pump = sdl_context.event_pump()?;
 
         let texture_creator = canvas.texture_creator();
 
@@ -47,17 +143,146 @@ impl Graphics {
             event_pump,
             canvas,
             texture_creator,
+            grid: options.grid,
+            keypad: options.keypad,
+            gamepad_editor: None,
+            mouse_key: None,
+            finger_keys: Vec::new(),
+            event_timestamp: 0,
         })
     }
 
     pub fn handle_events(&mut self) -> Option<Event> {
         for event in self.event_pump.poll_iter() {
+            self.event_timestamp = event.get_timestamp();
+
             match event {
                 sdl2::event::Event::Quit { .. }
                 | sdl2::event::Event::KeyDown {
                     keycode: Some(sdl2::keyboard::Keycode::Escape),
                     ..
                 } => return Some(Event::Quit),
+                sdl2::event::Event::KeyDown {
+                    keycode: Some(sdl2::keyboard::Keycode::F1),
+                    ..
+                } => return Some(Event::EditGamepad),
+                sdl2::event::Event::KeyDown {
+                    keycode: Some(sdl2::keyboard::Keycode::F2),
+                    ..
+                } => return Some(Event::CycleFilter),
+                sdl2::event::Event::KeyDown {
+                    keycode: Some(sdl2::keyboard::Keycode::F3),
+                    ..
+                } => return Some(Event::CyclePalette),
+                sdl2::event::Event::KeyDown {
+                    keycode: Some(sdl2::keyboard::Keycode::F4),
+                    ..
+                } => return Some(Event::ToggleGrid),
+                sdl2::event::Event::KeyDown {
+                    keycode: Some(sdl2::keyboard::Keycode::F5),
+                    ..
+                } => return Some(Event::SaveState),
+                sdl2::event::Event::KeyDown {
+                    keycode: Some(sdl2::keyboard::Keycode::F7),
+                    ..
+                } => return Some(Event::LoadState),
+                sdl2::event::Event::KeyDown {
+                    keycode: Some(sdl2::keyboard::Keycode::F6),
+                    ..
+                } => return Some(Event::Reset),
+                sdl2::event::Event::KeyDown {
+                    keycode: Some(sdl2::keyboard::Keycode::F8),
+                    ..
+                } => return Some(Event::PowerCycle),
+                sdl2::event::Event::KeyDown {
+                    keycode: Some(sdl2::keyboard::Keycode::F9),
+                    ..
+                } => return Some(Event::ToggleGifRecording),
+                sdl2::event::Event::KeyDown {
+                    keycode: Some(sdl2::keyboard::Keycode::F10),
+                    ..
+                } => return Some(Event::ToggleVideoRecording),
+                sdl2::event::Event::KeyDown {
+                    keycode: Some(sdl2::keyboard::Keycode::F11),
+                    ..
+                } => return Some(Event::ToggleFullscreen),
+                sdl2::event::Event::KeyDown {
+                    keycode: Some(sdl2::keyboard::Keycode::F12),
+                    ..
+                } => return Some(Event::Screenshot),
+                sdl2::event::Event::KeyDown {
+                    keycode: Some(sdl2::keyboard::Keycode::Tab),
+                    ..
+                } => return Some(Event::ToggleKeypad),
+                sdl2::event::Event::KeyDown {
+                    keycode: Some(keycode),
+                    repeat: false,
+                    ..
+                } => return Some(Event::KeyDown(keycode.name())),
+                sdl2::event::Event::KeyUp {
+                    keycode: Some(keycode),
+                    ..
+                } => return Some(Event::KeyUp(keycode.name())),
+                sdl2::event::Event::ControllerDeviceAdded { which, .. } => {
+                    return Some(Event::ControllerAdded(which))
+                }
+                sdl2::event::Event::ControllerDeviceRemoved { which, .. } => {
+                    return Some(Event::ControllerRemoved(which))
+                }
+                sdl2::event::Event::ControllerButtonDown { button, .. } => {
+                    if let Some(name) = controllers::button_name(button) {
+                        return Some(Event::ButtonDown(name.to_string()));
+                    }
+                }
+                sdl2::event::Event::ControllerButtonUp { button, .. } => {
+                    if let Some(name) = controllers::button_name(button) {
+                        return Some(Event::ButtonUp(name.to_string()));
+                    }
+                }
+                sdl2::event::Event::MouseButtonDown {
+                    which,
+                    mouse_btn: sdl2::mouse::MouseButton::Left,
+                    x,
+                    y,
+                    ..
+                } if self.keypad && which != TOUCH_MOUSE_ID && self.mouse_key.is_none() => {
+                    let window_size = self.canvas.window().size();
+
+                    if let Some(key) = Self::keypad_key_at(window_size, x, y) {
+                        self.mouse_key = Some(key);
+                        return Some(Event::KeypadDown(key));
+                    }
+                }
+                sdl2::event::Event::MouseButtonUp {
+                    which,
+                    mouse_btn: sdl2::mouse::MouseButton::Left,
+                    ..
+                } if which != TOUCH_MOUSE_ID => {
+                    if let Some(key) = self.mouse_key.take() {
+                        return Some(Event::KeypadUp(key));
+                    }
+                }
+                sdl2::event::Event::FingerDown {
+                    finger_id, x, y, ..
+                } if self.keypad => {
+                    let (width, height) = self.canvas.window().size();
+                    let (x, y) = ((x * width as f32) as i32, (y * height as f32) as i32);
+
+                    if let Some(key) = Self::keypad_key_at((width, height), x, y) {
+                        self.finger_keys.push((finger_id, key));
+                        return Some(Event::KeypadDown(key));
+                    }
+                }
+                sdl2::event::Event::FingerUp { finger_id, .. } => {
+                    if let Some(position) = self
+                        .finger_keys
+                        .iter()
+                        .position(|&(finger, _)| finger == finger_id)
+                    {
+                        let (_, key) = self.finger_keys.remove(position);
+                        return Some(Event::KeypadUp(key));
+                    }
+                }
                 _ => {}
             }
         }
@@ -65,30 +290,328 @@ impl Graphics {
         None
     }
 
-    pub fn render(&mut self, pixels: &[u8]) -> Result<(), String> {
+    pub fn event_timestamp(&self) -> u32 {
+        self.event_timestamp
+    }
+
+    pub fn set_title(&mut self, title: &str) -> Result<(), String> {
+        self.canvas
+            .window_mut()
+            .set_title(title)
+            .map_err(|error| error.to_string())
+    }
+
+    pub fn toggle_fullscreen(&mut self) -> Result<(), String> {
+        let window = self.canvas.window_mut();
+
+        let fullscreen = match window.fullscreen_state() {
+            sdl2::video::FullscreenType::Off => sdl2::video::FullscreenType::Desktop,
+            _ => sdl2::video::FullscreenType::Off,
+        };
+
+        window.set_fullscreen(fullscreen)
+    }
+
+    pub fn toggle_grid(&mut self) {
+        self.grid = !self.grid;
+    }
+
+    pub fn toggle_keypad(&mut self) {
+        self.keypad = !self.keypad;
+    }
+
+    // Shows the controller mapping editor asking for a button for `key`, or
+    // hides it.
+    pub fn set_gamepad_editor(&mut self, editor: Option<(&GamepadMap, u8)>) {
+        self.gamepad_editor = editor.map(|(gamepad, key)| (gamepad.clone(), key));
+    }
+
+    // The overlay's square in the bottom right corner of a window of the
+    // given size.
+    fn keypad_area(width: u32, height: u32) -> sdl2::rect::Rect {
+        let size = (width.min(height) as f64 * KEYPAD_SIZE) as u32;
+
+        sdl2::rect::Rect::new(
+            width as i32 - size as i32 - KEYPAD_MARGIN,
+            height as i32 - size as i32 - KEYPAD_MARGIN,
+            size,
+            size,
+        )
+    }
+
+    // Mouse and touch positions are in window coordinates, which can differ
+    // from the drawable size on high-DPI displays.
+    fn keypad_key_at((width, height): (u32, u32), x: i32, y: i32) -> Option<u8> {
+        let area = Self::keypad_area(width, height);
+
+        if !area.contains_point((x, y)) {
+            return None;
+        }
+
+        let column = (x - area.x()) as usize * 4 / area.width() as usize;
+        let row = (y - area.y()) as usize * 4 / area.height() as usize;
+
+        Some(KEYPAD_LAYOUT[row.min(3)][column.min(3)])
+    }
+
+    // Translucent 4x4 keys labelled with the CHIP-8 font, with the keys held
+    // down in `keyboard` lit up.
+    fn draw_keypad(
+        canvas: &mut sdl2::render::Canvas<sdl2::video::Window>,
+        keyboard: &Keyboard,
+    ) -> Result<(), String> {
+        let (width, height) = canvas.output_size()?;
+        let area = Self::keypad_area(width, height);
+        let cell = area.width() as i32 / 4;
+
+        canvas.set_blend_mode(sdl2::render::BlendMode::Blend);
+
+        for (row, keys) in KEYPAD_LAYOUT.iter().enumerate() {
+            for (column, &key) in keys.iter().enumerate() {
+                let x = area.x() + column as i32 * cell;
+                let y = area.y() + row as i32 * cell;
+                let pressed = keyboard.is_key_pressed(key).unwrap_or(false);
+
+                let (background, foreground) = if pressed {
+                    ((255, 255, 255, 192), (0, 0, 0, 255))
+                } else {
+                    ((32, 32, 32, 160), (255, 255, 255, 224))
+                };
+
+                canvas.set_draw_color(sdl2::pixels::Color::from(background));
+                canvas.fill_rect(sdl2::rect::Rect::new(
+                    x + 1,
+                    y + 1,
+                    (cell - 2).max(1) as u32,
+                    (cell - 2).max(1) as u32,
+                ))?;
+
+                canvas.set_draw_color(sdl2::pixels::Color::from(foreground));
+                Self::draw_glyph(canvas, key, x, y, cell)?;
+            }
+        }
+
+        canvas.set_blend_mode(sdl2::render::BlendMode::None);
+        canvas.set_draw_color(sdl2::pixels::Color::RGB(0, 0, 0));
+
+        Ok(())
+    }
+
+    // Draws the font's glyph for `key`, 4x5 font pixels centred in a square
+    // cell, in the current draw colour.
+    fn draw_glyph(
+        canvas: &mut sdl2::render::Canvas<sdl2::video::Window>,
+        key: u8,
+        x: i32,
+        y: i32,
+        cell: i32,
+    ) -> Result<(), String> {
+        let glyph_pixel = (cell / 10).max(1);
+        let glyph_x = x + (cell - 4 * glyph_pixel) / 2;
+        let glyph_y = y + (cell - 5 * glyph_pixel) / 2;
+
+        for (line, bits) in CHIP8_FONTSET[key as usize * 5..key as usize * 5 + 5]
+            .iter()
+            .enumerate()
+        {
+            for bit in 0..4 {
+                if bits & (0x80 >> bit) != 0 {
+                    canvas.fill_rect(sdl2::rect::Rect::new(
+                        glyph_x + bit * glyph_pixel,
+                        glyph_y + line as i32 * glyph_pixel,
+                        glyph_pixel as u32,
+                        glyph_pixel as u32,
+                    ))?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn draw_gamepad_button(
+        canvas: &mut sdl2::render::Canvas<sdl2::video::Window>,
+        key: Option<u8>,
+        lit: bool,
+        x: i32,
+        y: i32,
+        cell: i32,
+    ) -> Result<(), String> {
+        let (background, foreground) = if lit {
+            ((255, 192, 0, 255), (0, 0, 0, 255))
+        } else {
+            ((64, 64, 64, 255), (255, 255, 255, 224))
+        };
+
+        canvas.set_draw_color(sdl2::pixels::Color::from(background));
+        canvas.fill_rect(sdl2::rect::Rect::new(
+            x + 1,
+            y + 1,
+            (cell - 2).max(1) as u32,
+            (cell - 2).max(1) as u32,
+        ))?;
+
+        match key {
+            Some(key) => {
+                canvas.set_draw_color(sdl2::pixels::Color::from(foreground));
+                Self::draw_glyph(canvas, key, x, y, cell)
+            }
+            None => Ok(()),
+        }
+    }
+
+    // A controller with each button labelled with the CHIP-8 key it plays.
+    // The key being mapped is shown at the top and the buttons that play it
+    // now are lit up in the same colour.
+    fn draw_gamepad_editor(
+        canvas: &mut sdl2::render::Canvas<sdl2::video::Window>,
+        gamepad: &GamepadMap,
+        edited_key: u8,
+    ) -> Result<(), String> {
+        let (window_width, window_height) = canvas.output_size()?;
+        let width = (window_width.min(window_height * 2) as f64 * GAMEPAD_EDITOR_SIZE) as i32;
+        let height = width / 2;
+        let left = (window_width as i32 - width) / 2;
+        let top = (window_height as i32 - height) / 2;
+        let cell = (width as f64 * GAMEPAD_BUTTON_SIZE) as i32;
+        let position = |(x, y): (f64, f64)| {
+            (
+                left + (x * width as f64) as i32,
+                top + (y * height as f64) as i32,
+            )
+        };
+
+        canvas.set_blend_mode(sdl2::render::BlendMode::Blend);
+        canvas.set_draw_color(sdl2::pixels::Color::RGBA(16, 16, 16, 224));
+        canvas.fill_rect(sdl2::rect::Rect::new(
+            left,
+            top,
+            width.max(1) as u32,
+            height.max(1) as u32,
+        ))?;
+
+        for &(button, x, y) in &GAMEPAD_LAYOUT {
+            let key = gamepad.lookup(button);
+            let (x, y) = position((x, y));
+
+            Self::draw_gamepad_button(canvas, key, key == Some(edited_key), x, y, cell)?;
+        }
+
+        let (x, y) = position(GAMEPAD_EDITED_KEY);
+        canvas.set_draw_color(sdl2::pixels::Color::RGB(255, 192, 0));
+        canvas.draw_rect(sdl2::rect::Rect::new(
+            x - 2,
+            y - 2,
+            (cell + 4).max(1) as u32,
+            (cell + 4).max(1) as u32,
+        ))?;
+        Self::draw_gamepad_button(canvas, Some(edited_key), true, x, y, cell)?;
+
+        canvas.set_blend_mode(sdl2::render::BlendMode::None);
+        canvas.set_draw_color(sdl2::pixels::Color::RGB(0, 0, 0));
+
+        Ok(())
+    }
+
+    // Largest rectangle with the screen's aspect ratio that fits the window,
+    // centred so the remaining space becomes black bars.
+    fn letterbox(&self, screen_width: u32, screen_height: u32) -> Result<sdl2::rect::Rect, String> {
+        let (window_width, window_height) = self.canvas.output_size()?;
+
+        let scale = f64::min(
+            window_width as f64 / screen_width as f64,
+            window_height as f64 / screen_height as f64,
+        );
+
+        let width = (screen_width as f64 * scale) as u32;
+        let height = (screen_height as f64 * scale) as u32;
+
+        Ok(sdl2::rect::Rect::new(
+            ((window_width - width) / 2) as i32,
+            ((window_height - height) / 2) as i32,
+            width,
+            height,
+        ))
+    }
+
+    fn draw_grid(
+        canvas: &mut sdl2::render::Canvas<sdl2::video::Window>,
+        destination: sdl2::rect::Rect,
+        screen_width: u32,
+        screen_height: u32,
+    ) -> Result<(), String> {
+        let pixel_width = destination.width() as f64 / screen_width as f64;
+        let pixel_height = destination.height() as f64 / screen_height as f64;
+
+        if pixel_width < GRID_MINIMUM_PIXEL_SIZE as f64 {
+            return Ok(());
+        }
+
+        canvas.set_blend_mode(sdl2::render::BlendMode::Blend);
+        canvas.set_draw_color(sdl2::pixels::Color::RGBA(0, 0, 0, 96));
+
+        for x in 1..screen_width {
+            let line_x = destination.x() + (x as f64 * pixel_width) as i32;
+            canvas.draw_line(
+                (line_x, destination.top()),
+                (line_x, destination.bottom() - 1),
+            )?;
+        }
+
+        for y in 1..screen_height {
+            let line_y = destination.y() + (y as f64 * pixel_height) as i32;
+            canvas.draw_line(
+                (destination.left(), line_y),
+                (destination.right() - 1, line_y),
+            )?;
+        }
+
+        canvas.set_blend_mode(sdl2::render::BlendMode::None);
+        canvas.set_draw_color(sdl2::pixels::Color::RGB(0, 0, 0));
+
+        Ok(())
+    }
+
+    pub fn render(
+        &mut self,
+        pixels: &[u8],
+        screen_width: u32,
+        screen_height: u32,
+        keyboard: &Keyboard,
+    ) -> Result<(), String> {
         self.canvas.clear();
 
         let mut texture = match self.texture_creator.create_texture_streaming(
-            sdl2::pixels::PixelFormatEnum::RGBA8888,
-            64,
-            32,
+            sdl2::pixels::PixelFormatEnum::RGBA32,
+            screen_width,
+            screen_height,
         ) {
             Ok(texture) => texture,
             Err(error) => return Err(error.to_string()),
         };
 
-        match texture.with_lock(None, |buffer: &mut [u8], _: usize| {
-            for (i, pixel) in pixels.iter().enumerate() {
-                buffer[i] = *pixel;
+        let row_length = screen_width as usize * 4;
+
+        texture.with_lock(None, |buffer: &mut [u8], pitch: usize| {
+            for (y, row) in pixels.chunks(row_length).enumerate() {
+                buffer[y * pitch..y * pitch + row.len()].copy_from_slice(row);
             }
-        }) {
-            Ok(_) => {}
-            Err(error_message) => return Err(error_message),
+        })?;
+
+        let destination = self.letterbox(screen_width, screen_height)?;
+
+        self.canvas.copy(&texture, None, destination)?;
+
+        if self.grid {
+            Self::draw_grid(&mut self.canvas, destination, screen_width, screen_height)?;
+        }
+
+        if self.keypad {
+            Self::draw_keypad(&mut self.canvas, keyboard)?;
         }
 
-        match self.canvas.copy(&texture, None, None) {
-            Ok(_) => {}
-            Err(error_message) => return Err(error_message),
+        if let Some((gamepad, key)) = &self.gamepad_editor {
+            Self::draw_gamepad_editor(&mut self.canvas, gamepad, *key)?;
         }
 
         self.canvas.present();
@@ -0,0 +1,215 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+
+#[derive(Debug)]
+pub enum InputLogError {
+    IoError(std::io::Error),
+    ParseError(usize),
+}
+
+impl From<std::io::Error> for InputLogError {
+    fn from(error: std::io::Error) -> Self {
+        InputLogError::IoError(error)
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct InputEvent {
+    pub frame: u64,
+    // Instructions into the frame the change happened at.
+    pub offset: u64,
+    // 0 for the usual keypad, 1 for the CHIP-8X second keypad.
+    pub pad: u8,
+    pub key: u8,
+    pub pressed: bool,
+}
+
+// Keypad changes of a session together with the random seed, so that
+// replaying them against the same ROM and options reproduces it exactly.
+// Saved as text:
+//
+//   seed 1234
+//   120 5 down
+//   134+4 5 up
+//   140 2:A down
+//
+// where +4 places the change four instructions into frame 134 and 2: marks a
+// key on the second keypad.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct InputLog {
+    seed: u64,
+    events: Vec<InputEvent>,
+    position: usize,
+}
+
+impl InputLog {
+    pub fn new(seed: u64) -> InputLog {
+        InputLog {
+            seed,
+            events: Vec::new(),
+            position: 0,
+        }
+    }
+
+    pub fn get_seed(&self) -> u64 {
+        self.seed
+    }
+
+    pub fn events(&self) -> &[InputEvent] {
+        &self.events
+    }
+
+    pub fn record(&mut self, frame: u64, offset: u64, pad: u8, key: u8, pressed: bool) {
+        self.events.push(InputEvent {
+            frame,
+            offset,
+            pad,
+            key,
+            pressed,
+        });
+    }
+
+    // Returns the events recorded for `frame`. Frames must be requested in
+    // increasing order.
+    pub fn events_for_frame(&mut self, frame: u64) -> &[InputEvent] {
+        while self.position < self.events.len() && self.events[self.position].frame < frame {
+            self.position += 1;
+        }
+
+        let start = self.position;
+        while self.position < self.events.len() && self.events[self.position].frame == frame {
+            self.position += 1;
+        }
+
+        &self.events[start..self.position]
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.position >= self.events.len()
+    }
+
+    pub fn load(filename: &str) -> Result<InputLog, InputLogError> {
+        let reader = BufReader::new(File::open(filename)?);
+        let mut log = InputLog::new(0);
+
+        for (number, line) in reader.lines().enumerate() {
+            let line = line?;
+            let fields: Vec<&str> = line.split_whitespace().collect();
+
+            match fields.as_slice() {
+                [] => {}
+                ["seed", seed] => {
+                    log.seed = seed
+                        .parse()
+                        .map_err(|_| InputLogError::ParseError(number + 1))?;
+                }
+                [time, key, state] => {
+                    let (frame, offset) = time.split_once('+').unwrap_or((time, "0"));
+                    let frame = frame
+                        .parse()
+                        .map_err(|_| InputLogError::ParseError(number + 1))?;
+                    let offset = offset
+                        .parse()
+                        .map_err(|_| InputLogError::ParseError(number + 1))?;
+                    let (pad, key) = match key.split_once(':') {
+                        Some(("2", key)) => (1, key),
+                        Some(_) => return Err(InputLogError::ParseError(number + 1)),
+                        None => (0, *key),
+                    };
+                    let key = u8::from_str_radix(key, 16)
+                        .ok()
+                        .filter(|&key| key < 16)
+                        .ok_or(InputLogError::ParseError(number + 1))?;
+                    let pressed = match *state {
+                        "down" => true,
+                        "up" => false,
+                        _ => return Err(InputLogError::ParseError(number + 1)),
+                    };
+
+                    log.record(frame, offset, pad, key, pressed);
+                }
+                _ => return Err(InputLogError::ParseError(number + 1)),
+            }
+        }
+
+        log.events.sort_by_key(|event| (event.frame, event.offset));
+
+        Ok(log)
+    }
+
+    pub fn save(&self, filename: &str) -> Result<(), InputLogError> {
+        let mut writer = BufWriter::new(File::create(filename)?);
+
+        writeln!(writer, "seed {}", self.seed)?;
+        for event in &self.events {
+            let time = if event.offset > 0 {
+                format!("{}+{}", event.frame, event.offset)
+            } else {
+                event.frame.to_string()
+            };
+
+            let pad = if event.pad == 1 { "2:" } else { "" };
+
+            writeln!(
+                writer,
+                "{} {}{:X} {}",
+                time,
+                pad,
+                event.key,
+                if event.pressed { "down" } else { "up" }
+            )?;
+        }
+
+        writer.flush()?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn save_and_load_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("round-trip.log");
+        let path = path.to_str().unwrap();
+        let mut log = InputLog::new(1234);
+        log.record(120, 0, 0, 0x5, true);
+        log.record(134, 4, 0, 0x5, false);
+        log.record(140, 0, 1, 0xA, true);
+        log.save(path).unwrap();
+
+        let saved = std::fs::read_to_string(path).unwrap();
+        let loaded = InputLog::load(path);
+
+        assert_eq!(saved, "seed 1234\n120 5 down\n134+4 5 up\n140 2:A down\n");
+        assert_eq!(loaded.unwrap(), log);
+    }
+
+    #[test]
+    fn load_sorts_events_and_reports_the_bad_line() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("unsorted.log");
+        let path = path.to_str().unwrap();
+        std::fs::write(path, "seed 7\n\n9 1 up\n3+2 1 down\n3 F down\n").unwrap();
+        let mut log = InputLog::load(path).unwrap();
+
+        std::fs::write(path, "seed 7\n3 1 down\n4 G up\n").unwrap();
+        let error = InputLog::load(path);
+
+        assert_eq!(log.get_seed(), 7);
+        let times: Vec<(u64, u64)> = log
+            .events()
+            .iter()
+            .map(|event| (event.frame, event.offset))
+            .collect();
+        assert_eq!(times, [(3, 0), (3, 2), (9, 0)]);
+        assert_eq!(log.events_for_frame(3).len(), 2);
+        assert!(log.events_for_frame(5).is_empty());
+        assert_eq!(log.events_for_frame(9).len(), 1);
+        assert!(log.is_finished());
+        assert!(matches!(error, Err(InputLogError::ParseError(3))));
+    }
+}
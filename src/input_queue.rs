@@ -0,0 +1,232 @@
+use std::collections::VecDeque;
+
+use crate::keyboard::{Keyboard, KeyboardError, NUM_PADS};
+
+const NUM_KEYS: usize = 16;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct QueuedInput {
+    pub cycle: u64,
+    pub pad: u8,
+    pub key: u8,
+    pub pressed: bool,
+}
+
+// Key changes waiting to be applied to the keyboard at a given cycle, so that
+// input lands between the instructions it happened between rather than all
+// at the start of a frame. Latency delays every change, and a minimum hold
+// time holds back releases so that very short taps are still seen.
+pub struct InputQueue {
+    events: VecDeque<QueuedInput>,
+    latency: u64,
+    minimum_hold: u64,
+    pressed_at: [[Option<u64>; NUM_KEYS]; NUM_PADS],
+}
+
+impl Default for InputQueue {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl InputQueue {
+    pub fn new() -> InputQueue {
+        InputQueue {
+            events: VecDeque::new(),
+            latency: 0,
+            minimum_hold: 0,
+            pressed_at: [[None; NUM_KEYS]; NUM_PADS],
+        }
+    }
+
+    pub fn get_latency(&self) -> u64 {
+        self.latency
+    }
+
+    pub fn set_latency(&mut self, cycles: u64) {
+        self.latency = cycles;
+    }
+
+    pub fn get_minimum_hold(&self) -> u64 {
+        self.minimum_hold
+    }
+
+    pub fn set_minimum_hold(&mut self, cycles: u64) {
+        self.minimum_hold = cycles;
+    }
+
+    pub fn push(&mut self, cycle: u64, key: u8, pressed: bool) -> Result<(), KeyboardError> {
+        self.push_on(cycle, 0, key, pressed)
+    }
+
+    pub fn push_on(
+        &mut self,
+        cycle: u64,
+        pad: u8,
+        key: u8,
+        pressed: bool,
+    ) -> Result<(), KeyboardError> {
+        let pressed_at = self
+            .pressed_at
+            .get_mut(pad as usize)
+            .ok_or(KeyboardError::PadOutOfBounds)?
+            .get_mut(key as usize)
+            .ok_or(KeyboardError::KeyOutOfBounds)?;
+
+        // Changes to one key keep their order even when a held back release
+        // is still waiting.
+        let mut cycle = self
+            .events
+            .iter()
+            .filter(|event| event.pad == pad && event.key == key)
+            .map(|event| event.cycle)
+            .fold(cycle + self.latency, u64::max);
+
+        if pressed {
+            *pressed_at = Some(cycle);
+        } else if let Some(pressed_cycle) = pressed_at.take() {
+            cycle = cycle.max(pressed_cycle + self.minimum_hold);
+        }
+
+        let position = self
+            .events
+            .iter()
+            .position(|event| event.cycle > cycle)
+            .unwrap_or(self.events.len());
+
+        self.events.insert(
+            position,
+            QueuedInput {
+                cycle,
+                pad,
+                key,
+                pressed,
+            },
+        );
+
+        Ok(())
+    }
+
+    // Applies every change due at or before `cycle`.
+    pub fn apply(&mut self, cycle: u64, keyboard: &mut Keyboard) -> Result<(), KeyboardError> {
+        while let Some(event) = self.events.front().filter(|event| event.cycle <= cycle) {
+            keyboard.set_key_on(event.pad, event.key, event.pressed)?;
+
+            self.events.pop_front();
+        }
+
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cycles(queue: &InputQueue) -> Vec<(u64, u8, bool)> {
+        queue
+            .events
+            .iter()
+            .map(|event| (event.cycle, event.key, event.pressed))
+            .collect()
+    }
+
+    #[test]
+    fn changes_apply_in_cycle_order() {
+        let mut queue = InputQueue::new();
+        let mut keyboard = Keyboard::new();
+        queue.push(20, 0x1, true).unwrap();
+        queue.push(10, 0x2, true).unwrap();
+        queue.push_on(15, 1, 0x3, true).unwrap();
+
+        assert_eq!(
+            cycles(&queue),
+            [(10, 0x2, true), (15, 0x3, true), (20, 0x1, true)]
+        );
+
+        queue.apply(15, &mut keyboard).unwrap();
+        assert!(keyboard.is_key_pressed(0x2).unwrap());
+        assert!(keyboard.is_key_pressed_on(1, 0x3).unwrap());
+        assert!(!keyboard.is_key_pressed(0x1).unwrap());
+
+        queue.apply(20, &mut keyboard).unwrap();
+        assert!(keyboard.is_key_pressed(0x1).unwrap());
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn latency_delays_every_change() {
+        let mut queue = InputQueue::new();
+        let mut keyboard = Keyboard::new();
+        queue.set_latency(5);
+        queue.push(10, 0x4, true).unwrap();
+        queue.push(12, 0x4, false).unwrap();
+
+        assert_eq!(cycles(&queue), [(15, 0x4, true), (17, 0x4, false)]);
+
+        queue.apply(14, &mut keyboard).unwrap();
+        assert!(!keyboard.is_key_pressed(0x4).unwrap());
+        queue.apply(15, &mut keyboard).unwrap();
+        assert!(keyboard.is_key_pressed(0x4).unwrap());
+    }
+
+    #[test]
+    fn minimum_hold_holds_back_short_releases() {
+        let mut queue = InputQueue::new();
+        let mut keyboard = Keyboard::new();
+        queue.set_minimum_hold(100);
+        queue.push(10, 0x5, true).unwrap();
+        queue.push(12, 0x5, false).unwrap();
+        // Long enough already.
+        queue.push(20, 0x6, true).unwrap();
+        queue.push(200, 0x6, false).unwrap();
+
+        assert_eq!(
+            cycles(&queue),
+            [
+                (10, 0x5, true),
+                (20, 0x6, true),
+                (110, 0x5, false),
+                (200, 0x6, false)
+            ]
+        );
+
+        queue.apply(109, &mut keyboard).unwrap();
+        assert!(keyboard.is_key_pressed(0x5).unwrap());
+        queue.apply(110, &mut keyboard).unwrap();
+        assert!(!keyboard.is_key_pressed(0x5).unwrap());
+    }
+
+    #[test]
+    fn a_key_pressed_again_waits_for_its_held_back_release() {
+        let mut queue = InputQueue::new();
+        queue.set_minimum_hold(100);
+        queue.push(10, 0x7, true).unwrap();
+        queue.push(12, 0x7, false).unwrap();
+        queue.push(14, 0x7, true).unwrap();
+
+        assert_eq!(
+            cycles(&queue),
+            [(10, 0x7, true), (110, 0x7, false), (110, 0x7, true)]
+        );
+    }
+
+    #[test]
+    fn pads_and_keys_are_checked() {
+        let mut queue = InputQueue::new();
+
+        assert!(matches!(
+            queue.push(0, 16, true),
+            Err(KeyboardError::KeyOutOfBounds)
+        ));
+        assert!(matches!(
+            queue.push_on(0, NUM_PADS as u8, 0, true),
+            Err(KeyboardError::PadOutOfBounds)
+        ));
+        assert!(queue.is_empty());
+    }
+}
@@ -1,4 +1,5 @@
 const NUM_KEYS: usize = 16;
+pub const NUM_PADS: usize = 2;
 
 #[derive(Copy, Clone, PartialEq, Eq, Debug)]
 pub enum Key {
@@ -6,25 +7,50 @@ pub enum Key {
     Released,
 }
 
+// Besides the current state of each key, the keyboard remembers which keys
+// went down or up since the edges were last cleared, which the Cpu does once
+// per frame. A tap shorter than a frame therefore still shows as a press
+// followed by a release. CHIP-8X adds a second keypad, which programs can
+// only poll, so only its current state is kept.
 pub struct Keyboard {
     keys: [Key; NUM_KEYS],
+    just_pressed: [bool; NUM_KEYS],
+    just_released: [bool; NUM_KEYS],
+    second_pad: [Key; NUM_KEYS],
 }
 
 #[derive(Debug, Clone)]
 pub enum KeyboardError {
     KeyOutOfBounds,
+    PadOutOfBounds,
+}
+
+impl Default for Keyboard {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Keyboard {
     pub fn new() -> Keyboard {
         Keyboard {
-            keys: [Key::Released; 16],
+            keys: [Key::Released; NUM_KEYS],
+            just_pressed: [false; NUM_KEYS],
+            just_released: [false; NUM_KEYS],
+            second_pad: [Key::Released; NUM_KEYS],
         }
     }
 
-    fn _modify_key(&mut self, key: u8, state: Key) -> Result<(), KeyboardError> {
+    fn modify_key(&mut self, key: u8, state: Key) -> Result<(), KeyboardError> {
         match self.keys.get_mut(key as usize) {
             Some(k) => {
+                if *k != state {
+                    match state {
+                        Key::Pressed => self.just_pressed[key as usize] = true,
+                        Key::Released => self.just_released[key as usize] = true,
+                    }
+                }
+
                 *k = state;
                 Ok(())
             }
@@ -32,12 +58,12 @@ impl Keyboard {
         }
     }
 
-    pub fn _press_key(&mut self, key: u8) -> Result<(), KeyboardError> {
-        self._modify_key(key, Key::Pressed)
+    pub fn press_key(&mut self, key: u8) -> Result<(), KeyboardError> {
+        self.modify_key(key, Key::Pressed)
     }
 
-    pub fn _release_key(&mut self, key: u8) -> Result<(), KeyboardError> {
-        self._modify_key(key, Key::Released)
+    pub fn release_key(&mut self, key: u8) -> Result<(), KeyboardError> {
+        self.modify_key(key, Key::Released)
     }
 
     pub fn is_key_pressed(&self, key: u8) -> Result<bool, KeyboardError> {
@@ -47,6 +73,34 @@ impl Keyboard {
         }
     }
 
+    // Pad 0 is the usual keypad and pad 1 the CHIP-8X second keypad.
+    pub fn set_key_on(&mut self, pad: u8, key: u8, pressed: bool) -> Result<(), KeyboardError> {
+        let state = if pressed { Key::Pressed } else { Key::Released };
+
+        match pad {
+            0 => self.modify_key(key, state),
+            1 => {
+                *self
+                    .second_pad
+                    .get_mut(key as usize)
+                    .ok_or(KeyboardError::KeyOutOfBounds)? = state;
+                Ok(())
+            }
+            _ => Err(KeyboardError::PadOutOfBounds),
+        }
+    }
+
+    pub fn is_key_pressed_on(&self, pad: u8, key: u8) -> Result<bool, KeyboardError> {
+        match pad {
+            0 => self.is_key_pressed(key),
+            1 => match self.second_pad.get(key as usize) {
+                Some(&key_state) => Ok(key_state == Key::Pressed),
+                None => Err(KeyboardError::KeyOutOfBounds),
+            },
+            _ => Err(KeyboardError::PadOutOfBounds),
+        }
+    }
+
     pub fn get_pressed_key(&self) -> Option<u8> {
         for (i, &key) in self.keys.iter().enumerate() {
             if key == Key::Pressed {
@@ -56,4 +110,39 @@ impl Keyboard {
 
         None
     }
+
+    pub fn is_key_just_pressed(&self, key: u8) -> Result<bool, KeyboardError> {
+        self.just_pressed
+            .get(key as usize)
+            .copied()
+            .ok_or(KeyboardError::KeyOutOfBounds)
+    }
+
+    pub fn is_key_just_released(&self, key: u8) -> Result<bool, KeyboardError> {
+        self.just_released
+            .get(key as usize)
+            .copied()
+            .ok_or(KeyboardError::KeyOutOfBounds)
+    }
+
+    pub fn get_just_pressed_key(&self) -> Option<u8> {
+        self.just_pressed
+            .iter()
+            .position(|&pressed| pressed)
+            .map(|key| key as u8)
+    }
+
+    // Like get_just_pressed_key, but clears the edge so the same press
+    // cannot be seen twice.
+    pub fn take_just_pressed_key(&mut self) -> Option<u8> {
+        let key = self.get_just_pressed_key()?;
+        self.just_pressed[key as usize] = false;
+
+        Some(key)
+    }
+
+    pub fn clear_edges(&mut self) {
+        self.just_pressed = [false; NUM_KEYS];
+        self.just_released = [false; NUM_KEYS];
+    }
 }
@@ -0,0 +1,136 @@
+pub const KEYMAP_NAMES: [&str; 3] = ["qwerty", "azerty", "colemak"];
+
+// Host keys for the CHIP-8X second keypad, CHIP-8 keys 0 through F in order:
+// the digits on the numeric keypad, then the keys around them.
+const SECOND_PAD_KEYS: [&str; 16] = [
+    "Keypad 0",
+    "Keypad 1",
+    "Keypad 2",
+    "Keypad 3",
+    "Keypad 4",
+    "Keypad 5",
+    "Keypad 6",
+    "Keypad 7",
+    "Keypad 8",
+    "Keypad 9",
+    "Keypad /",
+    "Keypad *",
+    "Keypad -",
+    "Keypad +",
+    "Keypad Enter",
+    "Keypad .",
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KeymapError {
+    UnknownKeymap(String),
+    DuplicateKey(char),
+}
+
+// keys[n] is the host key for CHIP-8 key n. The presets place the hex keypad
+//
+//   1 2 3 C
+//   4 5 6 D
+//   7 8 9 E
+//   A 0 B F
+//
+// on the 4x4 block of keys starting at 1 on the given layout.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Keymap {
+    keys: [char; 16],
+    bindings: Vec<(String, u8)>,
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        Keymap::named("qwerty").unwrap()
+    }
+}
+
+impl Keymap {
+    pub fn named(name: &str) -> Result<Keymap, KeymapError> {
+        let keys = match name {
+            "qwerty" => "x123qweasdzc4rfv",
+            "azerty" => "x123azeqsdwc4rfv",
+            "colemak" => "x123qwfarszc4ptv",
+            _ => return Err(KeymapError::UnknownKeymap(name.to_string())),
+        };
+
+        Keymap::from_keys(keys)
+    }
+
+    // Accepts either a built-in layout name or 16 characters giving the host
+    // key for CHIP-8 keys 0 through F in order.
+    pub fn parse(text: &str) -> Result<Keymap, KeymapError> {
+        if KEYMAP_NAMES.contains(&text) {
+            return Keymap::named(text);
+        }
+
+        if text.chars().count() != 16 {
+            return Err(KeymapError::UnknownKeymap(text.to_string()));
+        }
+
+        Keymap::from_keys(text)
+    }
+
+    fn from_keys(text: &str) -> Result<Keymap, KeymapError> {
+        let mut keys = [' '; 16];
+
+        for (i, key) in text.chars().map(|c| c.to_ascii_lowercase()).enumerate() {
+            if keys[..i].contains(&key) {
+                return Err(KeymapError::DuplicateKey(key));
+            }
+            keys[i] = key;
+        }
+
+        Ok(Keymap {
+            keys,
+            bindings: Vec::new(),
+        })
+    }
+
+    // The numeric keypad layout used for the second keypad.
+    pub fn second_pad() -> Keymap {
+        Keymap {
+            keys: ['\0'; 16],
+            bindings: SECOND_PAD_KEYS
+                .iter()
+                .enumerate()
+                .map(|(key, &name)| (name.to_string(), key as u8))
+                .collect(),
+        }
+    }
+
+    // Binds extra host keys by name, such as "Up" or "Space". These take
+    // precedence over the layout.
+    pub fn with_bindings(mut self, bindings: &[(String, u8)]) -> Keymap {
+        self.bindings.extend_from_slice(bindings);
+        self
+    }
+
+    // Maps a host key name such as "Q" or "1" to a CHIP-8 key.
+    pub fn lookup(&self, name: &str) -> Option<u8> {
+        if let Some(&(_, key)) = self
+            .bindings
+            .iter()
+            .find(|(binding, _)| binding.eq_ignore_ascii_case(name))
+        {
+            return Some(key & 0xF);
+        }
+
+        let mut chars = name.chars();
+
+        match (chars.next(), chars.next()) {
+            (Some(key), None) => self
+                .keys
+                .iter()
+                .position(|&k| k == key.to_ascii_lowercase())
+                .map(|i| i as u8),
+            _ => None,
+        }
+    }
+
+    pub fn host_key(&self, key: u8) -> char {
+        self.keys[key as usize & 0xF]
+    }
+}
@@ -0,0 +1,33 @@
+pub mod analysis;
+pub mod cdp1802;
+pub mod color_layer;
+pub mod config;
+pub mod coverage;
+pub mod cpu;
+pub mod database;
+pub mod debugger;
+pub mod display_filter;
+pub mod gamepad;
+pub mod gdb;
+pub mod input_log;
+pub mod input_queue;
+pub mod keyboard;
+pub mod keymap;
+pub mod memory;
+pub mod octo;
+pub mod opcode;
+pub mod palette;
+pub mod platform;
+pub mod profiler;
+pub mod quirks;
+pub mod recording;
+pub mod registers;
+pub mod rom_source;
+pub mod screen;
+pub mod stack;
+pub mod timers;
+pub mod timestamp;
+pub mod timing;
+pub mod trace;
+pub mod vip;
+pub mod watcher;
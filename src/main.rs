@@ -1,42 +1,908 @@
 extern crate sdl2;
 
-mod cpu;
+use std::io::{BufRead, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::time::{Duration, Instant};
+
+use clap::Parser;
+
+use chip_8_emulator::config::{rom_sha1, Config, ConfigError};
+use chip_8_emulator::coverage::{Coverage, SourceMap};
+use chip_8_emulator::cpu::{Cpu, SaveState, PROGRAM_START};
+use chip_8_emulator::database::{Database, Entry};
+use chip_8_emulator::display_filter::DisplayFilter;
+use chip_8_emulator::gamepad::GamepadMap;
+use chip_8_emulator::input_log::InputLog;
+use chip_8_emulator::keymap::Keymap;
+use chip_8_emulator::palette::Palette;
+use chip_8_emulator::platform::Platform;
+use chip_8_emulator::recording::Recorder;
+use chip_8_emulator::rom_source::{RomError, RomSource};
+use chip_8_emulator::timestamp;
+use chip_8_emulator::trace::Tracer;
+use chip_8_emulator::vip::Vip;
+use chip_8_emulator::watcher::FileWatcher;
+use chip_8_emulator::{debugger, gdb};
+
+mod audio;
+mod cli;
+mod controllers;
 mod graphics;
-mod keyboard;
-mod memory;
-mod opcode;
-mod registers;
-mod screen;
-mod stack;
-mod timers;
 
-pub fn main() {
-    let filename = std::env::args()
-        .nth(1)
-        .expect("Usage: cargo run <filename>");
+const SCREENSHOT_SCALE: u32 = 8;
+// Database platforms whose programs only use features this emulator has.
+const SUPPORTED_PLATFORMS: [&str; 4] = ["originalChip8", "hybridVIP", "modernChip8", "chip8x"];
+const FRAME_DURATION: Duration = Duration::from_nanos(1_000_000_000 / 60);
+
+// State shared by the windowed and headless frame loops.
+struct Session {
+    cpu: Cpu,
+    frame: u64,
+    recorder: Recorder,
+    // The display filter and palette frames are shown, recorded and saved
+    // with, and the last frame as they made it.
+    filter: DisplayFilter,
+    palette: Palette,
+    pixels: Vec<u8>,
+    input_recording: Option<InputLog>,
+    replay: Option<InputLog>,
+    rom_source: RomSource,
+    save_state: Option<SaveState>,
+    source_map: Option<SourceMap>,
+    // Kept to look the ROM's settings up again when it is reloaded.
+    config_path: Option<PathBuf>,
+    config: Config,
+    database: Option<Database>,
+}
+
+impl Session {
+    // Applies replayed input, then emulates and records one frame.
+    fn run_frame(&mut self) -> Result<(), String> {
+        if let Some(replay) = self.replay.as_mut() {
+            for event in replay.events_for_frame(self.frame) {
+                self.cpu
+                    .queue_key_on(event.pad, event.key, event.pressed, event.offset)
+                    .map_err(|error| format!("invalid replayed key: {:?}", error))?;
+            }
+        }
+
+        self.cpu
+            .emulate_frame()
+            .map_err(|error| format!("emulation failed: {:?}", error))?;
+
+        let screen = &self.cpu.screen;
+        self.pixels = screen.get_filtered_pixels(&self.filter.apply(screen), &self.palette);
+
+        if let Err(error) = self
+            .recorder
+            .record_frame(&self.pixels, self.cpu.sound_active())
+        {
+            println!("Recording failed, stopping: {:?}", error);
+            // Close what was written so far. The recording has already
+            // failed, so a second error is not worth reporting.
+            let _ = std::mem::take(&mut self.recorder).finish();
+        }
+
+        self.frame += 1;
+
+        Ok(())
+    }
+
+    // Queues a key change `offset` instructions into the next frame. Live
+    // key presses are ignored while a replay is running so they cannot
+    // desynchronise it.
+    fn set_key(&mut self, pad: u8, key: u8, pressed: bool, offset: u64) {
+        if self.replay.is_some() {
+            return;
+        }
+
+        if self.cpu.queue_key_on(pad, key, pressed, offset).is_ok() {
+            if let Some(log) = self.input_recording.as_mut() {
+                log.record(self.frame, offset, pad, key, pressed);
+            }
+        }
+    }
+
+    // Loads the current image of the ROM into a freshly reset machine, or on
+    // top of the last save state when asked to. A changed image has a new
+    // SHA-1, so its settings are looked up again and returned.
+    fn reload_rom(&mut self, args: &cli::Args) -> Result<cli::Options, String> {
+        let rom = read_rom_source(&self.rom_source, self.cpu.start_address())?;
+        let options = resolve_options(args, &self.config, self.database.as_ref(), &rom)?;
+        let start_address = options
+            .start_address
+            .unwrap_or(options.platform.load_address());
+
+        let kept = match args.keep_memory {
+            Some((start, end)) => Some(
+                self.cpu
+                    .memory()
+                    .get_bytes(start, end - start + 1)
+                    .map_err(|error| format!("cannot keep memory: {:?}", error))?,
+            ),
+            None => None,
+        };
+
+        match self.save_state.as_ref().filter(|_| args.restore_state) {
+            Some(state) => self.cpu.load_state_with_rom(state, &rom, start_address),
+            None => {
+                if self.cpu.platform() != options.platform {
+                    self.cpu.set_platform(options.platform);
+                }
+
+                self.cpu.reload_rom(&rom, start_address)
+            }
+        }
+        .map_err(|error| format!("cannot load ROM: {:?}", error))?;
+
+        apply_options(&mut self.cpu, args, &options);
+
+        if let (Some((start, _)), Some(kept)) = (args.keep_memory, kept) {
+            self.cpu
+                .memory_mut()
+                .set_bytes(start, &kept)
+                .map_err(|error| format!("cannot keep memory: {:?}", error))?;
+        }
+
+        Ok(options)
+    }
+}
+
+pub fn main() -> ExitCode {
+    let args = cli::Args::parse();
+
+    let (mut session, options) = match start(&args) {
+        Ok(started) => started,
+        Err(error) => {
+            eprintln!("error: {}", error);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let result = if args.debug {
+        debugger::run(&mut session.cpu, &options.keymap).map_err(|error| error.to_string())
+    } else if let Some(port) = args.gdb {
+        gdb::serve(&mut session.cpu, port).map_err(|error| error.to_string())
+    } else if args.headless {
+        run_headless(&mut session, &args)
+    } else {
+        run_window(&mut session, &args, options)
+    };
+
+    let errors: Vec<String> = result
+        .err()
+        .into_iter()
+        .chain(finish(session, &args))
+        .collect();
+
+    for error in &errors {
+        eprintln!("error: {}", error);
+    }
+
+    if errors.is_empty() {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
+}
+
+fn config_error_reason(error: ConfigError) -> String {
+    match error {
+        ConfigError::IoError(error) => error.to_string(),
+        ConfigError::ParseError(error) => error.to_string(),
+        ConfigError::EditError(error) => error.to_string(),
+    }
+}
+
+// The config and the file it came from, which settings changed while running
+// are saved back to.
+fn load_config(args: &cli::Args) -> Result<(Option<PathBuf>, Config), String> {
+    let (path, result) = match (&args.config, Config::default_path()) {
+        (Some(filename), _) => (PathBuf::from(filename), Config::load(Path::new(filename))),
+        (None, Some(path)) => {
+            let result = Config::load_or_default(&path);
+            (path, result)
+        }
+        (None, None) => return Ok((None, Config::default())),
+    };
+
+    match result {
+        Ok(config) => Ok((Some(path), config)),
+        Err(error) => Err(format!(
+            "cannot read config '{}': {}",
+            path.display(),
+            config_error_reason(error)
+        )),
+    }
+}
+
+fn load_database(args: &cli::Args) -> Result<Option<Database>, String> {
+    if args.no_database {
+        return Ok(None);
+    }
+
+    match &args.database {
+        Some(directory) => Database::load(Path::new(directory))
+            .map(Some)
+            .map_err(|error| format!("cannot read database '{}': {:?}", directory, error)),
+        None => Ok(Some(Database::builtin())),
+    }
+}
+
+// Asks which ROM to run when an archive holds several.
+fn pick_entry(entries: &[String]) -> Option<String> {
+    if !std::io::stdin().is_terminal() {
+        return None;
+    }
+
+    for (number, entry) in entries.iter().enumerate() {
+        println!("{:3}) {}", number + 1, entry);
+    }
+
+    loop {
+        print!("Select a ROM [1-{}]: ", entries.len());
+        std::io::stdout().flush().ok()?;
+
+        let mut line = String::new();
+        if std::io::stdin().lock().read_line(&mut line).ok()? == 0 {
+            return None;
+        }
+
+        match line.trim().parse::<usize>() {
+            Ok(number) if (1..=entries.len()).contains(&number) => {
+                return Some(entries[number - 1].clone())
+            }
+            _ => println!("Enter a number between 1 and {}", entries.len()),
+        }
+    }
+}
+
+fn read_rom_source(source: &RomSource, address: u16) -> Result<Vec<u8>, String> {
+    source.read(address).map_err(|error| {
+        let reason = match error {
+            RomError::IoError(error) => error.to_string(),
+            RomError::ZipError(error) => error.to_string(),
+            RomError::InvalidHex { line, token } => {
+                format!("invalid hex '{}' on line {}", token, line)
+            }
+            RomError::NoRomInArchive => "archive holds no files".to_string(),
+            RomError::MultipleRomsInArchive(entries) => format!(
+                "archive holds several ROMs, choose one with --entry: {}",
+                entries.join(", ")
+            ),
+            RomError::EntryNotFound(entry) => format!("archive has no entry '{}'", entry),
+            RomError::GifError(error) => error.to_string(),
+            RomError::NotAGif => "file is named .gif but is not a GIF image".to_string(),
+            RomError::NotACartridge => "GIF image is not an Octo cartridge".to_string(),
+            RomError::AssemblyError(error) => {
+                format!(
+                    "{} on line {} of the Octo source",
+                    error.message, error.line
+                )
+            }
+            RomError::Empty => "ROM is empty".to_string(),
+            RomError::TooLarge { size, available } => format!(
+                "ROM is {} bytes but only {} bytes are free from 0x{:03X}",
+                size, available, address
+            ),
+        };
+
+        format!("cannot load ROM '{}': {}", source.name(), reason)
+    })
+}
+
+fn read_rom(args: &cli::Args) -> Result<(RomSource, Vec<u8>), String> {
+    let mut source = RomSource::from_argument(&args.rom);
+
+    if let Some(entry) = &args.entry {
+        source = source.with_entry(entry);
+    }
+
+    if let RomSource::Zip { path, entry: None } = &source {
+        if let Ok(entries) = RomSource::zip_entries(path) {
+            if entries.len() > 1 {
+                if let Some(entry) = pick_entry(&entries) {
+                    source = source.with_entry(&entry);
+                }
+            }
+        }
+    }
+
+    // The platform is not known yet, so only the usual address is checked.
+    let rom = read_rom_source(&source, args.start_address.unwrap_or(PROGRAM_START))?;
 
-    let mut graphics = match graphics::Graphics::new("Chip-8", 1024, 512) {
-        Ok(graphics) => graphics,
-        Err(e) => {
-            panic!("{}", e);
+    Ok((source, rom))
+}
+
+// The settings for `rom`: the command line, then the ROM's section of the
+// config file, then its database entry, then the config file's defaults.
+fn resolve_options(
+    args: &cli::Args,
+    config: &Config,
+    database: Option<&Database>,
+    rom: &[u8],
+) -> Result<cli::Options, String> {
+    let entry = database.and_then(|database| database.lookup(rom));
+
+    let known = entry.as_ref().map(Entry::settings).unwrap_or_default();
+    let mut options = cli::Options::resolve(args, &config.settings_for(rom, &known))?;
+
+    if let Some(entry) = &entry {
+        options.title = Some(entry.program.title.clone());
+        options.keymap = options.keymap.with_bindings(&entry.key_bindings());
+
+        match entry.platform {
+            Some(platform) => {
+                println!("{} ({})", entry.program.title, platform.name);
+
+                if !SUPPORTED_PLATFORMS.contains(&platform.id.as_str()) {
+                    println!("Warning: {} programs may not run correctly", platform.name);
+                }
+            }
+            None => println!("{}", entry.program.title),
         }
+    }
+
+    Ok(options)
+}
+
+// Applies the settings that can change without reloading the ROM.
+fn apply_options(cpu: &mut Cpu, args: &cli::Args, options: &cli::Options) {
+    cpu.set_instructions_per_frame(options.ips / 60);
+    cpu.input_queue_mut()
+        .set_latency(args.input_latency * options.ips as u64 / 1000);
+    cpu.input_queue_mut()
+        .set_minimum_hold(args.minimum_hold * options.ips as u64 / 1000);
+    cpu.set_quirks(options.quirks);
+    cpu.set_timing(options.timing);
+}
+
+fn start(args: &cli::Args) -> Result<(Session, cli::Options), String> {
+    let mut cpu = Cpu::new();
+
+    let (rom_source, rom) = read_rom(args)?;
+
+    let (config_path, config) = load_config(args)?;
+    let database = load_database(args)?;
+    let options = resolve_options(args, &config, database.as_ref(), &rom)?;
+
+    apply_options(&mut cpu, args, &options);
+    cpu.set_platform(options.platform);
+    cpu.load_rom_bytes_at(
+        &rom,
+        options
+            .start_address
+            .unwrap_or(options.platform.load_address()),
+    )
+    .map_err(|_| format!("ROM '{}' does not fit in memory", args.rom))?;
+
+    if let Some(filename) = &args.vip {
+        let read = |filename: &String| {
+            std::fs::read(filename)
+                .map_err(|error| format!("cannot read '{}': {}", filename, error))
+        };
+        let interpreter = read(filename)?;
+        let monitor = args.vip_monitor.as_ref().map(read).transpose()?;
+
+        let vip = Vip::new(&interpreter, monitor.as_deref())
+            .map_err(|error| format!("cannot emulate the VIP: {:?}", error))?;
+        cpu.enable_vip(vip)
+            .map_err(|error| format!("cannot load '{}': {:?}", filename, error))?;
+    }
+
+    let replay = match &args.replay {
+        Some(filename) => Some(
+            InputLog::load(filename)
+                .map_err(|error| format!("cannot read replay '{}': {:?}", filename, error))?,
+        ),
+        None => None,
+    };
+
+    // Recordings need a known seed to be replayable, so pick one up front.
+    let seed = match &replay {
+        Some(replay) => Some(replay.get_seed()),
+        None if args.record.is_some() => Some(args.seed.unwrap_or_else(rand::random)),
+        None => args.seed,
     };
 
-    let mut cpu = cpu::Cpu::new();
+    if let Some(seed) = seed {
+        cpu.set_seed(seed);
+    }
+
+    if let Some(trace) = &args.trace {
+        let mut tracer = match trace.as_str() {
+            "-" => Tracer::to_stdout(),
+            filename => Tracer::to_file(filename)
+                .map_err(|error| format!("cannot create trace '{}': {}", filename, error))?,
+        };
 
-    cpu.load_rom(&filename).expect("Failed to load ROM");
+        if let Some((start, end)) = args.trace_addresses {
+            tracer = tracer.with_address_range(start..=end);
+        }
 
-    'running: loop {
-        cpu.emulate_cycle().expect("Failed to emulate cycle");
+        if let Some((from, to)) = args.trace_cycles {
+            tracer = tracer.with_cycle_range(from..=to);
+        }
+
+        if let Some(format) = &args.trace_format {
+            tracer = tracer.with_format(format.clone());
+        }
+
+        cpu.enable_tracer(tracer);
+    }
+
+    if args.profile.is_some() || args.profile_folded.is_some() {
+        cpu.enable_profiler();
+    }
+
+    if let Some(filename) = &args.coverage {
+        let coverage = Coverage::load_or_new(filename)
+            .map_err(|error| format!("cannot read coverage '{}': {:?}", filename, error))?;
+        cpu.enable_coverage(coverage);
+    }
+
+    let source_map = match &args.source_map {
+        Some(filename) => Some(
+            SourceMap::load(filename)
+                .map_err(|error| format!("cannot read source map '{}': {:?}", filename, error))?,
+        ),
+        None => None,
+    };
+
+    let mut recorder = Recorder::new();
+
+    if let Some(filename) = &args.gif {
+        recorder
+            .start_gif(filename, &cpu.screen, &options.palette)
+            .map_err(|error| format!("cannot record GIF '{}': {:?}", filename, error))?;
+    }
+
+    if let Some(prefix) = &args.video {
+        recorder
+            .start_video(prefix, &cpu.screen)
+            .map_err(|error| format!("cannot record video '{}': {:?}", prefix, error))?;
+    }
+
+    let pixels = cpu.screen.get_pixels(&options.palette);
+
+    let session = Session {
+        cpu,
+        frame: 0,
+        recorder,
+        filter: DisplayFilter::new(options.filter),
+        palette: options.palette.clone(),
+        pixels,
+        input_recording: seed.filter(|_| args.record.is_some()).map(InputLog::new),
+        replay,
+        rom_source,
+        save_state: None,
+        source_map,
+        config_path,
+        config,
+        database,
+    };
+
+    Ok((session, options))
+}
+
+fn run_headless(session: &mut Session, args: &cli::Args) -> Result<(), String> {
+    while args.frames.is_none_or(|frames| session.frame < frames) {
+        session.run_frame()?;
+    }
+
+    if let Some(filename) = &args.screenshot {
+        session
+            .cpu
+            .screen
+            .save_png(filename, &session.pixels, SCREENSHOT_SCALE)
+            .map_err(|error| format!("cannot write '{}': {:?}", filename, error))?;
+    }
+
+    Ok(())
+}
+
+fn run_window(
+    session: &mut Session,
+    args: &cli::Args,
+    mut options: cli::Options,
+) -> Result<(), String> {
+    let sdl_context = sdl2::init()?;
+
+    let window_options = graphics::WindowOptions {
+        scale: options.scale,
+        resizable: !options.fixed_size,
+        fullscreen: options.fullscreen,
+        grid: options.grid,
+        keypad: args.keypad,
+    };
+
+    let mut title = window_title(&options);
+
+    let mut graphics = graphics::Graphics::new(
+        &sdl_context,
+        &title,
+        &window_options,
+        session.cpu.screen.get_width() as u32,
+        session.cpu.screen.get_height() as u32,
+    )?;
+
+    let mut audio = if options.mute {
+        None
+    } else {
+        match audio::Audio::new(&sdl_context, options.volume) {
+            Ok(audio) => Some(audio),
+            Err(error) => {
+                println!("Sound disabled: {}", error);
+                None
+            }
+        }
+    };
+
+    let mut second_pad = second_pad_keymap(&options);
+
+    let mut controllers = match controllers::Controllers::new(&sdl_context) {
+        Ok(controllers) => Some(controllers),
+        Err(error) => {
+            println!("Controllers disabled: {}", error);
+            None
+        }
+    };
+    let mut gamepad = options.gamepad.clone();
+    let mut editing_key = None;
+
+    let mut next_frame = Instant::now();
+
+    let timer = sdl_context.timer()?;
+    let mut last_poll_time = timer.ticks();
+
+    let mut watcher = match (args.watch, session.rom_source.path()) {
+        (true, Some(path)) => Some(FileWatcher::new(path)),
+        (true, None) => {
+            println!("Cannot watch a ROM read from stdin");
+            None
+        }
+        (false, _) => None,
+    };
+
+    'running: while args.frames.is_none_or(|frames| session.frame < frames) {
+        let poll_time = timer.ticks();
+
+        while let Some(event) = graphics.handle_events() {
+            let offset = frame_offset(
+                graphics.event_timestamp(),
+                last_poll_time,
+                poll_time,
+                session.cpu.get_instructions_per_frame(),
+            );
 
-        if let Some(event) = graphics.handle_events() {
             match event {
                 graphics::Event::Quit => break 'running,
+                graphics::Event::KeyDown(name) => {
+                    if let Some(key) = options.keymap.lookup(&name) {
+                        session.set_key(0, key, true, offset);
+                    } else if let Some(key) = second_pad.as_ref().and_then(|pad| pad.lookup(&name))
+                    {
+                        session.set_key(1, key, true, offset);
+                    }
+                }
+                graphics::Event::KeyUp(name) => {
+                    if let Some(key) = options.keymap.lookup(&name) {
+                        session.set_key(0, key, false, offset);
+                    } else if let Some(key) = second_pad.as_ref().and_then(|pad| pad.lookup(&name))
+                    {
+                        session.set_key(1, key, false, offset);
+                    }
+                }
+                graphics::Event::ControllerAdded(index) => {
+                    if let Some(controllers) = controllers.as_mut() {
+                        match controllers.add(index) {
+                            Ok(name) => println!("Controller connected: {}", name),
+                            Err(error) => println!("Cannot open controller: {}", error),
+                        }
+                    }
+                }
+                graphics::Event::ControllerRemoved(id) => {
+                    if let Some(name) = controllers
+                        .as_mut()
+                        .and_then(|controllers| controllers.remove(id))
+                    {
+                        println!("Controller disconnected: {}", name);
+                    }
+                }
+                graphics::Event::ButtonDown(button) => match editing_key {
+                    Some(key) => {
+                        gamepad.bind(&button, key);
+                        editing_key = edit_gamepad(editing_key, &mut graphics, &gamepad, session);
+                    }
+                    None => {
+                        if let Some(key) = gamepad.lookup(&button) {
+                            session.set_key(0, key, true, offset);
+                        }
+                    }
+                },
+                graphics::Event::ButtonUp(button) => {
+                    if let Some(key) = gamepad.lookup(&button) {
+                        session.set_key(0, key, false, offset);
+                    }
+                }
+                graphics::Event::EditGamepad => {
+                    editing_key = edit_gamepad(editing_key, &mut graphics, &gamepad, session);
+                }
+                graphics::Event::CycleFilter => {
+                    let filter = &mut session.filter;
+                    filter.set_mode(filter.get_mode().next());
+                }
+                graphics::Event::CyclePalette => session.palette = session.palette.next(),
+                graphics::Event::ToggleFullscreen => graphics.toggle_fullscreen()?,
+                graphics::Event::ToggleGrid => graphics.toggle_grid(),
+                graphics::Event::ToggleKeypad => graphics.toggle_keypad(),
+                graphics::Event::KeypadDown(key) => session.set_key(0, key, true, offset),
+                graphics::Event::KeypadUp(key) => session.set_key(0, key, false, offset),
+                graphics::Event::Reset => session.cpu.reset(),
+                graphics::Event::PowerCycle => {
+                    if let Err(error) = session.cpu.power_cycle() {
+                        println!("Power cycle failed: {:?}", error);
+                    }
+                }
+                graphics::Event::SaveState => {
+                    session.save_state = Some(session.cpu.save_state());
+                    println!("Saved state");
+                }
+                graphics::Event::LoadState => match &session.save_state {
+                    Some(state) => {
+                        session.cpu.load_state(state);
+                        println!("Loaded state");
+                    }
+                    None => println!("No state saved yet, press F5 to save one"),
+                },
+                graphics::Event::Screenshot => {
+                    let filename = timestamp::timestamped_filename("chip8", "png");
+
+                    match session
+                        .cpu
+                        .screen
+                        .save_png(&filename, &session.pixels, SCREENSHOT_SCALE)
+                    {
+                        Ok(()) => println!("Saved screenshot to {}", filename),
+                        Err(error) => println!("Failed to save screenshot: {:?}", error),
+                    }
+                }
+                graphics::Event::ToggleGifRecording => {
+                    let recorder = &mut session.recorder;
+
+                    if recorder.is_recording_gif() {
+                        match recorder.stop_gif() {
+                            Ok(()) => println!("Stopped GIF recording"),
+                            Err(error) => println!("Failed to finish GIF: {:?}", error),
+                        }
+                    } else {
+                        let filename = timestamp::timestamped_filename("chip8", "gif");
+
+                        match recorder.start_gif(&filename, &session.cpu.screen, &session.palette) {
+                            Ok(()) => println!("Recording GIF to {}", filename),
+                            Err(error) => println!("Failed to start GIF recording: {:?}", error),
+                        }
+                    }
+                }
+                graphics::Event::ToggleVideoRecording => {
+                    let recorder = &mut session.recorder;
+
+                    if recorder.is_recording_video() {
+                        match recorder.stop_video() {
+                            Ok(()) => println!("Stopped video recording"),
+                            Err(error) => println!("Failed to finish video: {:?}", error),
+                        }
+                    } else {
+                        let prefix = format!("chip8-{}", timestamp::timestamp());
+
+                        match recorder.start_video(&prefix, &session.cpu.screen) {
+                            Ok(()) => {
+                                println!("Recording video to {}.y4m and {}.wav", prefix, prefix)
+                            }
+                            Err(error) => println!("Failed to start video recording: {:?}", error),
+                        }
+                    }
+                }
             }
         }
 
-        let pixels = cpu.screen.get_pixels();
+        last_poll_time = poll_time;
+
+        if watcher.as_mut().is_some_and(FileWatcher::has_changed) {
+            match session.reload_rom(args) {
+                Ok(reloaded) => {
+                    println!("Reloaded {}", session.rom_source.name());
+
+                    options = reloaded;
+                    title = window_title(&options);
+                    second_pad = second_pad_keymap(&options);
+                    gamepad = options.gamepad.clone();
+                    session.palette = options.palette.clone();
+                    session.filter.set_mode(options.filter);
+                    editing_key = None;
+                    graphics.set_gamepad_editor(None);
+                    graphics.set_title(&title)?;
+                }
+                Err(error) => println!("Reload failed: {}", error),
+            }
+        }
+
+        session.run_frame()?;
+
+        if let Some(audio) = audio.as_mut() {
+            audio.set_playing(session.cpu.sound_active());
+        }
 
-        graphics.render(&pixels).expect("Failed to render");
+        let screen = &session.cpu.screen;
+
+        graphics.render(
+            &session.pixels,
+            screen.get_width() as u32,
+            screen.get_height() as u32,
+            session.cpu.keyboard(),
+        )?;
+
+        next_frame += FRAME_DURATION;
+        match next_frame.checked_duration_since(Instant::now()) {
+            Some(remaining) => std::thread::sleep(remaining),
+            None => next_frame = Instant::now(),
+        }
+    }
+
+    Ok(())
+}
+
+fn window_title(options: &cli::Options) -> String {
+    match &options.title {
+        Some(title) => format!("{} - Chip-8", title),
+        None => "Chip-8".to_string(),
+    }
+}
+
+// CHIP-8X's second keypad sits on the numeric keypad.
+fn second_pad_keymap(options: &cli::Options) -> Option<Keymap> {
+    (options.platform == Platform::Chip8X).then(Keymap::second_pad)
+}
+
+// Input is only polled once per frame, so events that happened while the
+// previous frame was shown are spread over the next frame in proportion to
+// when they happened between the two polls.
+fn frame_offset(timestamp: u32, start: u32, end: u32, instructions_per_frame: u32) -> u64 {
+    if end <= start {
+        return 0;
+    }
+
+    let elapsed = (timestamp.clamp(start, end) - start) as u64;
+    let offset = elapsed * instructions_per_frame as u64 / (end - start) as u64;
+
+    offset.min(instructions_per_frame as u64 - 1)
+}
+
+// Moves the controller mapping editor on to the next CHIP-8 key, starting it
+// if it is not running. After key F the mapping is saved to the ROM's section
+// of the config file.
+fn edit_gamepad(
+    editing_key: Option<u8>,
+    graphics: &mut graphics::Graphics,
+    gamepad: &GamepadMap,
+    session: &mut Session,
+) -> Option<u8> {
+    let next_key = match editing_key {
+        None => Some(0),
+        Some(0xF) => None,
+        Some(key) => Some(key + 1),
+    };
+
+    graphics.set_gamepad_editor(next_key.map(|key| (gamepad, key)));
+
+    if next_key.is_none() {
+        save_gamepad(session, gamepad);
+    }
+
+    next_key
+}
+
+fn save_gamepad(session: &mut Session, gamepad: &GamepadMap) {
+    let sha1 = rom_sha1(session.cpu.rom());
+    let mapping = gamepad.to_string();
+
+    let Some(path) = session.config_path.clone() else {
+        println!("No config file to save the controller mapping to, add it yourself:");
+        println!("[rom.{}]", sha1);
+        println!("gamepad = \"{}\"", mapping);
+        return;
+    };
+
+    match session
+        .config
+        .save_rom_setting(&path, &sha1, "gamepad", &mapping)
+    {
+        Ok(()) => println!("Saved controller mapping to {}", path.display()),
+        Err(error) => println!(
+            "Cannot save controller mapping to '{}': {}",
+            path.display(),
+            config_error_reason(error)
+        ),
     }
 }
+
+// Writes every requested output, carrying on past failures so one bad path
+// does not lose the others.
+fn finish(mut session: Session, args: &cli::Args) -> Vec<String> {
+    let mut errors = Vec::new();
+
+    if let Err(error) = session.recorder.finish() {
+        errors.push(format!("failed to finish recording: {:?}", error));
+    }
+
+    if let Some(tracer) = session.cpu.tracer_mut() {
+        if let Err(error) = tracer.flush() {
+            errors.push(format!("failed to write trace: {}", error));
+        }
+    }
+
+    if let (Some(log), Some(filename)) = (&session.input_recording, &args.record) {
+        if let Err(error) = log.save(filename) {
+            errors.push(format!("cannot write '{}': {:?}", filename, error));
+        }
+    }
+
+    if let Some(profiler) = session.cpu.profiler() {
+        if let Some(filename) = &args.profile {
+            let result = std::fs::File::create(filename)
+                .and_then(|mut file| profiler.write_report(&mut file));
+            if let Err(error) = result {
+                errors.push(format!("cannot write '{}': {}", filename, error));
+            }
+        }
+
+        if let Some(filename) = &args.profile_folded {
+            let result = std::fs::File::create(filename)
+                .and_then(|mut file| profiler.write_folded_stacks(&mut file));
+            if let Err(error) = result {
+                errors.push(format!("cannot write '{}': {}", filename, error));
+            }
+        }
+    }
+
+    if let (Some(coverage), Some(filename)) = (session.cpu.coverage(), &args.coverage) {
+        if let Err(error) = coverage.save(filename) {
+            errors.push(format!("cannot write '{}': {:?}", filename, error));
+        }
+
+        let rom = session.cpu.rom();
+        let platform = session.cpu.platform();
+        let start = session.cpu.start_address();
+        let listing_name = match &args.coverage_listing {
+            Some(filename) => filename.clone(),
+            None => format!("{}.lst", session.rom_source.name()),
+        };
+
+        if let Some(filename) = &args.coverage_listing {
+            let result = std::fs::File::create(filename)
+                .and_then(|mut file| coverage.write_listing(rom, platform, start, &mut file));
+            if let Err(error) = result {
+                errors.push(format!("cannot write '{}': {}", filename, error));
+            }
+        }
+
+        if let Some(filename) = &args.coverage_lcov {
+            let result = std::fs::File::create(filename).and_then(|mut file| {
+                coverage.write_lcov(
+                    rom,
+                    platform,
+                    start,
+                    &listing_name,
+                    session.source_map.as_ref(),
+                    &mut file,
+                )
+            });
+            if let Err(error) = result {
+                errors.push(format!("cannot write '{}': {}", filename, error));
+            }
+        }
+    }
+
+    errors
+}
@@ -1,5 +1,7 @@
-const MEMORY_SIZE: usize = 4096;
-const CHIP8_FONTSET: [u8; 80] = [
+pub const MEMORY_SIZE: usize = 4096;
+pub const FONTSET_SIZE: usize = 80;
+
+pub const CHIP8_FONTSET: [u8; FONTSET_SIZE] = [
     0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
     0x20, 0x60, 0x20, 0x20, 0x70, // 1
     0xF0, 0x10, 0xF0, 0x80, 0xF0, // 2
@@ -30,10 +32,17 @@ impl From<std::io::Error> for MemoryError {
     }
 }
 
+#[derive(Clone)]
 pub struct Memory {
     data: [u8; MEMORY_SIZE],
 }
 
+impl Default for Memory {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Memory {
     pub fn new() -> Memory {
         let mut memory = Memory {
@@ -102,21 +111,30 @@ impl Memory {
         Ok(())
     }
 
-    fn load_rom(&mut self, rom: &[u8]) -> Result<(), MemoryError> {
-        if rom.len() > MEMORY_SIZE - 0x200 {
-            return Err(MemoryError::OutOfBounds(0x200));
+    pub fn load_rom(&mut self, rom: &[u8], address: u16) -> Result<(), MemoryError> {
+        if address as usize + rom.len() > MEMORY_SIZE {
+            return Err(MemoryError::OutOfBounds(address));
         }
 
-        self.set_bytes(0x200, rom)?;
+        self.set_bytes(address, rom)?;
 
         Ok(())
     }
 
-    pub fn load_rom_from_file(&mut self, filename: &str) -> Result<(), MemoryError> {
+    // Both return the ROM bytes so callers can identify the game.
+    pub fn load_rom_from_file(&mut self, filename: &str) -> Result<Vec<u8>, MemoryError> {
+        self.load_rom_from_file_at(filename, 0x200)
+    }
+
+    pub fn load_rom_from_file_at(
+        &mut self,
+        filename: &str,
+        address: u16,
+    ) -> Result<Vec<u8>, MemoryError> {
         let rom = std::fs::read(filename)?;
 
-        self.load_rom(&rom)?;
+        self.load_rom(&rom, address)?;
 
-        Ok(())
+        Ok(rom)
     }
 }
@@ -0,0 +1,1349 @@
+use std::collections::{HashMap, VecDeque};
+
+use crate::cpu::PROGRAM_START;
+
+const ADDRESS_SPACE: usize = 0x10000;
+const BINARY_OPERATORS: [&str; 19] = [
+    "-", "+", "*", "/", "%", "pow", "min", "max", "&", "|", "^", "<<", ">>", "<", ">", "<=", ">=",
+    "==", "!=",
+];
+const UNARY_OPERATORS: [&str; 13] = [
+    "-", "~", "!", "sin", "cos", "tan", "exp", "log", "abs", "sqrt", "sign", "ceil", "floor",
+];
+
+#[derive(Debug, PartialEq)]
+pub struct AssemblyError {
+    pub line: usize,
+    pub message: String,
+}
+
+#[derive(Clone, Debug)]
+struct Token {
+    text: String,
+    // Quoted text, which is never a name or a number.
+    string: bool,
+    line: usize,
+}
+
+#[derive(Clone, Copy)]
+enum Patch {
+    // The low 12 bits of the instruction at the address.
+    Address,
+    // The two bytes at the address.
+    Long,
+    // The operands of the `v0 := ...; v1 := ...` pair :unpack emits.
+    Unpack(u8),
+    UnpackLong,
+}
+
+struct Fixup {
+    address: usize,
+    name: String,
+    patch: Patch,
+    line: usize,
+}
+
+enum Block {
+    If { jump: usize },
+    Else { jump: usize },
+    Loop { start: usize, breaks: Vec<usize> },
+}
+
+struct Macro {
+    arguments: Vec<String>,
+    body: Vec<Token>,
+}
+
+// Splits source into tokens. Comments run from '#' to the end of the line and
+// strings are quoted with '"'.
+fn tokenize(source: &str) -> Result<VecDeque<Token>, AssemblyError> {
+    let mut tokens = VecDeque::new();
+    let mut chars = source.chars().peekable();
+    let mut line = 1;
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\n' => line += 1,
+            c if c.is_whitespace() => {}
+            '#' => while chars.next_if(|&c| c != '\n').is_some() {},
+            '"' => {
+                let start = line;
+                let mut text = String::new();
+
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => text.push(match chars.next() {
+                            Some('n') => '\n',
+                            Some('r') => '\r',
+                            Some('t') => '\t',
+                            Some('v') => '\x0B',
+                            Some('0') => '\0',
+                            Some(c) => c,
+                            None => break,
+                        }),
+                        Some(c) => {
+                            line += (c == '\n') as usize;
+                            text.push(c);
+                        }
+                        None => {
+                            return Err(AssemblyError {
+                                line: start,
+                                message: "missing closing quote".to_string(),
+                            })
+                        }
+                    }
+                }
+
+                tokens.push_back(Token {
+                    text,
+                    string: true,
+                    line: start,
+                });
+            }
+            c => {
+                let mut text = c.to_string();
+                while let Some(c) = chars.next_if(|c| !c.is_whitespace()) {
+                    text.push(c);
+                }
+
+                tokens.push_back(Token {
+                    text,
+                    string: false,
+                    line,
+                });
+            }
+        }
+    }
+
+    Ok(tokens)
+}
+
+fn parse_number(text: &str) -> Option<f64> {
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, text),
+    };
+
+    let value = if let Some(hex) = digits.strip_prefix("0x") {
+        i64::from_str_radix(hex, 16).ok()? as f64
+    } else if let Some(binary) = digits.strip_prefix("0b") {
+        i64::from_str_radix(binary, 2).ok()? as f64
+    } else if digits.starts_with(|c: char| c.is_ascii_digit()) {
+        digits.parse::<f64>().ok()?
+    } else {
+        return None;
+    };
+
+    Some(if negative { -value } else { value })
+}
+
+fn parse_register(text: &str) -> Option<u8> {
+    match text.as_bytes() {
+        [b'v' | b'V', digit] => (*digit as char).to_digit(16).map(|digit| digit as u8),
+        _ => None,
+    }
+}
+
+fn apply_unary(operator: &str, value: f64) -> f64 {
+    match operator {
+        "-" => -value,
+        "~" => !(value as i64) as f64,
+        "!" => (value == 0.0) as u8 as f64,
+        "sin" => value.sin(),
+        "cos" => value.cos(),
+        "tan" => value.tan(),
+        "exp" => value.exp(),
+        "log" => value.ln(),
+        "abs" => value.abs(),
+        "sqrt" => value.sqrt(),
+        "sign" => value.signum(),
+        "ceil" => value.ceil(),
+        _ => value.floor(),
+    }
+}
+
+fn apply_binary(operator: &str, left: f64, right: f64) -> f64 {
+    let (a, b) = (left as i64, right as i64);
+
+    match operator {
+        "-" => left - right,
+        "+" => left + right,
+        "*" => left * right,
+        "/" => left / right,
+        "%" => left % right,
+        "pow" => left.powf(right),
+        "min" => left.min(right),
+        "max" => left.max(right),
+        "&" => (a & b) as f64,
+        "|" => (a | b) as f64,
+        "^" => (a ^ b) as f64,
+        "<<" => a.checked_shl(b as u32).unwrap_or(0) as f64,
+        ">>" => a.checked_shr(b as u32).unwrap_or(0) as f64,
+        "<" => (left < right) as u8 as f64,
+        ">" => (left > right) as u8 as f64,
+        "<=" => (left <= right) as u8 as f64,
+        ">=" => (left >= right) as u8 as f64,
+        "==" => (left == right) as u8 as f64,
+        _ => (left != right) as u8 as f64,
+    }
+}
+
+struct Assembler {
+    tokens: VecDeque<Token>,
+    line: usize,
+    memory: Vec<u8>,
+    here: usize,
+    end: usize,
+    // Whether the program starts with a jump to main, which is left out when
+    // main comes first.
+    jump_to_main: bool,
+    labels: HashMap<String, usize>,
+    constants: HashMap<String, f64>,
+    aliases: HashMap<String, u8>,
+    macros: HashMap<String, Macro>,
+    string_modes: HashMap<String, HashMap<char, (usize, Vec<Token>)>>,
+    fixups: Vec<Fixup>,
+    blocks: Vec<Block>,
+}
+
+impl Assembler {
+    fn new(tokens: VecDeque<Token>) -> Assembler {
+        Assembler {
+            tokens,
+            line: 1,
+            memory: vec![0; ADDRESS_SPACE],
+            here: PROGRAM_START as usize,
+            end: PROGRAM_START as usize,
+            jump_to_main: true,
+            labels: HashMap::new(),
+            constants: HashMap::new(),
+            aliases: HashMap::new(),
+            macros: HashMap::new(),
+            string_modes: HashMap::new(),
+            fixups: Vec::new(),
+            blocks: Vec::new(),
+        }
+    }
+
+    fn error<T>(&self, message: impl Into<String>) -> Result<T, AssemblyError> {
+        Err(AssemblyError {
+            line: self.line,
+            message: message.into(),
+        })
+    }
+
+    fn next(&mut self) -> Result<Token, AssemblyError> {
+        match self.tokens.pop_front() {
+            Some(token) => {
+                self.line = token.line;
+                Ok(token)
+            }
+            None => self.error("unexpected end of program"),
+        }
+    }
+
+    fn peek_is(&self, text: &str) -> bool {
+        self.tokens
+            .front()
+            .is_some_and(|token| !token.string && token.text == text)
+    }
+
+    fn expect(&mut self, text: &str) -> Result<(), AssemblyError> {
+        let token = self.next()?;
+
+        if token.string || token.text != text {
+            return self.error(format!("expected '{}' but found '{}'", text, token.text));
+        }
+
+        Ok(())
+    }
+
+    fn name(&mut self) -> Result<String, AssemblyError> {
+        let token = self.next()?;
+
+        if token.string || parse_number(&token.text).is_some() || self.is_register(&token.text) {
+            return self.error(format!("'{}' is not a valid name", token.text));
+        }
+
+        Ok(token.text)
+    }
+
+    fn is_register(&self, text: &str) -> bool {
+        self.aliases.contains_key(text) || parse_register(text).is_some()
+    }
+
+    fn register(&mut self) -> Result<u8, AssemblyError> {
+        let token = self.next()?;
+
+        match self.aliases.get(&token.text) {
+            Some(&register) => Ok(register),
+            None => match parse_register(&token.text) {
+                Some(register) => Ok(register),
+                None => self.error(format!("expected a register but found '{}'", token.text)),
+            },
+        }
+    }
+
+    fn next_is_register(&self) -> bool {
+        self.tokens
+            .front()
+            .is_some_and(|token| !token.string && self.is_register(&token.text))
+    }
+
+    // A number, constant or label that is already known.
+    fn value_of(&self, token: &Token) -> Result<f64, AssemblyError> {
+        if !token.string {
+            if let Some(value) = parse_number(&token.text) {
+                return Ok(value);
+            }
+
+            if let Some(&value) = self.constants.get(&token.text) {
+                return Ok(value);
+            }
+
+            if let Some(&address) = self.labels.get(&token.text) {
+                return Ok(address as f64);
+            }
+        }
+
+        self.error(format!("undefined name '{}'", token.text))
+    }
+
+    fn value(&mut self) -> Result<f64, AssemblyError> {
+        let token = self.next()?;
+        self.value_of(&token)
+    }
+
+    fn byte(&mut self) -> Result<u8, AssemblyError> {
+        let value = self.value()?;
+
+        if !(-128.0..=255.0).contains(&value) {
+            return self.error(format!("{} does not fit in a byte", value));
+        }
+
+        Ok((value as i64 & 0xFF) as u8)
+    }
+
+    fn nibble(&mut self) -> Result<u8, AssemblyError> {
+        let value = self.value()?;
+
+        if !(0.0..=15.0).contains(&value) {
+            return self.error(format!("{} does not fit in 4 bits", value));
+        }
+
+        Ok(value as u8)
+    }
+
+    fn emit(&mut self, byte: u8) -> Result<(), AssemblyError> {
+        if self.here >= ADDRESS_SPACE {
+            return self.error("program does not fit in memory");
+        }
+
+        self.memory[self.here] = byte;
+        self.here += 1;
+        self.end = self.end.max(self.here);
+
+        Ok(())
+    }
+
+    fn instruction(&mut self, high: u8, low: u8) -> Result<(), AssemblyError> {
+        self.emit(high)?;
+        self.emit(low)
+    }
+
+    fn patch(&mut self, address: usize, patch: Patch, value: usize) -> Result<(), AssemblyError> {
+        match patch {
+            Patch::Address if value > 0xFFF => {
+                return self.error(format!("address 0x{:X} does not fit in 12 bits", value))
+            }
+            Patch::Address => {
+                self.memory[address] = (self.memory[address] & 0xF0) | (value >> 8) as u8;
+                self.memory[address + 1] = value as u8;
+            }
+            _ if value > 0xFFFF => {
+                return self.error(format!("address 0x{:X} does not fit in 16 bits", value))
+            }
+            Patch::Long => {
+                self.memory[address] = (value >> 8) as u8;
+                self.memory[address + 1] = value as u8;
+            }
+            Patch::Unpack(nibble) if value > 0xFFF => {
+                return self.error(format!(
+                    "address 0x{:X} cannot be unpacked with 0x{:X}",
+                    value, nibble
+                ))
+            }
+            Patch::Unpack(nibble) => {
+                self.memory[address + 1] = (nibble << 4) | (value >> 8) as u8;
+                self.memory[address + 3] = value as u8;
+            }
+            Patch::UnpackLong => {
+                self.memory[address + 1] = (value >> 8) as u8;
+                self.memory[address + 3] = value as u8;
+            }
+        }
+
+        Ok(())
+    }
+
+    // Fills in the operand of the code at `address` now if it is known, or
+    // once the label it names is defined.
+    fn operand(&mut self, address: usize, patch: Patch) -> Result<(), AssemblyError> {
+        let token = self.next()?;
+
+        let known = !token.string
+            && (parse_number(&token.text).is_some()
+                || self.constants.contains_key(&token.text)
+                || self.labels.contains_key(&token.text));
+
+        if known {
+            let value = self.value_of(&token)?;
+
+            if value < 0.0 {
+                return self.error(format!("{} is not an address", value));
+            }
+
+            return self.patch(address, patch, value as usize);
+        }
+
+        if token.string || self.is_register(&token.text) {
+            return self.error(format!("expected an address but found '{}'", token.text));
+        }
+
+        self.fixups.push(Fixup {
+            address,
+            name: token.text,
+            patch,
+            line: token.line,
+        });
+
+        Ok(())
+    }
+
+    fn define_label(&mut self, name: String, address: usize) -> Result<(), AssemblyError> {
+        if self.labels.contains_key(&name) {
+            return self.error(format!("'{}' is already defined", name));
+        }
+
+        self.labels.insert(name, address);
+
+        Ok(())
+    }
+
+    // Tokens between '{' and the matching '}'.
+    fn braced(&mut self) -> Result<Vec<Token>, AssemblyError> {
+        self.expect("{")?;
+
+        let mut body = Vec::new();
+        let mut depth = 0;
+
+        loop {
+            let token = self.next()?;
+
+            if !token.string {
+                match token.text.as_str() {
+                    "{" => depth += 1,
+                    "}" if depth == 0 => return Ok(body),
+                    "}" => depth -= 1,
+                    _ => {}
+                }
+            }
+
+            body.push(token);
+        }
+    }
+
+    // Calculations have no precedence and are evaluated from right to left,
+    // so `2 * 3 + 1` is 8.
+    fn evaluate(&self, tokens: &[Token]) -> Result<f64, AssemblyError> {
+        let mut position = 0;
+        let value = self.expression(tokens, &mut position)?;
+
+        match tokens.get(position) {
+            Some(token) => self.error(format!("unexpected '{}' in calculation", token.text)),
+            None => Ok(value),
+        }
+    }
+
+    fn expression(&self, tokens: &[Token], position: &mut usize) -> Result<f64, AssemblyError> {
+        let left = self.term(tokens, position)?;
+
+        match tokens.get(*position) {
+            Some(token) if !token.string && BINARY_OPERATORS.contains(&token.text.as_str()) => {
+                *position += 1;
+                let right = self.expression(tokens, position)?;
+
+                Ok(apply_binary(&token.text, left, right))
+            }
+            _ => Ok(left),
+        }
+    }
+
+    fn term(&self, tokens: &[Token], position: &mut usize) -> Result<f64, AssemblyError> {
+        let Some(token) = tokens.get(*position) else {
+            return self.error("calculation is missing a value");
+        };
+        *position += 1;
+
+        match token.text.as_str() {
+            _ if token.string => {
+                self.error(format!("unexpected \"{}\" in calculation", token.text))
+            }
+            "(" => {
+                let value = self.expression(tokens, position)?;
+
+                match tokens.get(*position) {
+                    Some(token) if token.text == ")" => {
+                        *position += 1;
+                        Ok(value)
+                    }
+                    _ => self.error("missing ')' in calculation"),
+                }
+            }
+            "@" => {
+                let address = self.term(tokens, position)? as usize;
+
+                Ok(self.memory.get(address).copied().unwrap_or(0) as f64)
+            }
+            operator if UNARY_OPERATORS.contains(&operator) => {
+                Ok(apply_unary(operator, self.term(tokens, position)?))
+            }
+            "HERE" => Ok(self.here as f64),
+            "PI" => Ok(std::f64::consts::PI),
+            "E" => Ok(std::f64::consts::E),
+            _ => self.value_of(token),
+        }
+    }
+
+    fn expand(&mut self, tokens: Vec<Token>) {
+        for token in tokens.into_iter().rev() {
+            self.tokens.push_front(token);
+        }
+    }
+
+    fn call_macro(&mut self, name: &str) -> Result<(), AssemblyError> {
+        let count = self.macros[name].arguments.len();
+        let mut arguments = HashMap::new();
+
+        for i in 0..count {
+            let token = self.next()?;
+            arguments.insert(self.macros[name].arguments[i].clone(), token);
+        }
+
+        let body = self.macros[name]
+            .body
+            .iter()
+            .map(|token| match arguments.get(&token.text) {
+                Some(argument) if !token.string => Token {
+                    line: token.line,
+                    ..argument.clone()
+                },
+                _ => token.clone(),
+            })
+            .collect();
+
+        self.expand(body);
+
+        Ok(())
+    }
+
+    fn call_string_mode(&mut self, name: &str) -> Result<(), AssemblyError> {
+        let text = self.next()?;
+
+        if !text.string {
+            return self.error(format!("expected a string but found '{}'", text.text));
+        }
+
+        let mut expansion = Vec::new();
+
+        for (index, c) in text.text.chars().enumerate() {
+            let Some((value, body)) = self.string_modes[name].get(&c) else {
+                return self.error(format!("string mode '{}' has no '{}'", name, c));
+            };
+
+            expansion.extend(body.iter().map(|token| {
+                let replacement = match token.text.as_str() {
+                    _ if token.string => None,
+                    "VALUE" => Some(*value),
+                    "CHAR" => Some(c as usize),
+                    "INDEX" => Some(index),
+                    _ => None,
+                };
+
+                match replacement {
+                    Some(number) => Token {
+                        text: number.to_string(),
+                        ..token.clone()
+                    },
+                    None => token.clone(),
+                }
+            }));
+        }
+
+        self.expand(expansion);
+
+        Ok(())
+    }
+
+    // Emits an instruction that skips the next one unless the condition holds,
+    // or when `negated`, skips it if the condition holds. Comparisons are
+    // worked out in vF.
+    fn condition(&mut self, negated: bool) -> Result<(), AssemblyError> {
+        let x = self.register()?;
+        let token = self.next()?;
+
+        let comparison = match (negated, token.text.as_str()) {
+            (true, "==") => "!=",
+            (true, "!=") => "==",
+            (true, "<") => ">=",
+            (true, ">") => "<=",
+            (true, "<=") => ">",
+            (true, ">=") => "<",
+            (true, "key") => "-key",
+            (true, "-key") => "key",
+            (_, comparison) => comparison,
+        }
+        .to_string();
+
+        match comparison.as_str() {
+            "key" => return self.instruction(0xE0 | x, 0xA1),
+            "-key" => return self.instruction(0xE0 | x, 0x9E),
+            "==" | "!=" | "<" | ">" | "<=" | ">=" => {}
+            _ => return self.error(format!("unknown comparison '{}'", token.text)),
+        }
+
+        let register = self.next_is_register();
+        let (y, n) = if register {
+            (self.register()?, 0)
+        } else {
+            (0, self.byte()?)
+        };
+
+        match (comparison.as_str(), register) {
+            ("==", true) => self.instruction(0x90 | x, y << 4),
+            ("==", false) => self.instruction(0x40 | x, n),
+            ("!=", true) => self.instruction(0x50 | x, y << 4),
+            ("!=", false) => self.instruction(0x30 | x, n),
+            (comparison, _) => {
+                if register {
+                    self.instruction(0x8F, y << 4)?;
+                } else {
+                    self.instruction(0x6F, n)?;
+                }
+
+                // vF -= vX leaves vF at 1 when vX <= n, vF =- vX when vX >= n.
+                let subtract = if matches!(comparison, ">" | "<=") {
+                    0x5
+                } else {
+                    0x7
+                };
+                self.instruction(0x8F, (x << 4) | subtract)?;
+
+                if matches!(comparison, ">" | "<") {
+                    self.instruction(0x3F, 1)
+                } else {
+                    self.instruction(0x4F, 1)
+                }
+            }
+        }
+    }
+
+    fn jump_placeholder(&mut self) -> Result<usize, AssemblyError> {
+        let address = self.here;
+        self.instruction(0x10, 0x00)?;
+
+        Ok(address)
+    }
+
+    fn resolve_jump(&mut self, address: usize) -> Result<(), AssemblyError> {
+        let here = self.here;
+        self.patch(address, Patch::Address, here)
+    }
+
+    fn statement(&mut self, token: Token) -> Result<(), AssemblyError> {
+        if token.string {
+            return self.error(format!("unexpected \"{}\"", token.text));
+        }
+
+        if self.is_register(&token.text) {
+            self.tokens.push_front(token);
+            return self.register_statement();
+        }
+
+        let here = self.here;
+
+        match token.text.as_str() {
+            ":" => {
+                let name = self.name()?;
+
+                // With nothing before main there is no need to jump to it.
+                if name == "main"
+                    && self.here == PROGRAM_START as usize + 2
+                    && self.labels.is_empty()
+                {
+                    self.here = PROGRAM_START as usize;
+                    self.end = self.here;
+                    self.jump_to_main = false;
+                }
+
+                let here = self.here;
+                self.define_label(name, here)?;
+            }
+            ":next" => {
+                let name = self.name()?;
+                self.define_label(name, here + 1)?;
+            }
+            ":const" => {
+                let name = self.name()?;
+                let value = self.value()?;
+                self.constants.insert(name, value);
+            }
+            ":calc" => {
+                let name = self.name()?;
+                let tokens = self.braced()?;
+                let value = self.evaluate(&tokens)?;
+                self.constants.insert(name, value);
+            }
+            ":alias" => {
+                let name = self.name()?;
+                let register = self.register()?;
+                self.aliases.insert(name, register);
+            }
+            ":org" => {
+                let address = self.value()?;
+
+                if !(0.0..ADDRESS_SPACE as f64).contains(&address) {
+                    return self.error(format!("cannot place code at {}", address));
+                }
+
+                self.here = address as usize;
+            }
+            ":byte" => {
+                let byte = if self.peek_is("{") {
+                    let tokens = self.braced()?;
+                    (self.evaluate(&tokens)? as i64 & 0xFF) as u8
+                } else {
+                    self.byte()?
+                };
+                self.emit(byte)?;
+            }
+            ":call" => {
+                self.instruction(0x20, 0x00)?;
+                self.operand(here, Patch::Address)?;
+            }
+            ":unpack" => {
+                let patch = if self.peek_is("long") {
+                    self.next()?;
+                    Patch::UnpackLong
+                } else {
+                    Patch::Unpack(self.nibble()?)
+                };
+                self.instruction(0x60, 0x00)?;
+                self.instruction(0x61, 0x00)?;
+                self.operand(here, patch)?;
+            }
+            ":macro" => {
+                let name = self.name()?;
+                let mut arguments = Vec::new();
+                while !self.peek_is("{") {
+                    arguments.push(self.name()?);
+                }
+                let body = self.braced()?;
+                self.macros.insert(name, Macro { arguments, body });
+            }
+            ":stringmode" => {
+                let name = self.name()?;
+                let alphabet = self.next()?;
+                if !alphabet.string {
+                    return self.error(format!("expected a string but found '{}'", alphabet.text));
+                }
+                let body = self.braced()?;
+
+                let mode = self.string_modes.entry(name).or_default();
+                for (value, c) in alphabet.text.chars().enumerate() {
+                    mode.insert(c, (value, body.clone()));
+                }
+            }
+            ":assert" => {
+                let message = match self.tokens.front() {
+                    Some(token) if token.string => self.next()?.text,
+                    _ => "assertion failed".to_string(),
+                };
+                let tokens = self.braced()?;
+                if self.evaluate(&tokens)? == 0.0 {
+                    return self.error(message);
+                }
+            }
+            // Debugger hints, which only mean something inside Octo.
+            ":breakpoint" => {
+                self.next()?;
+            }
+            ":monitor" => {
+                self.next()?;
+                self.next()?;
+            }
+            "clear" => self.instruction(0x00, 0xE0)?,
+            "return" | ";" => self.instruction(0x00, 0xEE)?,
+            "hires" => self.instruction(0x00, 0xFF)?,
+            "lores" => self.instruction(0x00, 0xFE)?,
+            "exit" => self.instruction(0x00, 0xFD)?,
+            "scroll-left" => self.instruction(0x00, 0xFC)?,
+            "scroll-right" => self.instruction(0x00, 0xFB)?,
+            "scroll-down" => {
+                let n = self.nibble()?;
+                self.instruction(0x00, 0xC0 | n)?;
+            }
+            "scroll-up" => {
+                let n = self.nibble()?;
+                self.instruction(0x00, 0xD0 | n)?;
+            }
+            "audio" => self.instruction(0xF0, 0x02)?,
+            "plane" => {
+                let n = self.nibble()?;
+                self.instruction(0xF0 | n, 0x01)?;
+            }
+            "jump" | "jump0" | "native" => {
+                let high = match token.text.as_str() {
+                    "jump" => 0x10,
+                    "jump0" => 0xB0,
+                    _ => 0x00,
+                };
+                self.instruction(high, 0x00)?;
+                self.operand(here, Patch::Address)?;
+            }
+            "bcd" | "saveflags" | "loadflags" => {
+                let x = self.register()?;
+                let low = match token.text.as_str() {
+                    "bcd" => 0x33,
+                    "saveflags" => 0x75,
+                    _ => 0x85,
+                };
+                self.instruction(0xF0 | x, low)?;
+            }
+            "save" | "load" => {
+                let x = self.register()?;
+
+                if self.peek_is("-") {
+                    self.next()?;
+                    let y = self.register()?;
+                    let low = if token.text == "save" { 0x2 } else { 0x3 };
+                    self.instruction(0x50 | x, (y << 4) | low)?;
+                } else {
+                    let low = if token.text == "save" { 0x55 } else { 0x65 };
+                    self.instruction(0xF0 | x, low)?;
+                }
+            }
+            "sprite" => {
+                let x = self.register()?;
+                let y = self.register()?;
+                let n = self.nibble()?;
+                self.instruction(0xD0 | x, (y << 4) | n)?;
+            }
+            "delay" | "buzzer" | "pitch" => {
+                self.expect(":=")?;
+                let x = self.register()?;
+                let low = match token.text.as_str() {
+                    "delay" => 0x15,
+                    "buzzer" => 0x18,
+                    _ => 0x3A,
+                };
+                self.instruction(0xF0 | x, low)?;
+            }
+            "i" => self.index_statement()?,
+            "if" => self.if_statement()?,
+            "else" => match self.blocks.pop() {
+                Some(Block::If { jump }) => {
+                    let end = self.jump_placeholder()?;
+                    self.resolve_jump(jump)?;
+                    self.blocks.push(Block::Else { jump: end });
+                }
+                _ => return self.error("'else' without 'begin'"),
+            },
+            "end" => match self.blocks.pop() {
+                Some(Block::If { jump } | Block::Else { jump }) => self.resolve_jump(jump)?,
+                _ => return self.error("'end' without 'begin'"),
+            },
+            "loop" => self.blocks.push(Block::Loop {
+                start: here,
+                breaks: Vec::new(),
+            }),
+            "while" => {
+                self.condition(true)?;
+                let jump = self.jump_placeholder()?;
+
+                match self.blocks.iter_mut().rev().find_map(|block| match block {
+                    Block::Loop { breaks, .. } => Some(breaks),
+                    _ => None,
+                }) {
+                    Some(breaks) => breaks.push(jump),
+                    None => return self.error("'while' outside a loop"),
+                }
+            }
+            "again" => match self.blocks.pop() {
+                Some(Block::Loop { start, breaks }) => {
+                    self.instruction(0x10, 0x00)?;
+                    self.patch(here, Patch::Address, start)?;
+
+                    for jump in breaks {
+                        self.resolve_jump(jump)?;
+                    }
+                }
+                _ => return self.error("'again' without 'loop'"),
+            },
+            name if self.macros.contains_key(name) => {
+                let name = name.to_string();
+                self.call_macro(&name)?;
+            }
+            name if self.string_modes.contains_key(name) => {
+                let name = name.to_string();
+                self.call_string_mode(&name)?;
+            }
+            name if parse_number(name).is_some() || name.starts_with(':') => {
+                return self.error(format!("unexpected '{}'", name));
+            }
+            // Anything else names a subroutine to call.
+            _ => {
+                self.tokens.push_front(token);
+                self.instruction(0x20, 0x00)?;
+                self.operand(here, Patch::Address)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn if_statement(&mut self) -> Result<(), AssemblyError> {
+        // The condition is only known to be `then` or `begin` after it, so it
+        // is read ahead to find out.
+        let length = self
+            .tokens
+            .iter()
+            .take(4)
+            .position(|token| !token.string && (token.text == "then" || token.text == "begin"));
+
+        let Some(length) = length else {
+            return self.error("expected 'then' or 'begin' after the condition");
+        };
+
+        let begin = self.tokens[length].text == "begin";
+        self.condition(begin)?;
+        self.next()?;
+
+        if begin {
+            let jump = self.jump_placeholder()?;
+            self.blocks.push(Block::If { jump });
+        }
+
+        Ok(())
+    }
+
+    fn index_statement(&mut self) -> Result<(), AssemblyError> {
+        let here = self.here;
+        let operator = self.next()?;
+
+        match operator.text.as_str() {
+            "+=" => {
+                let x = self.register()?;
+                self.instruction(0xF0 | x, 0x1E)
+            }
+            ":=" if self.peek_is("hex") || self.peek_is("bighex") => {
+                let low = if self.next()?.text == "hex" {
+                    0x29
+                } else {
+                    0x30
+                };
+                let x = self.register()?;
+                self.instruction(0xF0 | x, low)
+            }
+            ":=" if self.peek_is("long") => {
+                self.next()?;
+                self.instruction(0xF0, 0x00)?;
+                self.instruction(0x00, 0x00)?;
+                self.operand(here + 2, Patch::Long)
+            }
+            ":=" => {
+                self.instruction(0xA0, 0x00)?;
+                self.operand(here, Patch::Address)
+            }
+            text => self.error(format!("unknown operator 'i {}'", text)),
+        }
+    }
+
+    fn register_statement(&mut self) -> Result<(), AssemblyError> {
+        let x = self.register()?;
+        let operator = self.next()?;
+
+        if operator.text == ":=" {
+            for (source, low) in [("key", 0x0A), ("delay", 0x07)] {
+                if self.peek_is(source) {
+                    self.next()?;
+                    return self.instruction(0xF0 | x, low);
+                }
+            }
+
+            if self.peek_is("random") {
+                self.next()?;
+                let mask = self.byte()?;
+                return self.instruction(0xC0 | x, mask);
+            }
+        }
+
+        if self.next_is_register() {
+            let y = self.register()?;
+            let low = match operator.text.as_str() {
+                ":=" => 0x0,
+                "|=" => 0x1,
+                "&=" => 0x2,
+                "^=" => 0x3,
+                "+=" => 0x4,
+                "-=" => 0x5,
+                ">>=" => 0x6,
+                "=-" => 0x7,
+                "<<=" => 0xE,
+                text => return self.error(format!("unknown operator '{}'", text)),
+            };
+
+            return self.instruction(0x80 | x, (y << 4) | low);
+        }
+
+        let n = self.byte()?;
+
+        match operator.text.as_str() {
+            ":=" => self.instruction(0x60 | x, n),
+            "+=" => self.instruction(0x70 | x, n),
+            "-=" => self.instruction(0x70 | x, n.wrapping_neg()),
+            text => self.error(format!("'{}' needs a register", text)),
+        }
+    }
+
+    fn assemble(mut self) -> Result<Vec<u8>, AssemblyError> {
+        self.instruction(0x10, 0x00)?;
+
+        while !self.tokens.is_empty() {
+            let token = self.next()?;
+            self.statement(token)?;
+        }
+
+        if !self.blocks.is_empty() {
+            return self.error("missing 'end' or 'again'");
+        }
+
+        if self.jump_to_main {
+            match self.labels.get("main") {
+                Some(&main) => self.patch(PROGRAM_START as usize, Patch::Address, main)?,
+                None => return self.error("no 'main' label"),
+            }
+        }
+
+        for fixup in std::mem::take(&mut self.fixups) {
+            self.line = fixup.line;
+
+            match self.labels.get(&fixup.name) {
+                Some(&value) => self.patch(fixup.address, fixup.patch, value)?,
+                None => return self.error(format!("undefined name '{}'", fixup.name)),
+            }
+        }
+
+        Ok(self.memory[PROGRAM_START as usize..self.end].to_vec())
+    }
+}
+
+// Assembles Octo source into a ROM loaded at 0x200.
+pub fn assemble(source: &str) -> Result<Vec<u8>, AssemblyError> {
+    Assembler::new(tokenize(source)?).assemble()
+}
+
+// Joins the low `bits` of each pixel into bytes.
+fn pack_pixels(pixels: &[u8], bits: usize, high_first: bool) -> Vec<u8> {
+    let per_byte = 8 / bits;
+    let mask = (1u8 << bits) - 1;
+
+    pixels
+        .chunks_exact(per_byte)
+        .map(|chunk| {
+            chunk.iter().enumerate().fold(0, |byte, (i, &pixel)| {
+                let slot = if high_first { per_byte - 1 - i } else { i };
+                byte | ((pixel & mask) << (slot * bits))
+            })
+        })
+        .collect()
+}
+
+// The program in a payload of `{"program": ..., "options": ...}` JSON, which
+// may follow its length as a 32-bit big-endian number.
+fn payload_program(bytes: &[u8]) -> Option<String> {
+    let prefixed = bytes.get(..4).and_then(|size| {
+        let size = u32::from_be_bytes(size.try_into().unwrap()) as usize;
+        bytes.get(4..4usize.checked_add(size)?)
+    });
+
+    [prefixed, Some(bytes)]
+        .into_iter()
+        .flatten()
+        .filter_map(|json| {
+            serde_json::Deserializer::from_slice(json)
+                .into_iter::<serde_json::Value>()
+                .next()?
+                .ok()
+        })
+        .find_map(|payload| payload.get("program")?.as_str().map(str::to_string))
+}
+
+// Octo cartridges are GIF images of a label with the program's source and
+// options hidden in the low bits of each pixel's colour index, continuing
+// across frames when they do not fit in one. Both the nibble and the two bit
+// packings are tried, and the payload is only accepted if it is valid JSON.
+pub fn cartridge_program(image: &[u8]) -> Result<Option<String>, gif::DecodingError> {
+    let mut options = gif::DecodeOptions::new();
+    options.set_color_output(gif::ColorOutput::Indexed);
+    let mut decoder = options.read_info(image)?;
+
+    let mut pixels = Vec::new();
+    while let Some(frame) = decoder.read_next_frame()? {
+        pixels.extend_from_slice(&frame.buffer);
+    }
+
+    for bits in [4, 2] {
+        for high_first in [true, false] {
+            if let Some(program) = payload_program(&pack_pixels(&pixels, bits, high_first)) {
+                return Ok(Some(program));
+            }
+        }
+    }
+
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn words(rom: &[u8]) -> Vec<u16> {
+        rom.chunks(2)
+            .map(|pair| u16::from_be_bytes([pair[0], *pair.get(1).unwrap_or(&0)]))
+            .collect()
+    }
+
+    fn assemble_words(source: &str) -> Vec<u16> {
+        words(&assemble(source).unwrap())
+    }
+
+    #[test]
+    fn main_is_jumped_to_unless_it_comes_first() {
+        assert_eq!(assemble_words(": main clear loop again"), [0x00E0, 0x1202]);
+        assert_eq!(
+            assemble_words(": draw return : main draw"),
+            [0x1204, 0x00EE, 0x2202]
+        );
+        assert_eq!(
+            assemble(": draw return"),
+            Err(AssemblyError {
+                line: 1,
+                message: "no 'main' label".to_string()
+            })
+        );
+    }
+
+    #[test]
+    fn statements_encode_to_their_instructions() {
+        let source = "
+            : main
+            v1 := 0x2A  v2 := v3  v4 += 1  v5 -= 1  v6 += v7  v6 -= v7  v6 =- v7
+            v8 |= v9  v8 &= v9  v8 ^= v9  v8 >>= v9  v8 <<= v9
+            va := random 0xF0  vb := key  vc := delay  delay := v1  buzzer := v2
+            i := 0x345  i += v1  i := hex v2  i := bighex v3  bcd v4  save v5  load v6
+            sprite v1 v2 5  jump0 0x300  native 0x123
+            save v1 - v3  load v2 - v4  plane 3  audio  pitch := v1
+            saveflags v7  loadflags v7  scroll-down 4  scroll-up 2
+            scroll-left  scroll-right  exit  lores  hires  i := long 0xABCD
+            return ;
+        ";
+
+        assert_eq!(
+            assemble_words(source),
+            [
+                0x612A, 0x8230, 0x7401, 0x75FF, 0x8674, 0x8675, 0x8677, 0x8891, 0x8892, 0x8893,
+                0x8896, 0x889E, 0xCAF0, 0xFB0A, 0xFC07, 0xF115, 0xF218, 0xA345, 0xF11E, 0xF229,
+                0xF330, 0xF433, 0xF555, 0xF665, 0xD125, 0xB300, 0x0123, 0x5132, 0x5243, 0xF301,
+                0xF002, 0xF13A, 0xF775, 0xF785, 0x00C4, 0x00D2, 0x00FC, 0x00FB, 0x00FD, 0x00FE,
+                0x00FF, 0xF000, 0xABCD, 0x00EE, 0x00EE,
+            ]
+        );
+    }
+
+    #[test]
+    fn conditions_skip_the_statement_after_then() {
+        let source = "
+            : main
+            if v1 == 2 then clear
+            if v1 != v2 then clear
+            if v1 key then clear
+            if v1 -key then clear
+            if v1 > 5 then clear
+            if v1 <= v2 then clear
+        ";
+
+        assert_eq!(
+            assemble_words(source),
+            [
+                0x4102, 0x00E0, 0x5120, 0x00E0, 0xE1A1, 0x00E0, 0xE19E, 0x00E0, 0x6F05, 0x8F15,
+                0x3F01, 0x00E0, 0x8F20, 0x8F15, 0x4F01, 0x00E0,
+            ]
+        );
+    }
+
+    #[test]
+    fn blocks_jump_over_their_branches() {
+        let source = "
+            : main
+            if v0 == 1 begin
+                clear
+            else
+                return
+            end
+            loop
+                v0 += 1
+                while v0 < 10
+            again
+        ";
+
+        assert_eq!(
+            assemble_words(source),
+            [
+                0x3001, 0x1208, 0x00E0, 0x120A, 0x00EE, 0x7001, 0x6F0A, 0x8F07, 0x4F01, 0x1216,
+                0x120A,
+            ]
+        );
+    }
+
+    #[test]
+    fn labels_can_be_used_before_they_are_defined() {
+        let source = "
+            : main
+            i := sprite  sprite v0 v0 1
+            jump main
+            :unpack 0xA sprite
+            :unpack long sprite
+            : sprite :byte 0xFF
+        ";
+
+        assert_eq!(
+            assemble_words(source),
+            [0xA20E, 0xD001, 0x1200, 0x60A2, 0x610E, 0x6002, 0x610E, 0xFF00]
+        );
+    }
+
+    #[test]
+    fn directives_define_data_and_names() {
+        let source = "
+            :alias x v3
+            :const speed 4
+            :calc double { speed * 2 + 1 }
+            : main
+            x := speed
+            x := double
+            :next target v0 := 0
+            jump target
+            :org 0x300
+            : data
+            :byte 0x12 :byte -1 :byte { data >> 8 }
+        ";
+
+        let rom = assemble(source).unwrap();
+
+        assert_eq!(words(&rom[..8]), [0x6304, 0x630C, 0x6000, 0x1205]);
+        assert_eq!(rom[0x100..], [0x12, 0xFF, 0x03]);
+    }
+
+    #[test]
+    fn macros_and_string_modes_expand_their_bodies() {
+        let source = "
+            :macro set register value { register := value }
+            :stringmode text \"AB\" { v0 := VALUE v1 := INDEX }
+            : main
+            set v2 7
+            text \"BA\"
+        ";
+
+        assert_eq!(
+            assemble_words(source),
+            [0x6207, 0x6001, 0x6100, 0x6000, 0x6101]
+        );
+    }
+
+    #[test]
+    fn mistakes_are_reported_with_their_line() {
+        for (source, line, message) in [
+            (": main\nv0 := 256", 2, "256 does not fit in a byte"),
+            (": main\n\njump nowhere", 3, "undefined name 'nowhere'"),
+            (": main\nloop\nclear", 3, "missing 'end' or 'again'"),
+            (": main\n: main", 2, "'main' is already defined"),
+            (
+                ": main\nif v0 == 1\nclear",
+                2,
+                "expected 'then' or 'begin' after the condition",
+            ),
+            (": main\n:assert \"too big\" { 2 > 1 }", 2, ""),
+            (": main\n:assert \"too small\" { 1 > 2 }", 2, "too small"),
+            (": main \"text", 1, "missing closing quote"),
+        ] {
+            match assemble(source) {
+                Ok(_) => assert!(message.is_empty(), "{:?} assembled", source),
+                Err(error) => assert_eq!(
+                    error,
+                    AssemblyError {
+                        line,
+                        message: message.to_string()
+                    },
+                    "{:?}",
+                    source
+                ),
+            }
+        }
+    }
+
+    // A cartridge holding `payload` a nibble per pixel, split over frames of
+    // 32x8 pixels.
+    fn cartridge(payload: &[u8]) -> Vec<u8> {
+        let mut bytes = (payload.len() as u32).to_be_bytes().to_vec();
+        bytes.extend_from_slice(payload);
+
+        let palette: Vec<u8> = (0..16).flat_map(|i| [i * 16, i * 16, i * 16]).collect();
+        let mut image = Vec::new();
+        let mut encoder = gif::Encoder::new(&mut image, 32, 8, &palette).unwrap();
+
+        for chunk in bytes.chunks(128) {
+            let mut pixels: Vec<u8> = chunk
+                .iter()
+                .flat_map(|byte| [byte >> 4, byte & 0xF])
+                .collect();
+            pixels.resize(256, 0);
+
+            encoder
+                .write_frame(&gif::Frame::from_indexed_pixels(32, 8, pixels, None))
+                .unwrap();
+        }
+
+        drop(encoder);
+        image
+    }
+
+    #[test]
+    fn cartridges_yield_their_program() {
+        let source = ": main\n  loop again # padding to spill into a second frame.....................................................";
+        let payload = serde_json::json!({ "program": source, "options": { "tickrate": 20 } });
+        let image = cartridge(payload.to_string().as_bytes());
+
+        assert_eq!(cartridge_program(&image).unwrap().as_deref(), Some(source));
+        assert_eq!(assemble(source).unwrap(), [0x12, 0x00]);
+    }
+
+    #[test]
+    fn images_without_a_payload_have_no_program() {
+        let image = cartridge(b"not json");
+
+        assert_eq!(cartridge_program(&image).unwrap(), None);
+        assert!(cartridge_program(b"GIF89a").is_err());
+    }
+}
@@ -1,3 +1,8 @@
+use std::fmt;
+
+use crate::platform::Platform;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
 pub enum Opcode {
     ClearScreen,
     ReturnFromSubroutine,
@@ -52,14 +57,16 @@ pub enum Opcode {
         register2: u8,
     },
     ShiftRegisterRight {
-        register: u8,
+        register1: u8,
+        register2: u8,
     },
     SetRegisterToRegisterMinusRegister {
         register1: u8,
         register2: u8,
     },
     ShiftRegisterLeft {
-        register: u8,
+        register1: u8,
+        register2: u8,
     },
     SkipIfRegisterNotEqualRegister {
         register1: u8,
@@ -81,10 +88,10 @@ pub enum Opcode {
         size: u8,
     },
     SkipIfKeyPressed {
-        key: u8,
+        register: u8,
     },
     SkipIfKeyNotPressed {
-        key: u8,
+        register: u8,
     },
     SetRegisterToDelayTimer {
         register: u8,
@@ -113,9 +120,73 @@ pub enum Opcode {
     LoadRegisters {
         last_memory_address: u8,
     },
+    // CHIP-8X
+    CycleBackgroundColor,
+    SetColorZones {
+        register1: u8,
+        register2: u8,
+    },
+    SetColorRows {
+        register1: u8,
+        register2: u8,
+        height: u8,
+    },
+    SkipIfSecondPadKeyPressed {
+        register: u8,
+    },
+    SkipIfSecondPadKeyNotPressed {
+        register: u8,
+    },
+    OutputToPort {
+        register: u8,
+    },
+    InputFromPort {
+        register: u8,
+    },
 }
 
 impl Opcode {
+    // Decodes with the 0NNN machine code calls `platform`'s interpreter
+    // handles itself.
+    pub fn decode_on(opcode: u16, platform: Platform) -> Option<Opcode> {
+        let register1 = ((opcode & 0x0F00) >> 8) as u8;
+        let register2 = ((opcode & 0x00F0) >> 4) as u8;
+
+        match (platform, opcode) {
+            (Platform::HiRes, 0x0230) => Some(Opcode::ClearScreen),
+            (Platform::Chip8X, 0x02A0) => Some(Opcode::CycleBackgroundColor),
+            (Platform::Chip8X, _) => match opcode & 0xF000 {
+                0xB000 => match opcode & 0x000F {
+                    0 => Some(Opcode::SetColorZones {
+                        register1,
+                        register2,
+                    }),
+                    height => Some(Opcode::SetColorRows {
+                        register1,
+                        register2,
+                        height: height as u8,
+                    }),
+                },
+                _ => match opcode & 0xF0FF {
+                    0xE0F2 => Some(Opcode::SkipIfSecondPadKeyPressed {
+                        register: register1,
+                    }),
+                    0xE0F5 => Some(Opcode::SkipIfSecondPadKeyNotPressed {
+                        register: register1,
+                    }),
+                    0xF0F8 => Some(Opcode::OutputToPort {
+                        register: register1,
+                    }),
+                    0xF0FB => Some(Opcode::InputFromPort {
+                        register: register1,
+                    }),
+                    _ => Opcode::decode(opcode),
+                },
+            },
+            _ => Opcode::decode(opcode),
+        }
+    }
+
     pub fn decode(opcode: u16) -> Option<Opcode> {
         match opcode & 0xF000 {
             0x0000 => match opcode & 0x00FF {
@@ -179,12 +250,18 @@ impl Opcode {
                         register1: x as u8,
                         register2: y as u8,
                     }),
-                    0x0006 => Some(Opcode::ShiftRegisterRight { register: x as u8 }),
+                    0x0006 => Some(Opcode::ShiftRegisterRight {
+                        register1: x as u8,
+                        register2: y as u8,
+                    }),
                     0x0007 => Some(Opcode::SetRegisterToRegisterMinusRegister {
                         register1: x as u8,
                         register2: y as u8,
                     }),
-                    0x000E => Some(Opcode::ShiftRegisterLeft { register: x as u8 }),
+                    0x000E => Some(Opcode::ShiftRegisterLeft {
+                        register1: x as u8,
+                        register2: y as u8,
+                    }),
                     _ => None,
                 }
             }
@@ -209,10 +286,10 @@ impl Opcode {
             }),
             0xE000 => match opcode & 0x00FF {
                 0x009E => Some(Opcode::SkipIfKeyPressed {
-                    key: ((opcode & 0x0F00) >> 8) as u8,
+                    register: ((opcode & 0x0F00) >> 8) as u8,
                 }),
                 0x00A1 => Some(Opcode::SkipIfKeyNotPressed {
-                    key: ((opcode & 0x0F00) >> 8) as u8,
+                    register: ((opcode & 0x0F00) >> 8) as u8,
                 }),
                 _ => None,
             },
@@ -249,4 +326,168 @@ impl Opcode {
             _ => None,
         }
     }
+
+    pub fn is_skip(&self) -> bool {
+        matches!(
+            self,
+            Opcode::SkipIfEqual { .. }
+                | Opcode::SkipIfNotEqual { .. }
+                | Opcode::SkipIfRegistersEqual { .. }
+                | Opcode::SkipIfRegisterNotEqualRegister { .. }
+                | Opcode::SkipIfKeyPressed { .. }
+                | Opcode::SkipIfKeyNotPressed { .. }
+                | Opcode::SkipIfSecondPadKeyPressed { .. }
+                | Opcode::SkipIfSecondPadKeyNotPressed { .. }
+        )
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Opcode::ClearScreen => "ClearScreen",
+            Opcode::ReturnFromSubroutine => "ReturnFromSubroutine",
+            Opcode::JumpToAddress { .. } => "JumpToAddress",
+            Opcode::CallAddress { .. } => "CallAddress",
+            Opcode::SkipIfEqual { .. } => "SkipIfEqual",
+            Opcode::SkipIfNotEqual { .. } => "SkipIfNotEqual",
+            Opcode::SkipIfRegistersEqual { .. } => "SkipIfRegistersEqual",
+            Opcode::SetRegisterToByte { .. } => "SetRegisterToByte",
+            Opcode::AddByteToRegister { .. } => "AddByteToRegister",
+            Opcode::SetRegisterToRegister { .. } => "SetRegisterToRegister",
+            Opcode::SetRegisterToRegisterOrRegister { .. } => "SetRegisterToRegisterOrRegister",
+            Opcode::SetRegisterToRegisterAndRegister { .. } => "SetRegisterToRegisterAndRegister",
+            Opcode::SetRegisterToRegisterXorRegister { .. } => "SetRegisterToRegisterXorRegister",
+            Opcode::AddRegisterToRegister { .. } => "AddRegisterToRegister",
+            Opcode::SubtractRegisterFromRegister { .. } => "SubtractRegisterFromRegister",
+            Opcode::ShiftRegisterRight { .. } => "ShiftRegisterRight",
+            Opcode::SetRegisterToRegisterMinusRegister { .. } => {
+                "SetRegisterToRegisterMinusRegister"
+            }
+            Opcode::ShiftRegisterLeft { .. } => "ShiftRegisterLeft",
+            Opcode::SkipIfRegisterNotEqualRegister { .. } => "SkipIfRegisterNotEqualRegister",
+            Opcode::SetIndexToAddress { .. } => "SetIndexToAddress",
+            Opcode::JumpToAddressPlusRegister0 { .. } => "JumpToAddressPlusRegister0",
+            Opcode::SetRegisterToRandAndByte { .. } => "SetRegisterToRandAndByte",
+            Opcode::DrawSprite { .. } => "DrawSprite",
+            Opcode::SkipIfKeyPressed { .. } => "SkipIfKeyPressed",
+            Opcode::SkipIfKeyNotPressed { .. } => "SkipIfKeyNotPressed",
+            Opcode::SetRegisterToDelayTimer { .. } => "SetRegisterToDelayTimer",
+            Opcode::WaitForKeyPress { .. } => "WaitForKeyPress",
+            Opcode::SetDelayTimerToRegister { .. } => "SetDelayTimerToRegister",
+            Opcode::SetSoundTimerToRegister { .. } => "SetSoundTimerToRegister",
+            Opcode::AddRegisterToIndex { .. } => "AddRegisterToIndex",
+            Opcode::SetIndexToSpriteLocation { .. } => "SetIndexToSpriteLocation",
+            Opcode::StoreBCD { .. } => "StoreBCD",
+            Opcode::StoreRegisters { .. } => "StoreRegisters",
+            Opcode::LoadRegisters { .. } => "LoadRegisters",
+            Opcode::CycleBackgroundColor => "CycleBackgroundColor",
+            Opcode::SetColorZones { .. } => "SetColorZones",
+            Opcode::SetColorRows { .. } => "SetColorRows",
+            Opcode::SkipIfSecondPadKeyPressed { .. } => "SkipIfSecondPadKeyPressed",
+            Opcode::SkipIfSecondPadKeyNotPressed { .. } => "SkipIfSecondPadKeyNotPressed",
+            Opcode::OutputToPort { .. } => "OutputToPort",
+            Opcode::InputFromPort { .. } => "InputFromPort",
+        }
+    }
+}
+
+impl fmt::Display for Opcode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Opcode::ClearScreen => write!(f, "CLS"),
+            Opcode::ReturnFromSubroutine => write!(f, "RET"),
+            Opcode::JumpToAddress { address } => write!(f, "JP 0x{:03X}", address),
+            Opcode::CallAddress { address } => write!(f, "CALL 0x{:03X}", address),
+            Opcode::SkipIfEqual { register, byte } => {
+                write!(f, "SE V{:X}, 0x{:02X}", register, byte)
+            }
+            Opcode::SkipIfNotEqual { register, byte } => {
+                write!(f, "SNE V{:X}, 0x{:02X}", register, byte)
+            }
+            Opcode::SkipIfRegistersEqual {
+                register1,
+                register2,
+            } => write!(f, "SE V{:X}, V{:X}", register1, register2),
+            Opcode::SetRegisterToByte { register, byte } => {
+                write!(f, "LD V{:X}, 0x{:02X}", register, byte)
+            }
+            Opcode::AddByteToRegister { register, byte } => {
+                write!(f, "ADD V{:X}, 0x{:02X}", register, byte)
+            }
+            Opcode::SetRegisterToRegister {
+                register1,
+                register2,
+            } => write!(f, "LD V{:X}, V{:X}", register1, register2),
+            Opcode::SetRegisterToRegisterOrRegister {
+                register1,
+                register2,
+            } => write!(f, "OR V{:X}, V{:X}", register1, register2),
+            Opcode::SetRegisterToRegisterAndRegister {
+                register1,
+                register2,
+            } => write!(f, "AND V{:X}, V{:X}", register1, register2),
+            Opcode::SetRegisterToRegisterXorRegister {
+                register1,
+                register2,
+            } => write!(f, "XOR V{:X}, V{:X}", register1, register2),
+            Opcode::AddRegisterToRegister {
+                register1,
+                register2,
+            } => write!(f, "ADD V{:X}, V{:X}", register1, register2),
+            Opcode::SubtractRegisterFromRegister {
+                register1,
+                register2,
+            } => write!(f, "SUB V{:X}, V{:X}", register1, register2),
+            Opcode::ShiftRegisterRight { register1, .. } => write!(f, "SHR V{:X}", register1),
+            Opcode::SetRegisterToRegisterMinusRegister {
+                register1,
+                register2,
+            } => write!(f, "SUBN V{:X}, V{:X}", register1, register2),
+            Opcode::ShiftRegisterLeft { register1, .. } => write!(f, "SHL V{:X}", register1),
+            Opcode::SkipIfRegisterNotEqualRegister {
+                register1,
+                register2,
+            } => write!(f, "SNE V{:X}, V{:X}", register1, register2),
+            Opcode::SetIndexToAddress { address } => write!(f, "LD I, 0x{:03X}", address),
+            Opcode::JumpToAddressPlusRegister0 { address } => {
+                write!(f, "JP V0, 0x{:03X}", address)
+            }
+            Opcode::SetRegisterToRandAndByte { register, byte } => {
+                write!(f, "RND V{:X}, 0x{:02X}", register, byte)
+            }
+            Opcode::DrawSprite {
+                register1,
+                register2,
+                size,
+            } => write!(f, "DRW V{:X}, V{:X}, {}", register1, register2, size),
+            Opcode::SkipIfKeyPressed { register } => write!(f, "SKP V{:X}", register),
+            Opcode::SkipIfKeyNotPressed { register } => write!(f, "SKNP V{:X}", register),
+            Opcode::SetRegisterToDelayTimer { register } => write!(f, "LD V{:X}, DT", register),
+            Opcode::WaitForKeyPress { register } => write!(f, "LD V{:X}, K", register),
+            Opcode::SetDelayTimerToRegister { register } => write!(f, "LD DT, V{:X}", register),
+            Opcode::SetSoundTimerToRegister { register } => write!(f, "LD ST, V{:X}", register),
+            Opcode::AddRegisterToIndex { register } => write!(f, "ADD I, V{:X}", register),
+            Opcode::SetIndexToSpriteLocation { register } => write!(f, "LD F, V{:X}", register),
+            Opcode::StoreBCD { register } => write!(f, "LD B, V{:X}", register),
+            Opcode::StoreRegisters { last_index } => write!(f, "LD [I], V{:X}", last_index),
+            Opcode::LoadRegisters {
+                last_memory_address,
+            } => write!(f, "LD V{:X}, [I]", last_memory_address),
+            Opcode::CycleBackgroundColor => write!(f, "BGC"),
+            Opcode::SetColorZones {
+                register1,
+                register2,
+            } => write!(f, "COL V{:X}, V{:X}", register1, register2),
+            Opcode::SetColorRows {
+                register1,
+                register2,
+                height,
+            } => write!(f, "COL V{:X}, V{:X}, {}", register1, register2, height),
+            Opcode::SkipIfSecondPadKeyPressed { register } => write!(f, "SKP2 V{:X}", register),
+            Opcode::SkipIfSecondPadKeyNotPressed { register } => {
+                write!(f, "SKNP2 V{:X}", register)
+            }
+            Opcode::OutputToPort { register } => write!(f, "OUT V{:X}", register),
+            Opcode::InputFromPort { register } => write!(f, "IN V{:X}", register),
+        }
+    }
 }
@@ -0,0 +1,252 @@
+pub const PALETTE_NAMES: [&str; 5] = ["classic", "green", "amber", "lcd", "high-contrast"];
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct Color {
+    pub red: u8,
+    pub green: u8,
+    pub blue: u8,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PaletteError {
+    UnknownPalette(String),
+    InvalidColor(String),
+    TooFewColors,
+}
+
+// colors[0] is the background and colors[1] the foreground. XO-CHIP style
+// multi-plane output indexes further colors by the bitmask of lit planes.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Palette {
+    name: String,
+    colors: Vec<Color>,
+    // A palette that isn't built in, kept so that cycling comes back to it.
+    custom: Option<(String, Vec<Color>)>,
+}
+
+impl Color {
+    pub const fn new(red: u8, green: u8, blue: u8) -> Color {
+        Color { red, green, blue }
+    }
+
+    pub fn parse(text: &str) -> Result<Color, PaletteError> {
+        let digits = text.trim().trim_start_matches('#');
+
+        if digits.len() != 6 || !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(PaletteError::InvalidColor(text.to_string()));
+        }
+
+        match u32::from_str_radix(digits, 16) {
+            Ok(value) => Ok(Color::new(
+                (value >> 16) as u8,
+                (value >> 8) as u8,
+                value as u8,
+            )),
+            Err(_) => Err(PaletteError::InvalidColor(text.to_string())),
+        }
+    }
+
+    pub fn blend(self, other: Color, amount: u8) -> Color {
+        let mix = |a: u8, b: u8| -> u8 {
+            ((a as u16 * (255 - amount as u16) + b as u16 * amount as u16) / 255) as u8
+        };
+
+        Color::new(
+            mix(self.red, other.red),
+            mix(self.green, other.green),
+            mix(self.blue, other.blue),
+        )
+    }
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Palette::named("classic").unwrap()
+    }
+}
+
+impl Palette {
+    pub fn new(name: &str, colors: Vec<Color>) -> Result<Palette, PaletteError> {
+        if colors.len() < 2 {
+            return Err(PaletteError::TooFewColors);
+        }
+
+        let custom = (!PALETTE_NAMES.contains(&name)).then(|| (name.to_string(), colors.clone()));
+
+        Ok(Palette {
+            name: name.to_string(),
+            colors,
+            custom,
+        })
+    }
+
+    pub fn named(name: &str) -> Result<Palette, PaletteError> {
+        let colors = match name {
+            "classic" => [0x000000, 0xFFFFFF, 0xAAAAAA, 0x555555],
+            "green" => [0x001100, 0x33FF33, 0x1A801A, 0x99FF99],
+            "amber" => [0x1A0F00, 0xFFB000, 0x805800, 0xFFD780],
+            "lcd" => [0x9BBC0F, 0x0F380F, 0x306230, 0x8BAC0F],
+            "high-contrast" => [0x000000, 0xFFFF00, 0x00FFFF, 0xFFFFFF],
+            _ => return Err(PaletteError::UnknownPalette(name.to_string())),
+        };
+
+        Palette::new(
+            name,
+            colors
+                .iter()
+                .map(|&rgb| Color::new((rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8))
+                .collect(),
+        )
+    }
+
+    // Accepts either a built-in name or a comma separated list of hex
+    // colours such as "#000000,#FFB000".
+    pub fn parse(text: &str) -> Result<Palette, PaletteError> {
+        if !text.contains('#') && !text.contains(',') {
+            return Palette::named(text.trim());
+        }
+
+        let colors = text
+            .split(',')
+            .map(Color::parse)
+            .collect::<Result<Vec<Color>, PaletteError>>()?;
+
+        Palette::new("custom", colors)
+    }
+
+    pub fn get_name(&self) -> &str {
+        &self.name
+    }
+
+    // The built-in palettes in turn, then any custom one the cycle began
+    // with.
+    pub fn next(&self) -> Palette {
+        let position = PALETTE_NAMES.iter().position(|&name| name == self.name);
+        let next = match (position, &self.custom) {
+            (Some(i), Some((name, colors))) if i + 1 == PALETTE_NAMES.len() => {
+                return Palette::new(name, colors.clone()).unwrap();
+            }
+            (Some(i), _) => PALETTE_NAMES[(i + 1) % PALETTE_NAMES.len()],
+            (None, _) => PALETTE_NAMES[0],
+        };
+
+        let mut palette = Palette::named(next).unwrap();
+        palette.custom = self.custom.clone();
+
+        palette
+    }
+
+    pub fn background(&self) -> Color {
+        self.colors[0]
+    }
+
+    pub fn foreground(&self) -> Color {
+        self.colors[1]
+    }
+
+    pub fn color(&self, planes: u8) -> Color {
+        match self.colors.get(planes as usize) {
+            Some(&color) => color,
+            None => self.foreground(),
+        }
+    }
+
+    pub fn shade(&self, intensity: u8) -> Color {
+        self.background().blend(self.foreground(), intensity)
+    }
+
+    pub fn intensities_to_rgba(&self, intensities: &[u8]) -> Vec<u8> {
+        intensities
+            .iter()
+            .flat_map(|&intensity| {
+                let color = self.shade(intensity);
+                [color.red, color.green, color.blue, 255]
+            })
+            .collect()
+    }
+
+    pub fn planes_to_rgba(&self, planes: &[u8]) -> Vec<u8> {
+        planes
+            .iter()
+            .flat_map(|&plane| {
+                let color = self.color(plane);
+                [color.red, color.green, color.blue, 255]
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn colors_parse_with_or_without_a_hash() {
+        assert_eq!(Color::parse("#FFB000"), Ok(Color::new(0xFF, 0xB0, 0x00)));
+        assert_eq!(Color::parse(" 0f380f "), Ok(Color::new(0x0F, 0x38, 0x0F)));
+
+        for text in ["+12345", "-12345", "#FFB00", "#FFB0000", "#GGGGGG"] {
+            assert_eq!(
+                Color::parse(text),
+                Err(PaletteError::InvalidColor(text.to_string()))
+            );
+        }
+    }
+
+    #[test]
+    fn palettes_parse_names_and_color_lists() {
+        assert_eq!(Palette::parse("amber").unwrap().get_name(), "amber");
+        assert_eq!(
+            Palette::parse("sepia"),
+            Err(PaletteError::UnknownPalette(String::from("sepia")))
+        );
+        assert_eq!(Palette::parse("#FFFFFF"), Err(PaletteError::TooFewColors));
+
+        let palette = Palette::parse("#000000, #FFB000").unwrap();
+        assert_eq!(palette.get_name(), "custom");
+        assert_eq!(palette.background(), Color::new(0, 0, 0));
+        assert_eq!(palette.foreground(), Color::new(0xFF, 0xB0, 0x00));
+        // Planes without a colour of their own use the foreground.
+        assert_eq!(palette.color(3), palette.foreground());
+    }
+
+    #[test]
+    fn next_cycles_through_the_built_in_palettes() {
+        let mut palette = Palette::default();
+        let mut names = Vec::new();
+
+        for _ in 0..PALETTE_NAMES.len() {
+            palette = palette.next();
+            names.push(palette.get_name().to_string());
+        }
+
+        assert_eq!(names, ["green", "amber", "lcd", "high-contrast", "classic"]);
+    }
+
+    #[test]
+    fn next_comes_back_to_a_custom_palette() {
+        let custom = Palette::parse("#102030,#405060").unwrap();
+        let mut palette = custom.next();
+        assert_eq!(palette.get_name(), "classic");
+
+        for _ in 1..PALETTE_NAMES.len() {
+            palette = palette.next();
+        }
+        assert_eq!(palette.get_name(), "high-contrast");
+
+        assert_eq!(palette.next(), custom);
+    }
+
+    #[test]
+    fn shades_blend_background_into_foreground() {
+        let palette = Palette::parse("#000000,#FF8000").unwrap();
+
+        assert_eq!(palette.shade(0), palette.background());
+        assert_eq!(palette.shade(255), palette.foreground());
+        assert_eq!(palette.shade(128), Color::new(128, 64, 0));
+        assert_eq!(
+            palette.intensities_to_rgba(&[0, 255]),
+            [0, 0, 0, 255, 0xFF, 0x80, 0, 255]
+        );
+    }
+}
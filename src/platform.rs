@@ -0,0 +1,52 @@
+use crate::cpu::PROGRAM_START;
+use crate::screen::{SCREEN_HEIGHT, SCREEN_WIDTH};
+
+pub const PLATFORM_NAMES: [&str; 3] = ["chip8", "hires", "chip8x"];
+
+// The CHIP-8 variant being emulated. `HiRes` is the 64x64 two-page display
+// interpreter for the VIP: its images are loaded at 0x200 as usual but carry
+// the interpreter's own patch up to 0x2BF, so the program proper starts at
+// 0x2C0. `Chip8X` is the VIP interpreter for the VP-590 colour board and a
+// second keypad, whose larger interpreter moves programs up to 0x300.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+pub enum Platform {
+    #[default]
+    Chip8,
+    HiRes,
+    Chip8X,
+}
+
+impl Platform {
+    pub fn screen_width(&self) -> usize {
+        SCREEN_WIDTH
+    }
+
+    pub fn screen_height(&self) -> usize {
+        match self {
+            Platform::Chip8 => SCREEN_HEIGHT,
+            Platform::HiRes => SCREEN_HEIGHT * 2,
+            Platform::Chip8X => SCREEN_HEIGHT,
+        }
+    }
+
+    pub fn has_colors(&self) -> bool {
+        *self == Platform::Chip8X
+    }
+
+    // Where images are loaded unless told otherwise.
+    pub fn load_address(&self) -> u16 {
+        match self {
+            Platform::Chip8 | Platform::HiRes => PROGRAM_START,
+            Platform::Chip8X => 0x300,
+        }
+    }
+
+    // Where execution starts for an image loaded at the usual address.
+    pub fn program_start(&self) -> u16 {
+        match self {
+            Platform::Chip8 => PROGRAM_START,
+            Platform::HiRes => 0x2C0,
+            Platform::Chip8X => 0x300,
+        }
+    }
+}
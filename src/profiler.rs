@@ -0,0 +1,372 @@
+use std::collections::BTreeMap;
+use std::io::Write;
+
+use crate::opcode::Opcode;
+
+const HOT_SPOT_LIMIT: usize = 20;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+pub struct SubroutineProfile {
+    pub calls: u64,
+    pub self_cycles: u64,
+    pub total_cycles: u64,
+}
+
+// A subroutine activation. Stacks are stored as a tree of (caller, entry)
+// pairs so that recording an instruction does not copy the call stack.
+#[derive(Copy, Clone, Debug)]
+struct Frame {
+    entry: u16,
+    stack: usize,
+    start_cycle: u64,
+    // Whether the entry is not already further down the stack. Recursive
+    // subroutines are only charged for the time of the outermost call.
+    outermost: bool,
+}
+
+pub struct Profiler {
+    // Where the program starts, reported as main.
+    entry: u16,
+    cycles: u64,
+    addresses: BTreeMap<u16, (Opcode, u64)>,
+    opcodes: BTreeMap<&'static str, u64>,
+    subroutines: BTreeMap<u16, SubroutineProfile>,
+    call_stack: Vec<Frame>,
+    // Parent stack and entry of every stack seen, with its cycle count.
+    stacks: Vec<(Option<usize>, u16, u64)>,
+    stack_ids: BTreeMap<(usize, u16), usize>,
+}
+
+impl Profiler {
+    pub fn new(entry: u16) -> Profiler {
+        Profiler {
+            entry,
+            cycles: 0,
+            addresses: BTreeMap::new(),
+            opcodes: BTreeMap::new(),
+            subroutines: BTreeMap::new(),
+            call_stack: vec![Frame {
+                entry,
+                stack: 0,
+                start_cycle: 0,
+                outermost: true,
+            }],
+            stacks: vec![(None, entry, 0)],
+            stack_ids: BTreeMap::new(),
+        }
+    }
+
+    pub fn record(&mut self, address: u16, opcode: Opcode) {
+        self.cycles += 1;
+
+        self.addresses.entry(address).or_insert((opcode, 0)).1 += 1;
+        *self.opcodes.entry(opcode.name()).or_insert(0) += 1;
+
+        let current = *self.call_stack.last().unwrap();
+        self.stacks[current.stack].2 += 1;
+        self.subroutines
+            .entry(current.entry)
+            .or_default()
+            .self_cycles += 1;
+
+        match opcode {
+            Opcode::CallAddress { address } => {
+                self.subroutines.entry(address).or_default().calls += 1;
+
+                let next_id = self.stacks.len();
+                let stack = *self
+                    .stack_ids
+                    .entry((current.stack, address))
+                    .or_insert(next_id);
+                if stack == next_id {
+                    self.stacks.push((Some(current.stack), address, 0));
+                }
+
+                let outermost = self.call_stack.iter().all(|frame| frame.entry != address);
+                self.call_stack.push(Frame {
+                    entry: address,
+                    stack,
+                    start_cycle: self.cycles,
+                    outermost,
+                });
+            }
+            Opcode::ReturnFromSubroutine if self.call_stack.len() > 1 => {
+                let frame = self.call_stack.pop().unwrap();
+                if frame.outermost {
+                    self.subroutines
+                        .entry(frame.entry)
+                        .or_default()
+                        .total_cycles += self.cycles - frame.start_cycle;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    pub fn address_counts(&self) -> BTreeMap<u16, u64> {
+        self.addresses
+            .iter()
+            .map(|(&address, &(_, count))| (address, count))
+            .collect()
+    }
+
+    pub fn opcode_counts(&self) -> &BTreeMap<&'static str, u64> {
+        &self.opcodes
+    }
+
+    // Subroutines still on the stack are charged up to now.
+    pub fn subroutines(&self) -> BTreeMap<u16, SubroutineProfile> {
+        let mut subroutines = self.subroutines.clone();
+
+        for frame in self.call_stack.iter().filter(|frame| frame.outermost) {
+            subroutines.entry(frame.entry).or_default().total_cycles +=
+                self.cycles - frame.start_cycle;
+        }
+
+        subroutines
+    }
+
+    fn percentage(&self, count: u64) -> f64 {
+        if self.cycles == 0 {
+            0.0
+        } else {
+            count as f64 * 100.0 / self.cycles as f64
+        }
+    }
+
+    pub fn write_report<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        writeln!(writer, "Total cycles: {}", self.cycles)?;
+
+        let mut hot_spots: Vec<(&u16, &(Opcode, u64))> = self.addresses.iter().collect();
+        hot_spots.sort_by(|a, b| b.1 .1.cmp(&a.1 .1).then(a.0.cmp(b.0)));
+
+        writeln!(writer)?;
+        writeln!(writer, "Hot spots:")?;
+        writeln!(
+            writer,
+            "  {:<8} {:>12} {:>7}  instruction",
+            "address", "cycles", "%"
+        )?;
+        for (address, (opcode, count)) in hot_spots.into_iter().take(HOT_SPOT_LIMIT) {
+            writeln!(
+                writer,
+                "  0x{:03X}    {:>12} {:>6.2}%  {}",
+                address,
+                count,
+                self.percentage(*count),
+                opcode
+            )?;
+        }
+
+        let mut opcodes: Vec<(&&'static str, &u64)> = self.opcodes.iter().collect();
+        opcodes.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+
+        writeln!(writer)?;
+        writeln!(writer, "Opcodes:")?;
+        for (name, count) in opcodes {
+            writeln!(
+                writer,
+                "  {:<36} {:>12} {:>6.2}%",
+                name,
+                count,
+                self.percentage(*count)
+            )?;
+        }
+
+        let subroutines = self.subroutines();
+        let mut subroutines: Vec<(&u16, &SubroutineProfile)> = subroutines.iter().collect();
+        subroutines.sort_by(|a, b| b.1.total_cycles.cmp(&a.1.total_cycles).then(a.0.cmp(b.0)));
+
+        writeln!(writer)?;
+        writeln!(writer, "Subroutines:")?;
+        writeln!(
+            writer,
+            "  {:<8} {:>10} {:>12} {:>12} {:>7}",
+            "entry", "calls", "self", "total", "%"
+        )?;
+        for (entry, profile) in subroutines {
+            writeln!(
+                writer,
+                "  0x{:03X}    {:>10} {:>12} {:>12} {:>6.2}%",
+                entry,
+                profile.calls,
+                profile.self_cycles,
+                profile.total_cycles,
+                self.percentage(profile.total_cycles)
+            )?;
+        }
+
+        Ok(())
+    }
+
+    fn stack_entries(&self, mut stack: usize) -> Vec<u16> {
+        let mut entries = Vec::new();
+
+        loop {
+            let (parent, entry, _) = self.stacks[stack];
+            entries.push(entry);
+
+            match parent {
+                Some(parent) => stack = parent,
+                None => break,
+            }
+        }
+
+        entries.reverse();
+        entries
+    }
+
+    pub fn write_folded_stacks<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        let stacks: BTreeMap<Vec<u16>, u64> = (0..self.stacks.len())
+            .filter(|&stack| self.stacks[stack].2 > 0)
+            .map(|stack| (self.stack_entries(stack), self.stacks[stack].2))
+            .collect();
+
+        for (stack, count) in &stacks {
+            let frames: Vec<String> = stack
+                .iter()
+                .map(|&entry| {
+                    if entry == self.entry {
+                        String::from("main")
+                    } else {
+                        format!("sub_0x{:03X}", entry)
+                    }
+                })
+                .collect();
+
+            writeln!(writer, "{} {}", frames.join(";"), count)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn profile(program: &[(u16, u16)]) -> Profiler {
+        let mut profiler = Profiler::new(0x200);
+
+        for &(address, raw) in program {
+            profiler.record(address, Opcode::decode(raw).unwrap());
+        }
+
+        profiler
+    }
+
+    fn folded_stacks(profiler: &Profiler) -> String {
+        let mut folded = Vec::new();
+        profiler.write_folded_stacks(&mut folded).unwrap();
+
+        String::from_utf8(folded).unwrap()
+    }
+
+    #[test]
+    fn counts_addresses_and_opcodes() {
+        let profiler = profile(&[(0x200, 0x6001), (0x202, 0x1200), (0x200, 0x6001)]);
+
+        assert_eq!(profiler.cycles(), 3);
+        assert_eq!(
+            profiler.address_counts(),
+            BTreeMap::from([(0x200, 2), (0x202, 1)])
+        );
+        assert_eq!(profiler.opcode_counts().values().sum::<u64>(), 3);
+        assert_eq!(profiler.opcode_counts().len(), 2);
+    }
+
+    #[test]
+    fn charges_subroutines_for_their_own_and_nested_cycles() {
+        // main calls 0x300 twice, which calls 0x400 each time.
+        let call = [
+            (0x300, 0x6001),
+            (0x302, 0x2400),
+            (0x400, 0x00EE),
+            (0x304, 0x00EE),
+        ];
+        let mut program = vec![(0x200, 0x2300)];
+        program.extend(call);
+        program.push((0x202, 0x2300));
+        program.extend(call);
+        program.push((0x204, 0x1204));
+        let profiler = profile(&program);
+
+        let subroutines = profiler.subroutines();
+        assert_eq!(
+            subroutines[&0x200],
+            SubroutineProfile {
+                calls: 0,
+                self_cycles: 3,
+                total_cycles: 11,
+            }
+        );
+        assert_eq!(
+            subroutines[&0x300],
+            SubroutineProfile {
+                calls: 2,
+                self_cycles: 6,
+                total_cycles: 8,
+            }
+        );
+        assert_eq!(
+            subroutines[&0x400],
+            SubroutineProfile {
+                calls: 2,
+                self_cycles: 2,
+                total_cycles: 2,
+            }
+        );
+        assert_eq!(
+            folded_stacks(&profiler),
+            "main 3\nmain;sub_0x300 6\nmain;sub_0x300;sub_0x400 2\n"
+        );
+    }
+
+    #[test]
+    fn recursion_is_charged_once() {
+        // 0x300 calls itself once, then both calls return.
+        let profiler = profile(&[
+            (0x200, 0x2300),
+            (0x300, 0x3001),
+            (0x302, 0x2300),
+            (0x300, 0x3001),
+            (0x304, 0x00EE),
+            (0x304, 0x00EE),
+        ]);
+
+        let subroutines = profiler.subroutines();
+        assert_eq!(subroutines[&0x300].calls, 2);
+        assert_eq!(subroutines[&0x300].self_cycles, 5);
+        assert_eq!(subroutines[&0x300].total_cycles, 5);
+        assert_eq!(
+            folded_stacks(&profiler),
+            "main 1\nmain;sub_0x300 3\nmain;sub_0x300;sub_0x300 2\n"
+        );
+    }
+
+    #[test]
+    fn subroutines_still_running_are_charged_so_far() {
+        let profiler = profile(&[(0x200, 0x2300), (0x300, 0x6001), (0x302, 0x6002)]);
+
+        let subroutines = profiler.subroutines();
+        assert_eq!(subroutines[&0x200].total_cycles, 3);
+        assert_eq!(subroutines[&0x300].total_cycles, 2);
+        assert_eq!(subroutines[&0x300].self_cycles, 2);
+    }
+
+    #[test]
+    fn main_is_the_entry_point() {
+        // A CHIP-8X program, which starts at 0x300.
+        let mut profiler = Profiler::new(0x300);
+        profiler.record(0x300, Opcode::decode(0x2400).unwrap());
+        profiler.record(0x400, Opcode::decode(0x00EE).unwrap());
+        profiler.record(0x302, Opcode::decode(0x1302).unwrap());
+
+        assert_eq!(profiler.subroutines()[&0x300].total_cycles, 3);
+        assert_eq!(folded_stacks(&profiler), "main 2\nmain;sub_0x400 1\n");
+    }
+}
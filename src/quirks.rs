@@ -0,0 +1,167 @@
+pub const QUIRK_PRESETS: [&str; 3] = ["chip8", "schip", "xochip"];
+pub const QUIRK_NAMES: [&str; 8] = [
+    "shift",
+    "load-store",
+    "jump",
+    "vf-reset",
+    "clip",
+    "key-release",
+    "key-wait-timers",
+    "display-wait",
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum QuirksError {
+    UnknownQuirk(String),
+}
+
+// Behaviours that differ between CHIP-8 interpreters. The defaults match what
+// this emulator has always done, except that FX0A waits for the key to be
+// released as on the COSMAC VIP; the presets follow the original COSMAC VIP
+// interpreter, SUPER-CHIP 1.1 and XO-CHIP.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct Quirks {
+    // 8XY6/8XYE shift VY into VX instead of shifting VX in place.
+    pub shift_uses_vy: bool,
+    // FX55/FX65 leave I pointing past the last register transferred.
+    pub load_store_increments_index: bool,
+    // BNNN jumps to NNN + VX, where X is the high nibble of NNN.
+    pub jump_uses_vx: bool,
+    // 8XY1/8XY2/8XY3 clear VF.
+    pub logic_resets_vf: bool,
+    // Sprites are cut off at the screen edges instead of wrapping around.
+    pub clip_sprites: bool,
+    // FX0A completes when the key is released rather than when it goes down.
+    pub wait_for_key_release: bool,
+    // The delay and sound timers stop counting while FX0A waits.
+    pub key_wait_stops_timers: bool,
+    // DXYN waits for the display interrupt, so it ends the frame.
+    pub display_wait: bool,
+}
+
+impl Default for Quirks {
+    fn default() -> Self {
+        Quirks {
+            shift_uses_vy: false,
+            load_store_increments_index: false,
+            jump_uses_vx: false,
+            logic_resets_vf: false,
+            clip_sprites: false,
+            wait_for_key_release: true,
+            key_wait_stops_timers: false,
+            display_wait: false,
+        }
+    }
+}
+
+impl Quirks {
+    pub fn preset(name: &str) -> Result<Quirks, QuirksError> {
+        match name {
+            "chip8" => Ok(Quirks {
+                shift_uses_vy: true,
+                load_store_increments_index: true,
+                jump_uses_vx: false,
+                logic_resets_vf: true,
+                clip_sprites: true,
+                wait_for_key_release: true,
+                key_wait_stops_timers: false,
+                display_wait: true,
+            }),
+            "schip" => Ok(Quirks {
+                shift_uses_vy: false,
+                load_store_increments_index: false,
+                jump_uses_vx: true,
+                logic_resets_vf: false,
+                clip_sprites: true,
+                wait_for_key_release: false,
+                key_wait_stops_timers: false,
+                display_wait: false,
+            }),
+            "xochip" => Ok(Quirks {
+                shift_uses_vy: true,
+                load_store_increments_index: true,
+                jump_uses_vx: false,
+                logic_resets_vf: false,
+                clip_sprites: false,
+                wait_for_key_release: true,
+                key_wait_stops_timers: false,
+                display_wait: false,
+            }),
+            _ => Err(QuirksError::UnknownQuirk(name.to_string())),
+        }
+    }
+
+    fn flag_mut(&mut self, name: &str) -> Option<&mut bool> {
+        match name {
+            "shift" => Some(&mut self.shift_uses_vy),
+            "load-store" => Some(&mut self.load_store_increments_index),
+            "jump" => Some(&mut self.jump_uses_vx),
+            "vf-reset" => Some(&mut self.logic_resets_vf),
+            "clip" => Some(&mut self.clip_sprites),
+            "key-release" => Some(&mut self.wait_for_key_release),
+            "key-wait-timers" => Some(&mut self.key_wait_stops_timers),
+            "display-wait" => Some(&mut self.display_wait),
+            _ => None,
+        }
+    }
+
+    // Accepts a comma separated list applied from left to right, where each
+    // item is a preset name, a quirk name to enable or a quirk name prefixed
+    // with "no-" to disable, e.g. "schip,no-clip".
+    pub fn parse(text: &str) -> Result<Quirks, QuirksError> {
+        let mut quirks = Quirks::default();
+
+        for item in text
+            .split(',')
+            .map(str::trim)
+            .filter(|item| !item.is_empty())
+        {
+            if QUIRK_PRESETS.contains(&item) {
+                quirks = Quirks::preset(item)?;
+                continue;
+            }
+
+            let (name, enabled) = match item.strip_prefix("no-") {
+                Some(name) => (name, false),
+                None => (item, true),
+            };
+
+            match quirks.flag_mut(name) {
+                Some(flag) => *flag = enabled,
+                None => return Err(QuirksError::UnknownQuirk(item.to_string())),
+            }
+        }
+
+        Ok(quirks)
+    }
+}
+
+// Lists every quirk, so that parsing the result gives back the same quirks.
+impl std::fmt::Display for Quirks {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let flags = [
+            self.shift_uses_vy,
+            self.load_store_increments_index,
+            self.jump_uses_vx,
+            self.logic_resets_vf,
+            self.clip_sprites,
+            self.wait_for_key_release,
+            self.key_wait_stops_timers,
+            self.display_wait,
+        ];
+
+        let items: Vec<String> = QUIRK_NAMES
+            .iter()
+            .zip(flags)
+            .map(|(name, enabled)| {
+                if enabled {
+                    name.to_string()
+                } else {
+                    format!("no-{}", name)
+                }
+            })
+            .collect();
+
+        write!(f, "{}", items.join(","))
+    }
+}
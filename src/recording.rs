@@ -0,0 +1,456 @@
+use std::borrow::Cow;
+use std::fs::File;
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+
+use crate::palette::Palette;
+use crate::screen::Screen;
+
+pub const FRAMES_PER_SECOND: u32 = 60;
+pub const SAMPLE_RATE: u32 = 44_100;
+const SAMPLES_PER_FRAME: u32 = SAMPLE_RATE / FRAMES_PER_SECOND;
+const TONE_FREQUENCY: u32 = 440;
+const TONE_AMPLITUDE: i16 = 8_000;
+const WAV_HEADER_SIZE: u32 = 44;
+
+#[derive(Debug)]
+pub enum RecordingError {
+    IoError(std::io::Error),
+    GifError(gif::EncodingError),
+}
+
+impl From<std::io::Error> for RecordingError {
+    fn from(error: std::io::Error) -> Self {
+        RecordingError::IoError(error)
+    }
+}
+
+impl From<gif::EncodingError> for RecordingError {
+    fn from(error: gif::EncodingError) -> Self {
+        RecordingError::GifError(error)
+    }
+}
+
+// RGBA pixels as indices into the colours they use. There are few of those:
+// the two palette colours, or the CHIP-8X background and foreground colours,
+// and the shades in between when a display filter is on.
+fn indexed_pixels(pixels: &[u8]) -> (Vec<u8>, Vec<u8>) {
+    let mut colors: Vec<[u8; 3]> = Vec::new();
+
+    let indices = pixels
+        .chunks(4)
+        .map(|rgba| {
+            let color = [rgba[0], rgba[1], rgba[2]];
+
+            match colors.iter().position(|&known| known == color) {
+                Some(index) => index as u8,
+                None if colors.len() < 256 => {
+                    colors.push(color);
+                    (colors.len() - 1) as u8
+                }
+                // A GIF palette cannot hold more, so use the nearest colour.
+                None => (0..colors.len())
+                    .min_by_key(|&index| {
+                        (0..3)
+                            .map(|i| (colors[index][i] as i32 - color[i] as i32).pow(2))
+                            .sum::<i32>()
+                    })
+                    .unwrap() as u8,
+            }
+        })
+        .collect();
+
+    (indices, colors.concat())
+}
+
+// GIF delays are in hundredths of a second, which cannot express 1/60 s.
+// Deriving each delay from the absolute frame number keeps the total length
+// exact instead of drifting.
+fn centiseconds(frame: u64) -> u64 {
+    (frame * 100 + FRAMES_PER_SECOND as u64 / 2) / FRAMES_PER_SECOND as u64
+}
+
+pub struct GifRecorder {
+    encoder: gif::Encoder<BufWriter<File>>,
+    width: u16,
+    height: u16,
+    // Indices, their colours and the frame they were first seen on.
+    pending: Option<(Vec<u8>, Vec<u8>, u64)>,
+    frame: u64,
+}
+
+impl GifRecorder {
+    // The recording keeps the size `screen` has when it starts.
+    pub fn new(
+        filename: &str,
+        screen: &Screen,
+        palette: &Palette,
+    ) -> Result<GifRecorder, RecordingError> {
+        let (width, height) = (screen.get_width() as u16, screen.get_height() as u16);
+        let global_palette: Vec<u8> = (0..2)
+            .flat_map(|planes| {
+                let color = palette.color(planes);
+                [color.red, color.green, color.blue]
+            })
+            .collect();
+
+        let file = BufWriter::new(File::create(filename)?);
+        let mut encoder = gif::Encoder::new(file, width, height, &global_palette)?;
+        encoder.set_repeat(gif::Repeat::Infinite)?;
+
+        Ok(GifRecorder {
+            encoder,
+            width,
+            height,
+            pending: None,
+            frame: 0,
+        })
+    }
+
+    // Records RGBA `pixels` the size the recording started with. Identical
+    // consecutive frames are merged into one longer frame.
+    pub fn record_frame(&mut self, pixels: &[u8]) -> Result<(), RecordingError> {
+        let (indices, colors) = indexed_pixels(pixels);
+
+        match &self.pending {
+            Some((pending, pending_colors, _))
+                if *pending == indices && *pending_colors == colors => {}
+            _ => {
+                self.flush_pending()?;
+                self.pending = Some((indices, colors, self.frame));
+            }
+        }
+
+        self.frame += 1;
+
+        Ok(())
+    }
+
+    fn flush_pending(&mut self) -> Result<(), RecordingError> {
+        if let Some((indices, colors, start)) = self.pending.take() {
+            let mut frame = gif::Frame {
+                width: self.width,
+                height: self.height,
+                buffer: Cow::Owned(indices),
+                palette: Some(colors),
+                ..gif::Frame::default()
+            };
+            frame.delay =
+                (centiseconds(self.frame) - centiseconds(start)).min(u16::MAX as u64) as u16;
+
+            self.encoder.write_frame(&frame)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> Result<(), RecordingError> {
+        self.flush_pending()?;
+        self.encoder.into_inner()?.flush()?;
+
+        Ok(())
+    }
+}
+
+pub struct Y4mRecorder {
+    writer: BufWriter<File>,
+}
+
+impl Y4mRecorder {
+    pub fn new(filename: &str, screen: &Screen) -> Result<Y4mRecorder, RecordingError> {
+        let mut writer = BufWriter::new(File::create(filename)?);
+
+        writeln!(
+            writer,
+            "YUV4MPEG2 W{} H{} F{}:1 Ip A1:1 C444",
+            screen.get_width(),
+            screen.get_height(),
+            FRAMES_PER_SECOND
+        )?;
+
+        Ok(Y4mRecorder { writer })
+    }
+
+    pub fn record_frame(&mut self, pixels: &[u8]) -> Result<(), RecordingError> {
+        let mut planes = [
+            Vec::with_capacity(pixels.len() / 4),
+            Vec::with_capacity(pixels.len() / 4),
+            Vec::with_capacity(pixels.len() / 4),
+        ];
+
+        // BT.601 limited range, which is what encoders assume for Y4M input.
+        for rgba in pixels.chunks(4) {
+            let (r, g, b) = (rgba[0] as f32, rgba[1] as f32, rgba[2] as f32);
+
+            planes[0].push((16.0 + (65.738 * r + 129.057 * g + 25.064 * b) / 256.0).round() as u8);
+            planes[1]
+                .push((128.0 + (-37.945 * r - 74.494 * g + 112.439 * b) / 256.0).round() as u8);
+            planes[2].push((128.0 + (112.439 * r - 94.154 * g - 18.285 * b) / 256.0).round() as u8);
+        }
+
+        self.writer.write_all(b"FRAME\n")?;
+        for plane in &planes {
+            self.writer.write_all(plane)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> Result<(), RecordingError> {
+        self.writer.flush()?;
+
+        Ok(())
+    }
+}
+
+pub struct WavRecorder {
+    writer: BufWriter<File>,
+    samples: u32,
+}
+
+impl WavRecorder {
+    pub fn new(filename: &str) -> Result<WavRecorder, RecordingError> {
+        let mut recorder = WavRecorder {
+            writer: BufWriter::new(File::create(filename)?),
+            samples: 0,
+        };
+
+        recorder.write_header()?;
+
+        Ok(recorder)
+    }
+
+    fn write_header(&mut self) -> std::io::Result<()> {
+        let data_size = self.samples * 2;
+
+        self.writer.write_all(b"RIFF")?;
+        self.writer
+            .write_all(&(WAV_HEADER_SIZE - 8 + data_size).to_le_bytes())?;
+        self.writer.write_all(b"WAVEfmt ")?;
+        self.writer.write_all(&16u32.to_le_bytes())?;
+        self.writer.write_all(&1u16.to_le_bytes())?;
+        self.writer.write_all(&1u16.to_le_bytes())?;
+        self.writer.write_all(&SAMPLE_RATE.to_le_bytes())?;
+        self.writer.write_all(&(SAMPLE_RATE * 2).to_le_bytes())?;
+        self.writer.write_all(&2u16.to_le_bytes())?;
+        self.writer.write_all(&16u16.to_le_bytes())?;
+        self.writer.write_all(b"data")?;
+        self.writer.write_all(&data_size.to_le_bytes())?;
+
+        Ok(())
+    }
+
+    pub fn record_frame(&mut self, sound_active: bool) -> Result<(), RecordingError> {
+        let half_period = SAMPLE_RATE / TONE_FREQUENCY / 2;
+
+        for _ in 0..SAMPLES_PER_FRAME {
+            let sample = if !sound_active {
+                0
+            } else if (self.samples / half_period).is_multiple_of(2) {
+                TONE_AMPLITUDE
+            } else {
+                -TONE_AMPLITUDE
+            };
+
+            self.writer.write_all(&sample.to_le_bytes())?;
+            self.samples += 1;
+        }
+
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> Result<(), RecordingError> {
+        self.writer.seek(SeekFrom::Start(0))?;
+        self.write_header()?;
+        self.writer.flush()?;
+
+        Ok(())
+    }
+}
+
+// Records every emulated frame to the enabled outputs. The emulation loop
+// calls record_frame once per frame, so recordings do not depend on how fast
+// the host runs.
+#[derive(Default)]
+pub struct Recorder {
+    gif: Option<GifRecorder>,
+    y4m: Option<Y4mRecorder>,
+    wav: Option<WavRecorder>,
+}
+
+impl Recorder {
+    pub fn new() -> Recorder {
+        Recorder::default()
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.gif.is_some() || self.y4m.is_some() || self.wav.is_some()
+    }
+
+    pub fn start_gif(
+        &mut self,
+        filename: &str,
+        screen: &Screen,
+        palette: &Palette,
+    ) -> Result<(), RecordingError> {
+        self.gif = Some(GifRecorder::new(filename, screen, palette)?);
+
+        Ok(())
+    }
+
+    // Writes `<prefix>.y4m` and `<prefix>.wav` with matching lengths.
+    pub fn start_video(&mut self, prefix: &str, screen: &Screen) -> Result<(), RecordingError> {
+        self.y4m = Some(Y4mRecorder::new(&format!("{}.y4m", prefix), screen)?);
+        self.wav = Some(WavRecorder::new(&format!("{}.wav", prefix))?);
+
+        Ok(())
+    }
+
+    pub fn is_recording_gif(&self) -> bool {
+        self.gif.is_some()
+    }
+
+    pub fn is_recording_video(&self) -> bool {
+        self.y4m.is_some()
+    }
+
+    pub fn stop_gif(&mut self) -> Result<(), RecordingError> {
+        match self.gif.take() {
+            Some(gif) => gif.finish(),
+            None => Ok(()),
+        }
+    }
+
+    pub fn stop_video(&mut self) -> Result<(), RecordingError> {
+        if let Some(y4m) = self.y4m.take() {
+            y4m.finish()?;
+        }
+
+        match self.wav.take() {
+            Some(wav) => wav.finish(),
+            None => Ok(()),
+        }
+    }
+
+    // `pixels` is the frame as it is shown, in RGBA.
+    pub fn record_frame(
+        &mut self,
+        pixels: &[u8],
+        sound_active: bool,
+    ) -> Result<(), RecordingError> {
+        if let Some(gif) = self.gif.as_mut() {
+            gif.record_frame(pixels)?;
+        }
+
+        if let Some(y4m) = self.y4m.as_mut() {
+            y4m.record_frame(pixels)?;
+        }
+
+        if let Some(wav) = self.wav.as_mut() {
+            wav.record_frame(sound_active)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> Result<(), RecordingError> {
+        self.stop_gif()?;
+        self.stop_video()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::display_filter::{DisplayFilter, FilterMode};
+
+    fn decode_gif(path: &str) -> Vec<Vec<u8>> {
+        let mut options = gif::DecodeOptions::new();
+        options.set_color_output(gif::ColorOutput::RGBA);
+        let mut decoder = options.read_info(File::open(path).unwrap()).unwrap();
+
+        let mut frames = Vec::new();
+        while let Some(frame) = decoder.read_next_frame().unwrap() {
+            frames.push(frame.buffer.to_vec());
+        }
+
+        frames
+    }
+
+    #[test]
+    fn gif_frames_match_the_screen_colours() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("colours.gif");
+        let path = path.to_str().unwrap();
+        let palette = Palette::named("amber").unwrap();
+        let mut screen = Screen::new();
+        screen.enable_colors();
+        screen.set_pixel(0, 0, true);
+        screen.set_pixel(20, 10, true);
+
+        let mut recorder = GifRecorder::new(path, &screen, &palette).unwrap();
+        recorder.record_frame(&screen.get_pixels(&palette)).unwrap();
+        let first = screen.get_pixels(&palette);
+
+        screen.colors_mut().unwrap().fill_zones(0x10, 0x0A, 2);
+        screen.colors_mut().unwrap().cycle_background();
+        recorder.record_frame(&screen.get_pixels(&palette)).unwrap();
+        recorder.record_frame(&screen.get_pixels(&palette)).unwrap();
+        let second = screen.get_pixels(&palette);
+        recorder.finish().unwrap();
+
+        let frames = decode_gif(path);
+
+        assert_ne!(first, second);
+        assert_eq!(frames, [first, second]);
+    }
+
+    #[test]
+    fn gif_frames_use_the_palette_without_colours() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("palette.gif");
+        let path = path.to_str().unwrap();
+        let palette = Palette::named("amber").unwrap();
+        let mut screen = Screen::new();
+        screen.set_pixel(3, 4, true);
+
+        let mut recorder = GifRecorder::new(path, &screen, &palette).unwrap();
+        recorder.record_frame(&screen.get_pixels(&palette)).unwrap();
+        recorder.finish().unwrap();
+
+        let frames = decode_gif(path);
+
+        assert_eq!(frames, [screen.get_pixels(&palette)]);
+    }
+
+    #[test]
+    fn gif_frames_keep_the_display_filter_shades() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("filtered.gif");
+        let path = path.to_str().unwrap();
+        let palette = Palette::named("amber").unwrap();
+        let mut filter = DisplayFilter::new(FilterMode::Phosphor { decay: 0.5 });
+        let mut screen = Screen::new();
+        let mut expected = Vec::new();
+
+        screen.set_pixel(3, 4, true);
+        let mut recorder = GifRecorder::new(path, &screen, &palette).unwrap();
+
+        for _ in 0..2 {
+            let pixels = screen.get_filtered_pixels(&filter.apply(&screen), &palette);
+            recorder.record_frame(&pixels).unwrap();
+            expected.push(pixels);
+            screen.set_pixel(3, 4, false);
+        }
+        recorder.finish().unwrap();
+
+        let frames = decode_gif(path);
+
+        let shade = palette.shade(128);
+        let offset = (4 * screen.get_width() + 3) * 4;
+        assert_eq!(
+            frames[1][offset..offset + 3],
+            [shade.red, shade.green, shade.blue]
+        );
+        assert_eq!(frames, expected);
+    }
+}
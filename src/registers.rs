@@ -1,5 +1,6 @@
 pub const NUM_REGISTERS: usize = 16;
 
+#[derive(Clone)]
 pub struct Registers {
     values: [u8; NUM_REGISTERS],
 }
@@ -9,6 +10,12 @@ pub enum RegisterError {
     IndexOutOfBounds(u8),
 }
 
+impl Default for Registers {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Registers {
     pub fn new() -> Registers {
         Registers {
@@ -33,6 +40,10 @@ impl Registers {
         self.get_value(index)
     }
 
+    pub fn read_all(&self) -> [u8; NUM_REGISTERS] {
+        self.values
+    }
+
     pub fn read_multiple(&self, start_index: u8, end_index: u8) -> Result<&[u8], RegisterError> {
         if start_index > end_index {
             return Err(RegisterError::IndexOutOfBounds(start_index));
@@ -54,7 +65,7 @@ impl Registers {
 
         let target_slice = self
             .values
-            .get_mut((start_index as usize)..(end_index as usize))
+            .get_mut((start_index as usize)..end_index)
             .ok_or(RegisterError::IndexOutOfBounds(start_index))?;
 
         target_slice.copy_from_slice(values);
@@ -153,10 +164,10 @@ impl Registers {
     where
         F: Fn(u8) -> (u8, u8),
     {
-        let value = self.get_value(index)?.clone();
+        let value = self.get_value(index)?;
         let (result, vf_value) = op(value);
 
-        *self.get_value_mut(0xF)? = vf_value as u8;
+        *self.get_value_mut(0xF)? = vf_value;
 
         *self.get_value_mut(index)? = result;
 
@@ -0,0 +1,393 @@
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use crate::memory::MEMORY_SIZE;
+use crate::octo::{self, AssemblyError};
+
+const ROM_EXTENSIONS: [&str; 5] = ["ch8", "c8", "sc8", "xo8", "rom"];
+const HEX_EXTENSIONS: [&str; 2] = ["hex", "txt"];
+const GIF_SIGNATURE: &[u8] = b"GIF8";
+
+#[derive(Debug)]
+pub enum RomError {
+    IoError(std::io::Error),
+    ZipError(zip::result::ZipError),
+    InvalidHex { line: usize, token: String },
+    NoRomInArchive,
+    MultipleRomsInArchive(Vec<String>),
+    EntryNotFound(String),
+    GifError(gif::DecodingError),
+    NotAGif,
+    NotACartridge,
+    AssemblyError(AssemblyError),
+    Empty,
+    TooLarge { size: usize, available: usize },
+}
+
+impl From<std::io::Error> for RomError {
+    fn from(error: std::io::Error) -> Self {
+        RomError::IoError(error)
+    }
+}
+
+impl From<zip::result::ZipError> for RomError {
+    fn from(error: zip::result::ZipError) -> Self {
+        RomError::ZipError(error)
+    }
+}
+
+impl From<gif::DecodingError> for RomError {
+    fn from(error: gif::DecodingError) -> Self {
+        RomError::GifError(error)
+    }
+}
+
+impl From<AssemblyError> for RomError {
+    fn from(error: AssemblyError) -> Self {
+        RomError::AssemblyError(error)
+    }
+}
+
+// Where a ROM image comes from. Every loader ends in `read`, which checks the
+// image fits in memory at the address it will be loaded at.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum RomSource {
+    Binary(PathBuf),
+    Hex(PathBuf),
+    Zip {
+        path: PathBuf,
+        entry: Option<String>,
+    },
+    OctoCartridge(PathBuf),
+    OctoSource(PathBuf),
+    Stdin,
+}
+
+fn has_extension(path: &Path, extensions: &[&str]) -> bool {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| {
+            extensions
+                .iter()
+                .any(|known| extension.eq_ignore_ascii_case(known))
+        })
+}
+
+// Reads hex dumps such as Octo's "0x00 0xE0 0x12 0x00" or "00E0 1200". Each
+// token is one or more whole bytes. An "ADDRESS:" label at the start of a line
+// and comments starting with '#', ';' or "//" are ignored.
+pub fn parse_hex(text: &str) -> Result<Vec<u8>, RomError> {
+    let mut rom = Vec::new();
+
+    for (number, line) in text.lines().enumerate() {
+        let line = line
+            .split(['#', ';'])
+            .next()
+            .and_then(|line| line.split("//").next())
+            .unwrap_or("");
+
+        let line = match line.split_once(':') {
+            Some((_, rest)) => rest,
+            None => line,
+        };
+
+        for token in line.split(|c: char| c.is_whitespace() || c == ',') {
+            let digits = token
+                .strip_prefix("0x")
+                .or_else(|| token.strip_prefix("0X"))
+                .unwrap_or(token);
+
+            if digits.is_empty() && token.is_empty() {
+                continue;
+            }
+
+            let invalid = || RomError::InvalidHex {
+                line: number + 1,
+                token: token.to_string(),
+            };
+
+            if digits.is_empty() || !digits.len().is_multiple_of(2) {
+                return Err(invalid());
+            }
+
+            for i in (0..digits.len()).step_by(2) {
+                let byte = digits
+                    .get(i..i + 2)
+                    .and_then(|pair| u8::from_str_radix(pair, 16).ok())
+                    .ok_or_else(invalid)?;
+                rom.push(byte);
+            }
+        }
+    }
+
+    Ok(rom)
+}
+
+impl RomSource {
+    // Picks a loader from the file name: "-" is stdin, otherwise the
+    // extension decides, with anything unknown treated as a raw binary.
+    pub fn from_argument(argument: &str) -> RomSource {
+        if argument == "-" {
+            return RomSource::Stdin;
+        }
+
+        let path = PathBuf::from(argument);
+
+        if has_extension(&path, &["zip"]) {
+            RomSource::Zip { path, entry: None }
+        } else if has_extension(&path, &["gif"]) {
+            RomSource::OctoCartridge(path)
+        } else if has_extension(&path, &["8o"]) {
+            RomSource::OctoSource(path)
+        } else if has_extension(&path, &HEX_EXTENSIONS) {
+            RomSource::Hex(path)
+        } else {
+            RomSource::Binary(path)
+        }
+    }
+
+    pub fn with_entry(self, entry: &str) -> RomSource {
+        match self {
+            RomSource::Zip { path, .. } => RomSource::Zip {
+                path,
+                entry: Some(entry.to_string()),
+            },
+            source => source,
+        }
+    }
+
+    pub fn path(&self) -> Option<&Path> {
+        match self {
+            RomSource::Binary(path)
+            | RomSource::Hex(path)
+            | RomSource::Zip { path, .. }
+            | RomSource::OctoCartridge(path)
+            | RomSource::OctoSource(path) => Some(path),
+            RomSource::Stdin => None,
+        }
+    }
+
+    pub fn name(&self) -> String {
+        match self {
+            RomSource::Zip {
+                path,
+                entry: Some(entry),
+            } => format!("{}:{}", path.display(), entry),
+            RomSource::Stdin => "stdin".to_string(),
+            source => source.path().unwrap().display().to_string(),
+        }
+    }
+
+    // Files in the archive that look like ROMs, or every file when none has a
+    // ROM extension.
+    pub fn zip_entries(path: &Path) -> Result<Vec<String>, RomError> {
+        let archive = zip::ZipArchive::new(File::open(path)?)?;
+
+        let files: Vec<String> = archive
+            .file_names()
+            .filter(|name| !name.ends_with('/'))
+            .map(str::to_string)
+            .collect();
+
+        let roms: Vec<String> = files
+            .iter()
+            .filter(|name| has_extension(Path::new(name), &ROM_EXTENSIONS))
+            .cloned()
+            .collect();
+
+        let mut entries = if roms.is_empty() { files } else { roms };
+        entries.sort();
+
+        Ok(entries)
+    }
+
+    fn read_zip(path: &Path, entry: &Option<String>) -> Result<Vec<u8>, RomError> {
+        let name = match entry {
+            Some(entry) => entry.clone(),
+            None => {
+                let mut entries = RomSource::zip_entries(path)?;
+
+                match entries.len() {
+                    0 => return Err(RomError::NoRomInArchive),
+                    1 => entries.remove(0),
+                    _ => return Err(RomError::MultipleRomsInArchive(entries)),
+                }
+            }
+        };
+
+        let mut archive = zip::ZipArchive::new(File::open(path)?)?;
+        let mut file = match archive.by_name(&name) {
+            Ok(file) => file,
+            Err(zip::result::ZipError::FileNotFound) => return Err(RomError::EntryNotFound(name)),
+            Err(error) => return Err(error.into()),
+        };
+
+        let mut rom = Vec::new();
+        file.read_to_end(&mut rom)?;
+
+        Ok(rom)
+    }
+
+    fn read_unchecked(&self) -> Result<Vec<u8>, RomError> {
+        match self {
+            RomSource::Binary(path) => Ok(std::fs::read(path)?),
+            RomSource::Hex(path) => parse_hex(&std::fs::read_to_string(path)?),
+            RomSource::Zip { path, entry } => RomSource::read_zip(path, entry),
+            // Octo cartridges store the program's source rather than a
+            // binary, so it is assembled like a .8o file.
+            RomSource::OctoCartridge(path) => {
+                let image = std::fs::read(path)?;
+
+                if !image.starts_with(GIF_SIGNATURE) {
+                    return Err(RomError::NotAGif);
+                }
+
+                match octo::cartridge_program(&image)? {
+                    Some(source) => Ok(octo::assemble(&source)?),
+                    None => Err(RomError::NotACartridge),
+                }
+            }
+            RomSource::OctoSource(path) => Ok(octo::assemble(&std::fs::read_to_string(path)?)?),
+            RomSource::Stdin => {
+                let mut rom = Vec::new();
+                std::io::stdin().lock().read_to_end(&mut rom)?;
+
+                Ok(rom)
+            }
+        }
+    }
+
+    // Reads the image and checks it fits in memory when loaded at `address`.
+    pub fn read(&self, address: u16) -> Result<Vec<u8>, RomError> {
+        let rom = self.read_unchecked()?;
+        let available = MEMORY_SIZE.saturating_sub(address as usize);
+
+        if rom.is_empty() {
+            return Err(RomError::Empty);
+        }
+
+        if rom.len() > available {
+            return Err(RomError::TooLarge {
+                size: rom.len(),
+                available,
+            });
+        }
+
+        Ok(rom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Write;
+
+    fn write_zip(path: &Path, names: &[&str]) {
+        let mut writer = zip::ZipWriter::new(File::create(path).unwrap());
+
+        for name in names {
+            if name.ends_with('/') {
+                writer
+                    .add_directory(*name, zip::write::SimpleFileOptions::default())
+                    .unwrap();
+            } else {
+                writer
+                    .start_file(*name, zip::write::SimpleFileOptions::default())
+                    .unwrap();
+                writer.write_all(name.as_bytes()).unwrap();
+            }
+        }
+
+        writer.finish().unwrap();
+    }
+
+    #[test]
+    fn parse_hex_reads_octo_and_plain_dumps() {
+        let text = "0x00 0xE0, 0x12 0x00 # clear and loop\n0204: 00E0 1200 // again\n; only a comment\n\n0XAB";
+
+        assert_eq!(
+            parse_hex(text).unwrap(),
+            [0x00, 0xE0, 0x12, 0x00, 0x00, 0xE0, 0x12, 0x00, 0xAB]
+        );
+    }
+
+    #[test]
+    fn parse_hex_reports_the_bad_token() {
+        for (text, bad) in [
+            ("00E0\n12 0x", "0x"),
+            ("00E0\n123", "123"),
+            ("00E0\nzz", "zz"),
+        ] {
+            match parse_hex(text) {
+                Err(RomError::InvalidHex { line: 2, token }) => assert_eq!(token, bad),
+                result => panic!("{:?} for {:?}", result, text),
+            }
+        }
+    }
+
+    #[test]
+    fn zip_entries_prefer_rom_extensions() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("roms.zip");
+        write_zip(&path, &["readme.txt", "games/", "games/b.ch8", "a.SC8"]);
+
+        let entries = RomSource::zip_entries(&path);
+        let chosen = RomSource::from_argument(path.to_str().unwrap()).read(0x200);
+        let named = RomSource::from_argument(path.to_str().unwrap())
+            .with_entry("readme.txt")
+            .read(0x200);
+        let missing = RomSource::from_argument(path.to_str().unwrap())
+            .with_entry("c.ch8")
+            .read(0x200);
+
+        assert_eq!(entries.unwrap(), ["a.SC8", "games/b.ch8"]);
+        assert!(
+            matches!(chosen, Err(RomError::MultipleRomsInArchive(entries)) if entries.len() == 2)
+        );
+        assert_eq!(named.unwrap(), b"readme.txt");
+        assert!(matches!(missing, Err(RomError::EntryNotFound(entry)) if entry == "c.ch8"));
+    }
+
+    #[test]
+    fn zip_entries_fall_back_to_every_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("single.zip");
+        write_zip(&path, &["docs/", "game.bin"]);
+
+        let entries = RomSource::zip_entries(&path);
+        let rom = RomSource::from_argument(path.to_str().unwrap()).read(0x200);
+
+        assert_eq!(entries.unwrap(), ["game.bin"]);
+        assert_eq!(rom.unwrap(), b"game.bin");
+    }
+
+    #[test]
+    fn octo_source_and_cartridges_are_assembled() {
+        let dir = tempfile::tempdir().unwrap();
+        let temp_path = |name: &str| dir.path().join(name);
+        let source = temp_path("game.8o");
+        let broken = temp_path("broken.8o");
+        let cartridge = temp_path("cartridge.gif");
+        let other = temp_path("other.gif");
+        std::fs::write(&source, ": main clear loop again").unwrap();
+        std::fs::write(&broken, ": main\njump nowhere").unwrap();
+        std::fs::write(&cartridge, b"GIF89a").unwrap();
+        std::fs::write(&other, [0x00, 0xE0]).unwrap();
+
+        let read = |path: &PathBuf| RomSource::from_argument(path.to_str().unwrap()).read(0x200);
+        let source_result = read(&source);
+        let broken_result = read(&broken);
+        let cartridge_result = read(&cartridge);
+        let other_result = read(&other);
+
+        assert_eq!(source_result.unwrap(), [0x00, 0xE0, 0x12, 0x02]);
+        assert!(matches!(
+            broken_result,
+            Err(RomError::AssemblyError(AssemblyError { line: 2, .. }))
+        ));
+        assert!(matches!(cartridge_result, Err(RomError::GifError(_))));
+        assert!(matches!(other_result, Err(RomError::NotAGif)));
+    }
+}
@@ -1,35 +1,107 @@
+use std::io::BufWriter;
+
+use crate::color_layer::ColorLayer;
+use crate::palette::Palette;
+
 pub const SCREEN_WIDTH: usize = 64;
 pub const SCREEN_HEIGHT: usize = 32;
 
+#[derive(Debug)]
+pub enum ScreenshotError {
+    IoError(std::io::Error),
+    EncodingError(png::EncodingError),
+}
+
+impl From<std::io::Error> for ScreenshotError {
+    fn from(error: std::io::Error) -> Self {
+        ScreenshotError::IoError(error)
+    }
+}
+
+impl From<png::EncodingError> for ScreenshotError {
+    fn from(error: png::EncodingError) -> Self {
+        ScreenshotError::EncodingError(error)
+    }
+}
+
 #[derive(Copy, Clone, PartialEq, Eq, Debug)]
 enum Pixel {
     On,
     Off,
 }
 
+#[derive(Clone)]
 pub struct Screen {
-    screen: [[Pixel; 64]; 32], // 64x32 pixels
+    screen: Vec<Vec<Pixel>>,
+    width: usize,
+    height: usize,
+    colors: Option<ColorLayer>,
+}
+
+impl Default for Screen {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Screen {
     pub fn new() -> Screen {
+        Screen::with_size(SCREEN_WIDTH, SCREEN_HEIGHT)
+    }
+
+    pub fn with_size(width: usize, height: usize) -> Screen {
         Screen {
-            screen: [[Pixel::Off; 64]; 32],
+            screen: vec![vec![Pixel::Off; width]; height],
+            width,
+            height,
+            colors: None,
         }
     }
 
+    // Adds a CHIP-8X colour layer, reset to its power-on colours.
+    pub fn enable_colors(&mut self) {
+        self.colors = Some(ColorLayer::new(self.width, self.height));
+    }
+
+    pub fn colors(&self) -> Option<&ColorLayer> {
+        self.colors.as_ref()
+    }
+
+    pub fn colors_mut(&mut self) -> Option<&mut ColorLayer> {
+        self.colors.as_mut()
+    }
+
+    pub fn get_width(&self) -> usize {
+        self.width
+    }
+
+    pub fn get_height(&self) -> usize {
+        self.height
+    }
+
     pub fn clear(&mut self) {
-        self.screen = [[Pixel::Off; 64]; 32];
+        self.screen = vec![vec![Pixel::Off; self.width]; self.height];
     }
 
-    pub fn draw(&mut self, x: usize, y: usize, sprite: &[u8]) -> bool {
+    // With `clip` set the starting position still wraps, but the parts of the
+    // sprite that run off the right or bottom edge are not drawn.
+    pub fn draw(&mut self, x: usize, y: usize, sprite: &[u8], clip: bool) -> bool {
         let mut collision = false;
+        let (x, y) = if clip {
+            (x % self.width, y % self.height)
+        } else {
+            (x, y)
+        };
 
         for (i, byte) in sprite.iter().enumerate() {
             for j in 0..8 {
+                if clip && (x + j >= self.width || y + i >= self.height) {
+                    continue;
+                }
+
                 let pixel = (byte >> (7 - j)) & 0x1;
-                let screen_x = (x + j) % SCREEN_WIDTH;
-                let screen_y = (y + i) % SCREEN_HEIGHT;
+                let screen_x = (x + j) % self.width;
+                let screen_y = (y + i) % self.height;
 
                 if pixel == 1 {
                     if self.screen[screen_y][screen_x] == Pixel::On {
@@ -46,18 +118,66 @@ impl Screen {
         collision
     }
 
-    pub fn get_pixels(&self) -> Vec<u8> {
-        self.screen
+    pub fn get_pixel(&self, x: usize, y: usize) -> bool {
+        self.screen[y % self.height][x % self.width] == Pixel::On
+    }
+
+    pub fn set_pixel(&mut self, x: usize, y: usize, on: bool) {
+        self.screen[y % self.height][x % self.width] = if on { Pixel::On } else { Pixel::Off };
+    }
+
+    pub fn get_pixels(&self, palette: &Palette) -> Vec<u8> {
+        let planes: Vec<u8> = self
+            .screen
             .iter()
-            .flat_map(|row| {
-                row.iter().flat_map(|&pixel| {
-                    if pixel == Pixel::On {
-                        vec![255, 255, 255, 255]
-                    } else {
-                        vec![0, 0, 0, 0]
-                    }
-                })
-            })
-            .collect()
+            .flat_map(|row| row.iter().map(|&pixel| (pixel == Pixel::On) as u8))
+            .collect();
+
+        match &self.colors {
+            Some(colors) => {
+                let intensities: Vec<u8> = planes.iter().map(|&plane| plane * 255).collect();
+                colors.intensities_to_rgba(&intensities, self.width)
+            }
+            None => palette.planes_to_rgba(&planes),
+        }
+    }
+
+    // The screen in the palette or CHIP-8X colours, with each pixel lit as
+    // brightly as a display filter left it.
+    pub fn get_filtered_pixels(&self, intensities: &[u8], palette: &Palette) -> Vec<u8> {
+        match &self.colors {
+            Some(colors) => colors.intensities_to_rgba(intensities, self.width),
+            None => palette.intensities_to_rgba(intensities),
+        }
+    }
+
+    // Saves RGBA `pixels` the size of the screen, such as get_pixels returns.
+    pub fn save_png(
+        &self,
+        filename: &str,
+        pixels: &[u8],
+        scale: u32,
+    ) -> Result<(), ScreenshotError> {
+        let scale = scale.max(1) as usize;
+        let width = self.width * scale;
+        let height = self.height * scale;
+
+        let mut data = Vec::with_capacity(width * height * 4);
+        for y in 0..height {
+            for x in 0..width {
+                let offset = ((y / scale) * self.width + x / scale) * 4;
+                data.extend_from_slice(&pixels[offset..offset + 4]);
+            }
+        }
+
+        let file = std::fs::File::create(filename)?;
+        let mut encoder = png::Encoder::new(BufWriter::new(file), width as u32, height as u32);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(&data)?;
+
+        Ok(())
     }
 }
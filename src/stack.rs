@@ -1,10 +1,23 @@
 pub const STACK_SIZE: usize = 16;
 
+#[derive(Clone)]
 pub struct Stack {
     values: [u16; STACK_SIZE],
     pointer: u16,
 }
 
+#[derive(Debug, PartialEq)]
+pub enum StackError {
+    Overflow,
+    Underflow,
+}
+
+impl Default for Stack {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Stack {
     pub fn new() -> Stack {
         Stack {
@@ -13,13 +26,78 @@ impl Stack {
         }
     }
 
-    pub fn push(&mut self, value: u16) {
-        self.values[self.pointer as usize] = value;
+    pub fn get_pointer(&self) -> u16 {
+        self.pointer
+    }
+
+    // A pointer of STACK_SIZE is a full stack, which 16 nested calls reach.
+    pub fn set_pointer(&mut self, pointer: u16) -> bool {
+        if pointer as usize > STACK_SIZE {
+            return false;
+        }
+
+        self.pointer = pointer;
+        true
+    }
+
+    pub fn get_values(&self) -> [u16; STACK_SIZE] {
+        self.values
+    }
+
+    pub fn set_value(&mut self, index: usize, value: u16) {
+        self.values[index] = value;
+    }
+
+    pub fn push(&mut self, value: u16) -> Result<(), StackError> {
+        let slot = self
+            .values
+            .get_mut(self.pointer as usize)
+            .ok_or(StackError::Overflow)?;
+
+        *slot = value;
         self.pointer += 1;
+
+        Ok(())
+    }
+
+    pub fn pop(&mut self) -> Result<u16, StackError> {
+        self.pointer = self.pointer.checked_sub(1).ok_or(StackError::Underflow)?;
+
+        Ok(self.values[self.pointer as usize])
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn push_fails_on_a_full_stack() {
+        let mut stack = Stack::new();
+
+        for value in 0..STACK_SIZE as u16 {
+            stack.push(value).unwrap();
+        }
+
+        assert_eq!(stack.push(0x300), Err(StackError::Overflow));
+        assert_eq!(stack.get_pointer(), STACK_SIZE as u16);
+        assert_eq!(stack.pop(), Ok(STACK_SIZE as u16 - 1));
+    }
+
+    #[test]
+    fn pop_fails_on_an_empty_stack() {
+        let mut stack = Stack::new();
+
+        assert_eq!(stack.pop(), Err(StackError::Underflow));
+        assert_eq!(stack.get_pointer(), 0);
+    }
+
+    #[test]
+    fn set_pointer_stops_at_a_full_stack() {
+        let mut stack = Stack::new();
 
-    pub fn pop(&mut self) -> u16 {
-        self.pointer -= 1;
-        self.values[self.pointer as usize]
+        assert!(stack.set_pointer(STACK_SIZE as u16));
+        assert!(!stack.set_pointer(STACK_SIZE as u16 + 1));
+        assert_eq!(stack.push(0x200), Err(StackError::Overflow));
     }
 }
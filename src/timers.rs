@@ -8,6 +8,12 @@ pub struct DelayTimer {
     value: u8,
 }
 
+impl Default for DelayTimer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl DelayTimer {
     pub fn new() -> DelayTimer {
         DelayTimer { value: 0 }
@@ -34,6 +40,12 @@ pub struct SoundTimer {
     value: u8,
 }
 
+impl Default for SoundTimer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl SoundTimer {
     pub fn new() -> SoundTimer {
         SoundTimer { value: 0 }
@@ -51,9 +63,6 @@ impl Timer for SoundTimer {
 
     fn update(&mut self) {
         if self.value > 0 {
-            if self.value == 1 {
-                println!("BEEP!");
-            }
             self.value -= 1;
         }
     }
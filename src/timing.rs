@@ -0,0 +1,85 @@
+use crate::opcode::Opcode;
+
+pub const TIMING_NAMES: [&str; 2] = ["fixed", "vip"];
+
+// The VIP's 1.76 MHz clock gives 3668 machine cycles of 8 clock periods per
+// 60 Hz frame. About half of them go to the display interrupt: the video
+// chip's DMA and the interpreter's timer routine.
+pub const VIP_CYCLES_PER_FRAME: u64 = 3668;
+pub const VIP_INTERRUPT_CYCLES: u64 = 1832;
+// Fetching an instruction and dispatching to its routine.
+const VIP_INSTRUCTION_OVERHEAD: u64 = 68;
+// Taking a skip.
+const VIP_SKIP_CYCLES: u64 = 4;
+
+// How emulated time is measured. `Fixed` runs the same number of
+// instructions every frame, `Vip` charges every instruction roughly what the
+// original COSMAC VIP interpreter takes and fills each frame with as many
+// instructions as fit between display interrupts.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+pub enum Timing {
+    #[default]
+    Fixed,
+    Vip,
+}
+
+// Approximate VIP cost in machine cycles of running `opcode`. `vx` is the
+// value of the opcode's X register before it ran, used by instructions whose
+// routines loop over its bits or digits, and `skipped` whether it skipped.
+pub fn vip_cycles(opcode: Opcode, vx: u8, skipped: bool) -> u64 {
+    let skip = if skipped { VIP_SKIP_CYCLES } else { 0 };
+
+    let cycles = match opcode {
+        Opcode::ClearScreen => 3078,
+        Opcode::ReturnFromSubroutine => 10,
+        Opcode::JumpToAddress { .. } => 12,
+        Opcode::CallAddress { .. } => 26,
+        Opcode::SkipIfEqual { .. } | Opcode::SkipIfNotEqual { .. } => 10 + skip,
+        Opcode::SkipIfRegistersEqual { .. } | Opcode::SkipIfRegisterNotEqualRegister { .. } => {
+            14 + skip
+        }
+        Opcode::SetRegisterToByte { .. } => 6,
+        Opcode::AddByteToRegister { .. } => 10,
+        Opcode::SetRegisterToRegister { .. } => 12,
+        Opcode::SetRegisterToRegisterOrRegister { .. }
+        | Opcode::SetRegisterToRegisterAndRegister { .. }
+        | Opcode::SetRegisterToRegisterXorRegister { .. }
+        | Opcode::AddRegisterToRegister { .. }
+        | Opcode::SubtractRegisterFromRegister { .. }
+        | Opcode::ShiftRegisterRight { .. }
+        | Opcode::SetRegisterToRegisterMinusRegister { .. }
+        | Opcode::ShiftRegisterLeft { .. } => 20,
+        Opcode::SetIndexToAddress { .. } => 12,
+        Opcode::JumpToAddressPlusRegister0 { .. } => 22,
+        Opcode::SetRegisterToRandAndByte { .. } => 36,
+        // Sprite rows that straddle two display bytes take longer to merge.
+        Opcode::DrawSprite { size, .. } => {
+            let row_cycles = if vx.is_multiple_of(8) { 34 } else { 46 };
+            26 + size as u64 * row_cycles
+        }
+        Opcode::SkipIfKeyPressed { .. } | Opcode::SkipIfKeyNotPressed { .. } => 14 + skip,
+        Opcode::SetRegisterToDelayTimer { .. } => 10,
+        Opcode::WaitForKeyPress { .. } => 18,
+        Opcode::SetDelayTimerToRegister { .. } | Opcode::SetSoundTimerToRegister { .. } => 10,
+        Opcode::AddRegisterToIndex { .. } => 16,
+        Opcode::SetIndexToSpriteLocation { .. } => 16,
+        // Digits are found by repeated subtraction.
+        Opcode::StoreBCD { .. } => 80 + 16 * (vx / 100 + vx / 10 % 10 + vx % 10) as u64,
+        Opcode::StoreRegisters { last_index } => 14 + 14 * (last_index as u64 + 1),
+        Opcode::LoadRegisters {
+            last_memory_address,
+        } => 14 + 14 * (last_memory_address as u64 + 1),
+        // CHIP-8X ran on the same machine. Colour writes loop over the zones
+        // they cover.
+        Opcode::CycleBackgroundColor => 10,
+        Opcode::SetColorZones { .. } => 60,
+        Opcode::SetColorRows { height, .. } => 26 + 8 * height as u64,
+        Opcode::SkipIfSecondPadKeyPressed { .. } | Opcode::SkipIfSecondPadKeyNotPressed { .. } => {
+            14 + skip
+        }
+        Opcode::OutputToPort { .. } => 10,
+        Opcode::InputFromPort { .. } => 18,
+    };
+
+    VIP_INSTRUCTION_OVERHEAD + cycles
+}
@@ -0,0 +1,328 @@
+use std::io::{BufWriter, Write};
+use std::ops::RangeInclusive;
+
+use crate::cpu::Cpu;
+use crate::opcode::Opcode;
+
+const DEFAULT_FORMAT: &str = "{cycle:8} PC:{pc} OP:{opcode} {mnemonic:18} \
+    V0:{v0} V1:{v1} V2:{v2} V3:{v3} V4:{v4} V5:{v5} V6:{v6} V7:{v7} \
+    V8:{v8} V9:{v9} VA:{va} VB:{vb} VC:{vc} VD:{vd} VE:{ve} VF:{vf} \
+    I:{i} SP:{sp} DT:{dt} ST:{st}";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TraceFormatError {
+    InvalidField(String),
+    Unterminated,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+enum Field {
+    Cycle,
+    ProgramCounter,
+    Opcode,
+    Mnemonic,
+    Register(u8),
+    Index,
+    StackPointer,
+    DelayTimer,
+    SoundTimer,
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+enum Part {
+    Text(String),
+    Field {
+        field: Field,
+        width: Option<usize>,
+        lowercase: bool,
+    },
+}
+
+// The layout of a trace line, so traces can be compared line by line with
+// another emulator's.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct TraceFormat {
+    parts: Vec<Part>,
+}
+
+impl Default for TraceFormat {
+    fn default() -> Self {
+        TraceFormat::parse(DEFAULT_FORMAT).unwrap()
+    }
+}
+
+impl TraceFormat {
+    // Text with fields in braces: {cycle}, {pc}, {opcode}, {mnemonic},
+    // {v0} to {vf}, {i}, {sp}, {dt} and {st}. Numbers are in upper case hex
+    // except the cycle count. A width after a colon pads numbers with zeros
+    // and the mnemonic with spaces, and a trailing "x" gives lower case hex,
+    // e.g. {pc:3x}.
+    pub fn parse(text: &str) -> Result<TraceFormat, TraceFormatError> {
+        let mut parts = Vec::new();
+        let mut rest = text;
+
+        while let Some(start) = rest.find('{') {
+            let end = rest[start..]
+                .find('}')
+                .ok_or(TraceFormatError::Unterminated)?
+                + start;
+            let spec = &rest[start + 1..end];
+            let invalid = || TraceFormatError::InvalidField(spec.to_string());
+
+            let (name, options) = spec.split_once(':').unwrap_or((spec, ""));
+            let (width, lowercase) = match options.strip_suffix('x') {
+                Some(width) => (width, true),
+                None => (options, false),
+            };
+            let width = match width {
+                "" => None,
+                width => Some(width.parse::<usize>().map_err(|_| invalid())?),
+            };
+
+            let field = match name {
+                "cycle" => Field::Cycle,
+                "pc" => Field::ProgramCounter,
+                "opcode" => Field::Opcode,
+                "mnemonic" => Field::Mnemonic,
+                "i" => Field::Index,
+                "sp" => Field::StackPointer,
+                "dt" => Field::DelayTimer,
+                "st" => Field::SoundTimer,
+                _ => match name
+                    .strip_prefix('v')
+                    .map(|digit| u8::from_str_radix(digit, 16))
+                {
+                    Some(Ok(register)) if register < 16 && name.len() == 2 => {
+                        Field::Register(register)
+                    }
+                    _ => return Err(invalid()),
+                },
+            };
+
+            parts.push(Part::Text(rest[..start].to_string()));
+            parts.push(Part::Field {
+                field,
+                width,
+                lowercase,
+            });
+            rest = &rest[end + 1..];
+        }
+
+        parts.push(Part::Text(rest.to_string()));
+        parts.retain(|part| *part != Part::Text(String::new()));
+
+        Ok(TraceFormat { parts })
+    }
+
+    fn format(&self, cpu: &Cpu, opcode: Opcode) -> String {
+        let mut line = String::new();
+
+        for part in &self.parts {
+            let (field, width, lowercase) = match part {
+                Part::Text(text) => {
+                    line.push_str(text);
+                    continue;
+                }
+                Part::Field {
+                    field,
+                    width,
+                    lowercase,
+                } => (*field, *width, *lowercase),
+            };
+
+            let (value, digits) = match field {
+                Field::Cycle => {
+                    line.push_str(&format!(
+                        "{:0width$}",
+                        cpu.cycles(),
+                        width = width.unwrap_or(0)
+                    ));
+                    continue;
+                }
+                Field::Mnemonic => {
+                    line.push_str(&format!(
+                        "{:<width$}",
+                        opcode.to_string(),
+                        width = width.unwrap_or(0)
+                    ));
+                    continue;
+                }
+                Field::ProgramCounter => (cpu.program_counter(), 4),
+                Field::Opcode => (cpu.opcode(), 4),
+                Field::Index => (cpu.index(), 4),
+                Field::Register(register) => {
+                    (cpu.registers().read_all()[register as usize] as u16, 2)
+                }
+                Field::StackPointer => (cpu.stack().get_pointer(), 2),
+                Field::DelayTimer => (cpu.delay_timer() as u16, 2),
+                Field::SoundTimer => (cpu.sound_timer() as u16, 2),
+            };
+
+            let width = width.unwrap_or(digits);
+            if lowercase {
+                line.push_str(&format!("{:0width$x}", value, width = width));
+            } else {
+                line.push_str(&format!("{:0width$X}", value, width = width));
+            }
+        }
+
+        line
+    }
+}
+
+pub struct Tracer {
+    writer: Box<dyn Write>,
+    format: TraceFormat,
+    addresses: Option<RangeInclusive<u16>>,
+    cycles: Option<RangeInclusive<u64>>,
+}
+
+impl Tracer {
+    pub fn new(writer: Box<dyn Write>) -> Tracer {
+        Tracer {
+            writer,
+            format: TraceFormat::default(),
+            addresses: None,
+            cycles: None,
+        }
+    }
+
+    pub fn to_stdout() -> Tracer {
+        Tracer::new(Box::new(BufWriter::new(std::io::stdout())))
+    }
+
+    pub fn to_file(filename: &str) -> std::io::Result<Tracer> {
+        let file = std::fs::File::create(filename)?;
+
+        Ok(Tracer::new(Box::new(BufWriter::new(file))))
+    }
+
+    pub fn with_format(mut self, format: TraceFormat) -> Tracer {
+        self.format = format;
+        self
+    }
+
+    pub fn with_address_range(mut self, addresses: RangeInclusive<u16>) -> Tracer {
+        self.addresses = Some(addresses);
+        self
+    }
+
+    pub fn with_cycle_range(mut self, cycles: RangeInclusive<u64>) -> Tracer {
+        self.cycles = Some(cycles);
+        self
+    }
+
+    // Logs the state before the instruction executes, one line per cycle.
+    pub fn trace(&mut self, cpu: &Cpu, opcode: Opcode) -> std::io::Result<()> {
+        if let Some(addresses) = &self.addresses {
+            if !addresses.contains(&cpu.program_counter()) {
+                return Ok(());
+            }
+        }
+
+        if let Some(cycles) = &self.cycles {
+            if !cycles.contains(&cpu.cycles()) {
+                return Ok(());
+            }
+        }
+
+        writeln!(self.writer, "{}", self.format.format(cpu, opcode))
+    }
+
+    pub fn flush(&mut self) -> std::io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    // LD V0, 2A; ADD V0, 01; JP 204.
+    const PROGRAM: [u8; 6] = [0x60, 0x2A, 0x70, 0x01, 0x12, 0x04];
+
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, bytes: &[u8]) -> std::io::Result<usize> {
+            self.0.borrow_mut().write(bytes)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn trace(configure: impl Fn(Tracer) -> Tracer, cycles: usize) -> Vec<String> {
+        let buffer = SharedBuffer::default();
+        let mut cpu = Cpu::new();
+        cpu.load_rom_bytes_at(&PROGRAM, 0x200).unwrap();
+        cpu.enable_tracer(configure(Tracer::new(Box::new(buffer.clone()))));
+
+        for _ in 0..cycles {
+            cpu.emulate_cycle().unwrap();
+        }
+
+        let text = String::from_utf8(buffer.0.borrow().clone()).unwrap();
+        text.lines().map(str::to_string).collect()
+    }
+
+    #[test]
+    fn the_default_layout_shows_the_whole_machine() {
+        let lines = trace(|tracer| tracer, 2);
+
+        assert_eq!(lines.len(), 2);
+        assert!(lines[1].starts_with("00000002 PC:0202 OP:7001 "));
+        assert!(lines[1].contains(" V0:2A V1:00 "));
+        assert!(lines[1].ends_with(" VF:00 I:0000 SP:00 DT:00 ST:00"));
+        assert_eq!(lines[0].find(" V0:"), lines[1].find(" V0:"));
+    }
+
+    #[test]
+    fn custom_layouts_pick_fields_and_widths() {
+        let format =
+            TraceFormat::parse("{cycle:3}: {pc:3x} {opcode:x} v0={v0} {mnemonic}").unwrap();
+        let lines = trace(|tracer| tracer.with_format(format.clone()), 2);
+
+        assert_eq!(
+            lines[0],
+            format!("001: 200 602a v0=00 {}", Opcode::decode(0x602A).unwrap())
+        );
+        assert!(lines[1].starts_with("002: 202 7001 v0=2A "));
+    }
+
+    #[test]
+    fn bad_layouts_are_refused() {
+        for (text, error) in [
+            ("{pc", TraceFormatError::Unterminated),
+            ("{vg}", TraceFormatError::InvalidField("vg".to_string())),
+            ("{v10}", TraceFormatError::InvalidField("v10".to_string())),
+            (
+                "{pc:wide}",
+                TraceFormatError::InvalidField("pc:wide".to_string()),
+            ),
+        ] {
+            assert_eq!(TraceFormat::parse(text), Err(error));
+        }
+    }
+
+    #[test]
+    fn only_instructions_in_both_ranges_are_traced() {
+        let format = TraceFormat::parse("{cycle} {pc}").unwrap();
+        let lines = trace(
+            |tracer| {
+                tracer
+                    .with_format(format.clone())
+                    .with_address_range(0x202..=0x204)
+                    .with_cycle_range(3..=4)
+            },
+            6,
+        );
+
+        assert_eq!(lines, ["3 0204", "4 0204"]);
+    }
+}
@@ -0,0 +1,242 @@
+use crate::cdp1802::{Bus, Cdp1802};
+use crate::keyboard::Keyboard;
+use crate::memory::{Memory, MemoryError, MEMORY_SIZE};
+use crate::screen::Screen;
+use crate::timing::VIP_CYCLES_PER_FRAME;
+
+// The interpreter occupies the memory below the program.
+pub const INTERPRETER_SIZE: usize = 0x200;
+pub const MONITOR_SIZE: usize = 0x200;
+
+// CDP1861 frame layout in 14 machine cycle lines.
+const CYCLES_PER_LINE: u64 = 14;
+const DISPLAY_START_LINE: u64 = 80;
+const DISPLAY_LINES: usize = 128;
+const BYTES_PER_LINE: usize = 8;
+// INT is raised 29 cycles ahead of the first DMA so the interrupt routine
+// can set R0 up, and held until the display starts.
+const INTERRUPT_START: u64 = DISPLAY_START_LINE * CYCLES_PER_LINE - 29;
+const INTERRUPT_END: u64 = DISPLAY_START_LINE * CYCLES_PER_LINE;
+// EF1 warns of the start and end of the display area, four lines ahead.
+const EF1_LINES: [(u64, u64); 2] = [(76, 80), (204, 208)];
+
+#[derive(Debug)]
+pub enum VipError {
+    InterpreterTooLarge(usize),
+    MonitorTooLarge(usize),
+    MemoryError(MemoryError),
+}
+
+impl From<MemoryError> for VipError {
+    fn from(error: MemoryError) -> Self {
+        VipError::MemoryError(error)
+    }
+}
+
+// A COSMAC VIP running the original CHIP-8 interpreter on its CDP1802, with
+// the CDP1861 drawing the display from RAM over DMA. The interpreter and the
+// optional monitor ROM are supplied by the user.
+#[derive(Clone)]
+pub struct Vip {
+    cpu: Cdp1802,
+    interpreter: Vec<u8>,
+    monitor: Option<Vec<u8>>,
+    // After reset the monitor ROM also answers at 0000 until the first
+    // access with A15 set.
+    boot_mapping: bool,
+    display_enabled: bool,
+    keypad_latch: u8,
+    cycle: u64,
+    interrupted: bool,
+    raster: [[u8; BYTES_PER_LINE]; DISPLAY_LINES],
+}
+
+// The VIP's view of the machine while the processor runs: 4K of RAM mirrored
+// below 8000 and the monitor ROM mirrored above it.
+struct VipBus<'a> {
+    memory: &'a mut Memory,
+    keyboard: &'a Keyboard,
+    monitor: Option<&'a [u8]>,
+    boot_mapping: &'a mut bool,
+    display_enabled: &'a mut bool,
+    keypad_latch: &'a mut u8,
+    ef1: bool,
+}
+
+impl Bus for VipBus<'_> {
+    fn read(&mut self, address: u16) -> u8 {
+        if address & 0x8000 != 0 {
+            *self.boot_mapping = false;
+        }
+
+        match self.monitor {
+            Some(monitor) if address & 0x8000 != 0 || *self.boot_mapping => {
+                monitor[address as usize % monitor.len()]
+            }
+            _ if address & 0x8000 != 0 => 0xFF,
+            _ => self
+                .memory
+                .get_byte(address % MEMORY_SIZE as u16)
+                .unwrap_or(0),
+        }
+    }
+
+    fn write(&mut self, address: u16, value: u8) {
+        if address & 0x8000 != 0 {
+            *self.boot_mapping = false;
+        } else {
+            let _ = self.memory.set_byte(address % MEMORY_SIZE as u16, value);
+        }
+    }
+
+    // INP 1 turns the display on.
+    fn input(&mut self, port: u8) -> u8 {
+        if port == 1 {
+            *self.display_enabled = true;
+        }
+
+        0xFF
+    }
+
+    // OUT 1 turns the display off and OUT 2 selects the key EF3 reports.
+    fn output(&mut self, port: u8, value: u8) {
+        match port {
+            1 => *self.display_enabled = false,
+            2 => *self.keypad_latch = value & 0xF,
+            _ => {}
+        }
+    }
+
+    fn flag(&mut self, number: u8) -> bool {
+        match number {
+            1 => self.ef1,
+            3 => self
+                .keyboard
+                .is_key_pressed(*self.keypad_latch)
+                .unwrap_or(false),
+            _ => false,
+        }
+    }
+}
+
+impl Vip {
+    pub fn new(interpreter: &[u8], monitor: Option<&[u8]>) -> Result<Vip, VipError> {
+        if interpreter.len() > INTERPRETER_SIZE {
+            return Err(VipError::InterpreterTooLarge(interpreter.len()));
+        }
+
+        if let Some(monitor) = monitor.filter(|monitor| monitor.len() > MONITOR_SIZE) {
+            return Err(VipError::MonitorTooLarge(monitor.len()));
+        }
+
+        Ok(Vip {
+            cpu: Cdp1802::new(),
+            interpreter: interpreter.to_vec(),
+            monitor: monitor
+                .filter(|monitor| !monitor.is_empty())
+                .map(<[u8]>::to_vec),
+            boot_mapping: true,
+            display_enabled: false,
+            keypad_latch: 0,
+            cycle: 0,
+            interrupted: false,
+            raster: [[0; BYTES_PER_LINE]; DISPLAY_LINES],
+        })
+    }
+
+    // Puts the interpreter in place of the fontset, which it brings its own
+    // copy of.
+    pub fn load(&self, memory: &mut Memory) -> Result<(), MemoryError> {
+        memory.set_bytes(0, &self.interpreter)
+    }
+
+    pub fn reset(&mut self) {
+        self.cpu.reset();
+        self.boot_mapping = true;
+        self.display_enabled = false;
+        self.cycle = 0;
+        self.interrupted = false;
+        self.raster = [[0; BYTES_PER_LINE]; DISPLAY_LINES];
+    }
+
+    pub fn processor(&self) -> &Cdp1802 {
+        &self.cpu
+    }
+
+    // The VIP's tone generator sounds while Q is set.
+    pub fn sound(&self) -> bool {
+        self.cpu.q()
+    }
+
+    // Runs one 60 Hz frame of 262 lines.
+    pub fn run_frame(&mut self, memory: &mut Memory, keyboard: &Keyboard, screen: &mut Screen) {
+        let mut dma_line = None;
+
+        while self.cycle < VIP_CYCLES_PER_FRAME {
+            let line = self.cycle / CYCLES_PER_LINE;
+            let mut bus = VipBus {
+                memory,
+                keyboard,
+                monitor: self.monitor.as_deref(),
+                boot_mapping: &mut self.boot_mapping,
+                display_enabled: &mut self.display_enabled,
+                keypad_latch: &mut self.keypad_latch,
+                ef1: EF1_LINES
+                    .iter()
+                    .any(|&(start, end)| (start..end).contains(&line)),
+            };
+
+            // DMA requests are served between instructions, taking eight
+            // cycles at the start of every display line.
+            let display_line = line.wrapping_sub(DISPLAY_START_LINE) as usize;
+
+            if *bus.display_enabled && display_line < DISPLAY_LINES && dma_line != Some(line) {
+                for byte in self.raster[display_line].iter_mut() {
+                    *byte = self.cpu.dma_out(&mut bus);
+                }
+
+                dma_line = Some(line);
+                self.cycle += BYTES_PER_LINE as u64;
+                continue;
+            }
+
+            if *bus.display_enabled
+                && !self.interrupted
+                && (INTERRUPT_START..INTERRUPT_END).contains(&self.cycle)
+            {
+                let cycles = self.cpu.interrupt();
+
+                if cycles > 0 {
+                    self.interrupted = true;
+                    self.cycle += cycles;
+                    continue;
+                }
+            }
+
+            self.cycle += self.cpu.step(&mut bus);
+        }
+
+        self.cycle -= VIP_CYCLES_PER_FRAME;
+        self.interrupted = false;
+
+        if !self.display_enabled {
+            self.raster = [[0; BYTES_PER_LINE]; DISPLAY_LINES];
+        }
+
+        self.update_screen(screen);
+    }
+
+    // The interpreter shows each of its rows on several consecutive lines,
+    // four for 32 rows, so the first of each group stands for the row.
+    fn update_screen(&self, screen: &mut Screen) {
+        let lines_per_row = DISPLAY_LINES / screen.get_height();
+
+        for y in 0..screen.get_height() {
+            let line = &self.raster[y * lines_per_row];
+
+            for x in 0..screen.get_width() {
+                screen.set_pixel(x, y, line[x / 8] & (0x80 >> (x % 8)) != 0);
+            }
+        }
+    }
+}
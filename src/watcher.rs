@@ -0,0 +1,135 @@
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+// Polls a file's modification time. Build tools often write the output in
+// several steps, so a change is only reported once the time has stopped
+// moving for one poll interval.
+pub struct FileWatcher {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+    pending: Option<SystemTime>,
+    last_poll: Instant,
+}
+
+impl FileWatcher {
+    pub fn new(path: &Path) -> FileWatcher {
+        let mut watcher = FileWatcher {
+            path: path.to_path_buf(),
+            modified: None,
+            pending: None,
+            last_poll: Instant::now(),
+        };
+
+        watcher.modified = watcher.modification_time();
+
+        watcher
+    }
+
+    fn modification_time(&self) -> Option<SystemTime> {
+        std::fs::metadata(&self.path)
+            .and_then(|metadata| metadata.modified())
+            .ok()
+    }
+
+    pub fn has_changed(&mut self) -> bool {
+        if self.last_poll.elapsed() < POLL_INTERVAL {
+            return false;
+        }
+
+        self.last_poll = Instant::now();
+
+        let modified = self.modification_time();
+        self.settle(modified)
+    }
+
+    // One poll's worth of debouncing: `modified` must be seen twice in a row
+    // before it counts as a change.
+    fn settle(&mut self, modified: Option<SystemTime>) -> bool {
+        if modified.is_none() || modified == self.modified {
+            self.pending = None;
+            return false;
+        }
+
+        if self.pending != modified {
+            self.pending = modified;
+            return false;
+        }
+
+        self.modified = modified;
+        self.pending = None;
+
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn time(seconds: u64) -> Option<SystemTime> {
+        Some(SystemTime::UNIX_EPOCH + Duration::from_secs(seconds))
+    }
+
+    #[test]
+    fn new_records_the_current_modification_time() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("game.ch8");
+        std::fs::write(&path, [0x12, 0x00]).unwrap();
+
+        let present = FileWatcher::new(&path);
+        let missing = FileWatcher::new(&dir.path().join("missing.ch8"));
+
+        assert!(present.modified.is_some());
+        assert_eq!(present.modified, present.modification_time());
+        assert_eq!(missing.modified, None);
+    }
+
+    #[test]
+    fn polls_are_spaced_out() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("game.ch8");
+        let mut watcher = FileWatcher::new(&path);
+        std::fs::write(&path, [0x12, 0x00]).unwrap();
+
+        // Too soon after starting to look at the file at all.
+        assert!(!watcher.has_changed());
+        assert_eq!(watcher.pending, None);
+    }
+
+    #[test]
+    fn changes_are_reported_once_the_time_settles() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut watcher = FileWatcher::new(&dir.path().join("game.ch8"));
+        watcher.modified = time(1);
+
+        assert!(!watcher.settle(time(1)));
+        // Still being written.
+        assert!(!watcher.settle(time(2)));
+        assert!(!watcher.settle(time(3)));
+        assert!(watcher.settle(time(3)));
+        assert_eq!(watcher.modified, time(3));
+        // Reported only once.
+        assert!(!watcher.settle(time(3)));
+    }
+
+    #[test]
+    fn missing_files_and_reverted_times_are_not_changes() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut watcher = FileWatcher::new(&dir.path().join("game.ch8"));
+        watcher.modified = time(1);
+
+        // Deleted while a new version was being written.
+        assert!(!watcher.settle(time(2)));
+        assert!(!watcher.settle(None));
+        assert!(!watcher.settle(time(2)));
+        assert!(watcher.settle(time(2)));
+
+        // Back to what was already loaded before settling.
+        assert!(!watcher.settle(time(4)));
+        assert!(!watcher.settle(time(2)));
+        assert!(!watcher.settle(time(2)));
+        assert_eq!(watcher.modified, time(2));
+    }
+}